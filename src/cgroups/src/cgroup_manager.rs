@@ -1,6 +1,13 @@
+use crate::subsystems::freezer_subsystem::FreezerSubsystem;
+use crate::subsystems::memory_subsystem::MemorySubsystem;
 use crate::subsystems::subsystem::*;
 use anyhow::Result;
 
+/// Manages the cgroups of a container.
+///
+/// The freezer cgroup only backs `pause`, `unpause` and listing the
+/// processes of the container. Hosts without a freezer hierarchy still run
+/// containers, failing to set up the freezer cgroup is ignored.
 #[derive(Default)]
 pub struct CgroupManager {
     cgroup_path: Option<String>,
@@ -29,6 +36,8 @@ impl CgroupManager {
                 }
             }
         }
+        let _ = FreezerSubsystem::new()
+            .set(self.cgroup_path.as_ref().unwrap(), res);
         Ok(())
     }
 
@@ -48,11 +57,44 @@ impl CgroupManager {
                 }
             }
         }
+        let _ = FreezerSubsystem::new()
+            .apply(self.cgroup_path.as_ref().unwrap(), pid);
         Ok(())
     }
 
+    /// number of processes of the cgroup the OOM killer killed so far
+    pub fn oom_kill_count(&self) -> Result<u64> {
+        MemorySubsystem::new()
+            .oom_kill_count(self.cgroup_path.as_ref().unwrap())
+    }
+
+    /// whether the OOM killer killed a process of the cgroup since
+    /// [`CgroupManager::oom_kill_count`] returned `since`, must be checked
+    /// before the cgroup is destroyed
+    pub fn oom_killed(&self, since: u64) -> Result<bool> {
+        Ok(oom_killed_since(since, self.oom_kill_count()?))
+    }
+
+    /// freeze the processes of the cgroup, returns once all of them are
+    /// frozen
+    pub fn freeze(&self) -> Result<()> {
+        FreezerSubsystem::new().freeze(self.cgroup_path.as_ref().unwrap())
+    }
+
+    /// thaw the processes of a frozen cgroup
+    pub fn thaw(&self) -> Result<()> {
+        FreezerSubsystem::new().thaw(self.cgroup_path.as_ref().unwrap())
+    }
+
+    /// processes of the cgroup, none once it is destroyed
+    pub fn pids(&self) -> Result<Vec<i32>> {
+        FreezerSubsystem::new().pids(self.cgroup_path.as_ref().unwrap())
+    }
+
     /// destory the cgroup
     pub fn destroy(&self) -> Result<()> {
+        let _ =
+            FreezerSubsystem::new().remove(self.cgroup_path.as_ref().unwrap());
         for subsystem in get_subsystems_initialized() {
             match subsystem.remove(self.cgroup_path.as_ref().unwrap()) {
                 Ok(_) => {
@@ -70,3 +112,25 @@ impl CgroupManager {
         Ok(())
     }
 }
+
+/// Whether the OOM kill counter went up from `before` to `after`.
+///
+/// The counter of a cgroup keeps counting across the runs of a restarted
+/// container, so only kills during the current run count.
+fn oom_killed_since(before: u64, after: u64) -> bool {
+    after > before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oom_killed_since() {
+        assert!(!oom_killed_since(0, 0));
+        assert!(oom_killed_since(0, 1));
+        // An earlier run was OOM killed, this one wasn't
+        assert!(!oom_killed_since(2, 2));
+        assert!(oom_killed_since(2, 3));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::subsystems::{subsystem::*, util::get_cgroup_path};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions, remove_dir};
+use std::io::prelude::*;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How long freezing or thawing may take before it is given up.
+const FREEZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the freezer state is checked while it changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct FreezerSubsystem {}
+
+/// Detect if system is using cgroup v2
+fn is_cgroup_v2() -> bool {
+    if let Ok(mut mount_info_file) = File::open("/proc/self/mountinfo") {
+        let mut buf: String = String::new();
+        if mount_info_file.read_to_string(&mut buf).is_ok() {
+            return buf.contains("cgroup2");
+        }
+    }
+    false
+}
+
+/// State of the processes of a cgroup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreezerState {
+    Thawed,
+    /// Some processes are not frozen yet
+    Freezing,
+    Frozen,
+}
+
+impl Subsystem for FreezerSubsystem {
+    fn name(&self) -> &str {
+        "freezer"
+    }
+
+    /// The freezer has no resource limits, only create the cgroup
+    fn set(&self, cgroup_path: &str, _res: &ResourceConfig) -> Result<()> {
+        get_cgroup_path(self.name(), cgroup_path, true).map(|_| ())
+    }
+
+    /// Add the process into the cgroup
+    fn apply(&self, cgroup_path: &str, pid: i32) -> Result<()> {
+        let path = get_cgroup_path(self.name(), cgroup_path, false)?;
+        // cgroup v2 uses cgroup.procs, v1 uses tasks
+        let tasks_file = if is_cgroup_v2() {
+            "cgroup.procs"
+        } else {
+            "tasks"
+        };
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(Path::new(&path).join(tasks_file))
+            .with_context(|| format!("Failed to open {}", tasks_file))?;
+        file.write_all(pid.to_string().as_bytes())
+            .map_err(|e| anyhow::anyhow!("apply cgroup freezer failed {}", e))
+    }
+
+    /// Remove specific cgroup
+    fn remove(&self, cgroup_path: &str) -> Result<()> {
+        match get_cgroup_path(self.name(), cgroup_path, false) {
+            Ok(path) => remove_dir(path)
+                .map_err(|e| anyhow::anyhow!("remove cgroup failed {}", e)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Default for FreezerSubsystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FreezerSubsystem {
+    pub fn new() -> Self {
+        FreezerSubsystem {}
+    }
+
+    /// Current freezer state of the cgroup
+    pub fn state(&self, cgroup_path: &str) -> Result<FreezerState> {
+        let path = get_cgroup_path(self.name(), cgroup_path, false)?;
+        // cgroup v2 reports in cgroup.events, v1 in freezer.state
+        let state_file = if is_cgroup_v2() {
+            "cgroup.events"
+        } else {
+            "freezer.state"
+        };
+        let state = std::fs::read_to_string(Path::new(&path).join(state_file))
+            .with_context(|| format!("Failed to read {}", state_file))?;
+        parse_state(&state)
+            .ok_or_else(|| anyhow::anyhow!("Unknown freezer state {}", state))
+    }
+
+    /// Processes of the cgroup, none if it doesn't exist (anymore)
+    pub fn pids(&self, cgroup_path: &str) -> Result<Vec<i32>> {
+        let path = get_cgroup_path(self.name(), cgroup_path, false)?;
+        let procs = match std::fs::read_to_string(
+            Path::new(&path).join("cgroup.procs"),
+        ) {
+            Ok(procs) => procs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Vec::new());
+            }
+            Err(e) => {
+                return Err(e).context("Failed to read cgroup.procs");
+            }
+        };
+        procs
+            .lines()
+            .map(|pid| {
+                pid.trim().parse().context("Invalid PID in cgroup.procs")
+            })
+            .collect()
+    }
+
+    /// Freeze the processes of the cgroup, returns once all of them are
+    /// frozen
+    pub fn freeze(&self, cgroup_path: &str) -> Result<()> {
+        self.change_state(cgroup_path, FreezerState::Frozen)
+    }
+
+    /// Thaw the processes of the cgroup
+    pub fn thaw(&self, cgroup_path: &str) -> Result<()> {
+        self.change_state(cgroup_path, FreezerState::Thawed)
+    }
+
+    fn change_state(
+        &self,
+        cgroup_path: &str,
+        target: FreezerState,
+    ) -> Result<()> {
+        let path = get_cgroup_path(self.name(), cgroup_path, false)?;
+        let (state_file, value) = match (is_cgroup_v2(), target) {
+            (true, FreezerState::Frozen) => ("cgroup.freeze", "1"),
+            (true, _) => ("cgroup.freeze", "0"),
+            (false, FreezerState::Frozen) => ("freezer.state", "FROZEN"),
+            (false, _) => ("freezer.state", "THAWED"),
+        };
+        let state_path = Path::new(&path).join(state_file);
+
+        let deadline = Instant::now() + FREEZE_TIMEOUT;
+        loop {
+            // A v1 cgroup may stay in FREEZING when a process is busy, e.g.
+            // in vfork, writing FROZEN again retries freezing it
+            std::fs::write(&state_path, value)
+                .with_context(|| format!("Failed to write {}", state_file))?;
+            if self.state(cgroup_path)? == target {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow::anyhow!(
+                    "Timed out waiting for cgroup {} to become {:?}",
+                    cgroup_path,
+                    target
+                ));
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Parse `freezer.state` of v1 or the `frozen` key of `cgroup.events` of v2.
+fn parse_state(state: &str) -> Option<FreezerState> {
+    if let Some(frozen) =
+        state.lines().find_map(|line| line.strip_prefix("frozen "))
+    {
+        return match frozen.trim() {
+            "1" => Some(FreezerState::Frozen),
+            "0" => Some(FreezerState::Thawed),
+            _ => None,
+        };
+    }
+    match state.trim() {
+        "THAWED" => Some(FreezerState::Thawed),
+        "FREEZING" => Some(FreezerState::Freezing),
+        "FROZEN" => Some(FreezerState::Frozen),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_parse_state() {
+        assert_eq!(parse_state("FROZEN\n"), Some(FreezerState::Frozen));
+        assert_eq!(parse_state("FREEZING\n"), Some(FreezerState::Freezing));
+        assert_eq!(parse_state("THAWED\n"), Some(FreezerState::Thawed));
+        let v2 = "populated 1\nfrozen 1\n";
+        assert_eq!(parse_state(v2), Some(FreezerState::Frozen));
+        let v2 = "populated 0\nfrozen 0\n";
+        assert_eq!(parse_state(v2), Some(FreezerState::Thawed));
+        assert_eq!(parse_state("melted"), None);
+    }
+
+    #[test]
+    fn test_freezer_subsystem() {
+        let freezer_subsystem = FreezerSubsystem::new();
+        let cgroup_path = "testfreezer";
+        freezer_subsystem
+            .set(cgroup_path, &ResourceConfig::default())
+            .unwrap();
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        freezer_subsystem
+            .apply(cgroup_path, child.id() as i32)
+            .unwrap();
+        assert_eq!(
+            freezer_subsystem.pids(cgroup_path).unwrap(),
+            vec![child.id() as i32]
+        );
+        assert_eq!(
+            freezer_subsystem.state(cgroup_path).unwrap(),
+            FreezerState::Thawed
+        );
+
+        freezer_subsystem.freeze(cgroup_path).unwrap();
+        assert_eq!(
+            freezer_subsystem.state(cgroup_path).unwrap(),
+            FreezerState::Frozen
+        );
+
+        freezer_subsystem.thaw(cgroup_path).unwrap();
+        assert_eq!(
+            freezer_subsystem.state(cgroup_path).unwrap(),
+            FreezerState::Thawed
+        );
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        freezer_subsystem.remove(cgroup_path).unwrap();
+        assert!(freezer_subsystem.pids(cgroup_path).unwrap().is_empty());
+        let path =
+            get_cgroup_path(freezer_subsystem.name(), cgroup_path, false)
+                .unwrap();
+        assert!(
+            !Path::new(&path).exists(),
+            "freezer subsystem cgroup path should not exist"
+        );
+    }
+}
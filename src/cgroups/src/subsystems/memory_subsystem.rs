@@ -105,6 +105,31 @@ impl MemorySubsystem {
     pub fn new() -> Self {
         MemorySubsystem {}
     }
+
+    /// Number of processes of the cgroup killed by the OOM killer
+    pub fn oom_kill_count(&self, cgroup_path: &str) -> Result<u64> {
+        let path = get_cgroup_path(self.name(), cgroup_path, false)?;
+        // cgroup v2 counts in memory.events, v1 in memory.oom_control
+        let events_file = if is_cgroup_v2() {
+            "memory.events"
+        } else {
+            "memory.oom_control"
+        };
+        let events_path = Path::new(&path).join(events_file);
+        let events = std::fs::read_to_string(&events_path)
+            .with_context(|| format!("Failed to read {}", events_file))?;
+        Ok(parse_oom_kill(&events))
+    }
+}
+
+/// Read the `oom_kill` counter of `memory.events` or `memory.oom_control`.
+/// Kernels older than 4.13 don't have it.
+fn parse_oom_kill(events: &str) -> u64 {
+    events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0)
 }
 
 #[cfg(test)]
@@ -125,6 +150,15 @@ mod tests {
         false
     }
 
+    #[test]
+    fn test_parse_oom_kill() {
+        let v1 = "oom_kill_disable 0\nunder_oom 0\noom_kill 2\n";
+        assert_eq!(parse_oom_kill(v1), 2);
+        let v2 = "low 0\nhigh 0\nmax 5\noom 1\noom_kill 1\n";
+        assert_eq!(parse_oom_kill(v2), 1);
+        assert_eq!(parse_oom_kill("oom_kill_disable 0\nunder_oom 0\n"), 0);
+    }
+
     #[test]
     fn test_memory_subsystem() {
         let memory_subsystem = MemorySubsystem::new();
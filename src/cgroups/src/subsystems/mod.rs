@@ -1,5 +1,6 @@
 pub mod cpu_set_subsystem;
 pub mod cpu_subsystem;
+pub mod freezer_subsystem;
 pub mod memory_subsystem;
 pub mod subsystem;
 mod util;
@@ -33,7 +33,9 @@ pub trait Subsystem: Send + Sync {
 
 /// Get the initialized subsystems.
 ///
-/// Uses OnceLock to ensure thread-safe one-time initialization.
+/// Uses OnceLock to ensure thread-safe one-time initialization. The freezer
+/// is not part of them, it is optional (see
+/// [`crate::cgroup_manager::CgroupManager`]).
 pub fn get_subsystems_initialized() -> &'static Vec<Box<dyn Subsystem>> {
     static SUBSYSTEMS: OnceLock<Vec<Box<dyn Subsystem>>> = OnceLock::new();
 
@@ -0,0 +1,413 @@
+//! Linux capabilities of the container command.
+//!
+//! Root inside a container would otherwise keep every capability of the
+//! user who started it, including `CAP_SYS_ADMIN`, which is enough to
+//! remount filesystems or escape the container. The command instead runs
+//! with the same small allow-list as Docker ([`DEFAULT_CAPABILITIES`]),
+//! adjusted with `rocker run --cap-add` / `--cap-drop`.
+//!
+//! The bounding, effective, permitted, inheritable and ambient sets are all
+//! reduced to the allow-list, so the command can't regain a dropped
+//! capability through `execve` of a setuid or file-capability binary.
+
+use anyhow::Result;
+use std::io;
+
+/// Capability names, indexed by capability number.
+const CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Capabilities kept by default, the same as Docker's.
+pub const DEFAULT_CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_RAW",
+    "CAP_SYS_CHROOT",
+    "CAP_MKNOD",
+    "CAP_AUDIT_WRITE",
+    "CAP_SETFCAP",
+];
+
+/// Keyword of `--cap-add` / `--cap-drop` standing for every capability.
+const ALL: &str = "ALL";
+
+/// Version 3 of the capget/capset interface, with 64-bit sets.
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Normalize a capability name.
+///
+/// Names are case insensitive and the `CAP_` prefix is optional, so
+/// `net_admin` becomes `CAP_NET_ADMIN`. `ALL` is kept as is.
+///
+/// # Errors
+///
+/// Returns an error if the capability is unknown
+pub fn normalize(name: &str) -> Result<String> {
+    let upper = name.to_ascii_uppercase();
+    if upper == ALL {
+        return Ok(upper);
+    }
+    let name = if upper.starts_with("CAP_") {
+        upper
+    } else {
+        format!("CAP_{}", upper)
+    };
+    if number(&name).is_none() {
+        return Err(anyhow::anyhow!("Unknown capability {}", name));
+    }
+    Ok(name)
+}
+
+/// Resolve the capabilities of a container from `--cap-add` and
+/// `--cap-drop`.
+///
+/// Like Docker, the defaults are extended with `add` and reduced by `drop`,
+/// with `add` winning over `drop`. `--cap-add ALL` starts from all
+/// capabilities and `--cap-drop ALL` from none. The result is sorted by
+/// capability number.
+///
+/// # Example
+///
+/// ```rust
+/// use container::capabilities;
+///
+/// let caps = capabilities::resolve(
+///     &["sys_admin".to_string()],
+///     &["ALL".to_string()],
+/// )
+/// .unwrap();
+/// assert_eq!(caps, vec!["CAP_SYS_ADMIN".to_string()]);
+/// ```
+pub fn resolve(add: &[String], drop: &[String]) -> Result<Vec<String>> {
+    let add = add
+        .iter()
+        .map(|name| normalize(name))
+        .collect::<Result<Vec<_>>>()?;
+    let drop = drop
+        .iter()
+        .map(|name| normalize(name))
+        .collect::<Result<Vec<_>>>()?;
+    let contains = |list: &[String], name: &str| list.iter().any(|c| c == name);
+
+    let keep = |name: &str| {
+        if contains(&add, ALL) {
+            !contains(&drop, name) || contains(&add, name)
+        } else if contains(&drop, ALL) {
+            contains(&add, name)
+        } else {
+            (DEFAULT_CAPABILITIES.contains(&name) && !contains(&drop, name))
+                || contains(&add, name)
+        }
+    };
+
+    Ok(CAPABILITIES
+        .iter()
+        .filter(|name| keep(name))
+        .map(|name| name.to_string())
+        .collect())
+}
+
+fn number(name: &str) -> Option<u32> {
+    CAPABILITIES
+        .iter()
+        .position(|cap| *cap == name)
+        .map(|n| n as u32)
+}
+
+/// Capability sets of the container command.
+///
+/// # Example
+///
+/// ```rust
+/// use container::capabilities::{self, CapabilitySet};
+///
+/// let caps = capabilities::resolve(&[], &[]).unwrap();
+/// let set = CapabilitySet::from_names(&caps);
+/// assert!(set.is_ok());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct CapabilitySet {
+    /// Bit mask of the kept capabilities
+    mask: u64,
+
+    /// Highest capability number known to the kernel
+    last_cap: u32,
+}
+
+impl CapabilitySet {
+    /// Build the set from capability names.
+    ///
+    /// Capabilities the running kernel doesn't know are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a capability is unknown
+    pub fn from_names(names: &[String]) -> Result<Self> {
+        let last_cap = Self::last_cap();
+        let mut mask = 0u64;
+        for name in names {
+            let name = normalize(name)?;
+            match number(&name) {
+                Some(n) if n <= last_cap => mask |= 1 << n,
+                Some(_) => debug!("{} is not supported by the kernel", name),
+                None => {
+                    return Err(anyhow::anyhow!("Invalid capability {}", name));
+                }
+            }
+        }
+        Ok(CapabilitySet { mask, last_cap })
+    }
+
+    /// Highest capability of the running kernel, from
+    /// `/proc/sys/kernel/cap_last_cap`.
+    fn last_cap() -> u32 {
+        std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+            .ok()
+            .and_then(|last| last.trim().parse().ok())
+            .unwrap_or(CAPABILITIES.len() as u32 - 1)
+            .min(63)
+    }
+
+    /// Reduce the capabilities of the calling thread to this set.
+    ///
+    /// Capabilities outside the set are dropped from the bounding set,
+    /// then the effective, permitted and inheritable sets are replaced by
+    /// the set, which is finally raised in the ambient set as well.
+    ///
+    /// Capabilities the calling thread doesn't have itself, e.g. when
+    /// rocker runs with a reduced bounding set, can't be gained and are
+    /// left out.
+    ///
+    /// This only performs `prctl`, `capget` and `capset` calls, so it can
+    /// be used between `fork` and `execve`.
+    pub fn apply(&self) -> io::Result<()> {
+        self.drop_bounding()?;
+        self.apply_process_sets()
+    }
+
+    /// Drop the capabilities outside the set from the bounding set.
+    ///
+    /// This is the first half of [`CapabilitySet::apply`]. It needs
+    /// `CAP_SETPCAP`, so when switching to another user it is done before
+    /// the switch, and [`CapabilitySet::apply_process_sets`] after it.
+    pub fn drop_bounding(&self) -> io::Result<()> {
+        let mask = self.mask & Self::permitted()?;
+        for cap in 0..=self.last_cap {
+            if mask & (1 << cap) == 0 {
+                prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the effective, permitted, inheritable and ambient sets by
+    /// the set.
+    ///
+    /// This is the second half of [`CapabilitySet::apply`]. After
+    /// switching from root to another user the permitted set is empty,
+    /// so the user command is left without capabilities, as in Docker.
+    pub fn apply_process_sets(&self) -> io::Result<()> {
+        let mask = self.mask & Self::permitted()?;
+
+        let mut data = [CapUserData::default(); 2];
+        for (i, word) in data.iter_mut().enumerate() {
+            let bits = (mask >> (32 * i)) as u32;
+            *word = CapUserData {
+                effective: bits,
+                permitted: bits,
+                inheritable: bits,
+            };
+        }
+        // SAFETY: header and data match the layout of the version 3
+        // interface, which reads two data structs
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_capset,
+                &Self::header() as *const CapUserHeader,
+                data.as_ptr(),
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+        )?;
+        for cap in 0..=self.last_cap {
+            if mask & (1 << cap) != 0 {
+                prctl_ambient_raise(cap)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn header() -> CapUserHeader {
+        CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        }
+    }
+
+    /// Permitted set of the calling thread.
+    fn permitted() -> io::Result<u64> {
+        let mut data = [CapUserData::default(); 2];
+        // SAFETY: header and data match the layout of the version 3
+        // interface, which writes two data structs
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_capget,
+                &Self::header() as *const CapUserHeader,
+                data.as_mut_ptr(),
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(data[0].permitted as u64 | (data[1].permitted as u64) << 32)
+    }
+}
+
+fn prctl(option: libc::c_int, arg: libc::c_ulong) -> io::Result<()> {
+    // SAFETY: the options used here only take integer arguments
+    let ret = unsafe { libc::prctl(option, arg, 0, 0, 0) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn prctl_ambient_raise(cap: u32) -> io::Result<()> {
+    // SAFETY: PR_CAP_AMBIENT only takes integer arguments
+    let ret = unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+            cap as libc::c_ulong,
+            0,
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_capability_numbers() {
+        assert_eq!(number("CAP_CHOWN"), Some(0));
+        assert_eq!(number("CAP_SYS_ADMIN"), Some(21));
+        assert_eq!(number("CAP_CHECKPOINT_RESTORE"), Some(40));
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("net_admin").unwrap(), "CAP_NET_ADMIN");
+        assert_eq!(normalize("CAP_KILL").unwrap(), "CAP_KILL");
+        assert_eq!(normalize("all").unwrap(), "ALL");
+        assert!(normalize("CAP_FLY").is_err());
+    }
+
+    #[test]
+    fn test_resolve_defaults() {
+        let caps = resolve(&[], &[]).unwrap();
+        assert_eq!(caps.len(), DEFAULT_CAPABILITIES.len());
+        let mask = caps
+            .iter()
+            .fold(0u64, |mask, cap| mask | 1 << number(cap).unwrap());
+        // CapBnd of a default Docker container
+        assert_eq!(mask, 0xa80425fb);
+    }
+
+    #[test]
+    fn test_resolve_add_drop() {
+        let caps =
+            resolve(&names(&["sys_admin"]), &names(&["NET_RAW", "MKNOD"]))
+                .unwrap();
+        assert!(caps.contains(&"CAP_SYS_ADMIN".to_string()));
+        assert!(!caps.contains(&"CAP_NET_RAW".to_string()));
+        assert!(!caps.contains(&"CAP_MKNOD".to_string()));
+        assert_eq!(caps[0], "CAP_CHOWN");
+
+        let caps = resolve(&names(&["ALL"]), &names(&["SYS_ADMIN"])).unwrap();
+        assert_eq!(caps.len(), CAPABILITIES.len() - 1);
+
+        let caps =
+            resolve(&names(&["KILL"]), &names(&["ALL", "KILL"])).unwrap();
+        assert_eq!(caps, names(&["CAP_KILL"]));
+
+        assert!(resolve(&names(&["FLY"]), &[]).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! The container `/dev`.
+//!
+//! `/dev` is a private tmpfs holding only the standard device nodes, a
+//! private devpts instance, `/dev/shm`, `/dev/mqueue` and the usual
+//! symlinks, so no host device is reachable from the container.
+//!
+//! Device nodes can only be created with `CAP_MKNOD` in the initial user
+//! namespace. Inside the user namespace of a rootless container the host
+//! nodes are bind-mounted instead, which is why `/dev` has to be set up
+//! while the host root is still reachable.
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::mount::{MsFlags, mount};
+use nix::sys::stat::{Mode, SFlag, makedev, mknod};
+use std::fs;
+use std::os::unix::fs::{PermissionsExt, symlink};
+use std::path::Path;
+
+/// Character devices created in the container, with their major and minor
+/// numbers.
+const DEVICES: &[(&str, u64, u64)] = &[
+    ("null", 1, 3),
+    ("zero", 1, 5),
+    ("full", 1, 7),
+    ("random", 1, 8),
+    ("urandom", 1, 9),
+    ("tty", 5, 0),
+];
+
+/// Symlinks created in `/dev`, as `(link, target)`.
+const SYMLINKS: &[(&str, &str)] = &[
+    ("fd", "/proc/self/fd"),
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+    ("ptmx", "pts/ptmx"),
+];
+
+/// Group owning the pseudo terminals, `tty` in most images.
+const TTY_GID: u32 = 5;
+
+/// Set up `/dev` in the new container root.
+///
+/// This must be called after `pivot_root` and `/proc` is mounted, but
+/// before the old root is detached.
+///
+/// # Arguments
+///
+/// * `old_root` - Where the host root is mounted inside the container
+pub fn setup(old_root: &Path) -> Result<()> {
+    let dev = Path::new("/dev");
+    fs::create_dir_all(dev).context("Failed to create /dev")?;
+    mount(
+        Some("tmpfs"),
+        dev,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_STRICTATIME,
+        Some("mode=755,size=65536k"),
+    )
+    .context("Failed to mount tmpfs to /dev")?;
+
+    for (name, major, minor) in DEVICES {
+        create_device(dev, old_root, name, *major, *minor)?;
+    }
+
+    mount_devpts(dev)?;
+
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    let shm = dev.join("shm");
+    fs::create_dir_all(&shm).context("Failed to create /dev/shm")?;
+    mount(
+        Some("shm"),
+        &shm,
+        Some("tmpfs"),
+        flags,
+        Some("mode=1777,size=65536k"),
+    )
+    .context("Failed to mount /dev/shm")?;
+
+    let mqueue = dev.join("mqueue");
+    fs::create_dir_all(&mqueue).context("Failed to create /dev/mqueue")?;
+    mount(Some("mqueue"), &mqueue, Some("mqueue"), flags, None::<&str>)
+        .context("Failed to mount /dev/mqueue")?;
+
+    for (link, target) in SYMLINKS {
+        symlink(target, dev.join(link))
+            .with_context(|| format!("Failed to create /dev/{}", link))?;
+    }
+    Ok(())
+}
+
+/// Create a character device, or bind-mount the host one if the kernel
+/// doesn't let us create it.
+fn create_device(
+    dev: &Path,
+    old_root: &Path,
+    name: &str,
+    major: u64,
+    minor: u64,
+) -> Result<()> {
+    let path = dev.join(name);
+    match mknod(
+        &path,
+        SFlag::S_IFCHR,
+        Mode::from_bits_truncate(0o666),
+        makedev(major, minor),
+    ) {
+        Ok(()) => {
+            // mknod applies the umask
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o666))
+                .with_context(|| {
+                    format!("Failed to set permissions of {}", path.display())
+                })?;
+        }
+        Err(Errno::EPERM) => {
+            let source = old_root.join("dev").join(name);
+            trace!("bind mount {:?} to {:?}", source, path);
+            fs::File::create(&path).with_context(|| {
+                format!("Failed to create mount point {}", path.display())
+            })?;
+            mount(
+                Some(&source),
+                &path,
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            )
+            .with_context(|| {
+                format!("Failed to bind mount {}", path.display())
+            })?;
+        }
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("Failed to create device {}", path.display())
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Mount a devpts instance of the container's own on `/dev/pts`.
+///
+/// A new instance keeps the container from seeing or opening the host
+/// terminals.
+fn mount_devpts(dev: &Path) -> Result<()> {
+    let pts = dev.join("pts");
+    fs::create_dir_all(&pts).context("Failed to create /dev/pts")?;
+
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC;
+    let options =
+        format!("newinstance,ptmxmode=0666,mode=0620,gid={}", TTY_GID);
+    let result =
+        mount(Some("devpts"), &pts, Some("devpts"), flags, Some(&*options));
+    if result == Err(Errno::EINVAL) {
+        // The tty group is not mapped in the user namespace of a rootless
+        // container without subordinate ids
+        debug!("mounting devpts without gid={}", TTY_GID);
+        mount(
+            Some("devpts"),
+            &pts,
+            Some("devpts"),
+            flags,
+            Some("newinstance,ptmxmode=0666,mode=0620"),
+        )
+    } else {
+        result
+    }
+    .context("Failed to mount /dev/pts")
+}
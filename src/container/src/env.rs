@@ -0,0 +1,206 @@
+//! Container environment variables.
+//!
+//! Containers don't inherit the environment of the `rocker` process, which
+//! would leak host secrets such as `SUDO_*`, `SSH_AUTH_SOCK` or tokens.
+//! Instead the environment is built from a small set of defaults, the
+//! image defaults and the variables given with `-e` / `--env-file`, where
+//! later sources override earlier ones.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Default `PATH` inside containers.
+pub const DEFAULT_PATH: &str =
+    "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Default `HOME` inside containers, replaced by the home directory of the
+/// user given with `--user`.
+pub const DEFAULT_HOME: &str = "/root";
+
+/// Default environment of a container.
+///
+/// # Arguments
+///
+/// * `hostname` - The container hostname, exported as `HOSTNAME`
+///
+/// # Example
+///
+/// ```rust
+/// use container::env;
+///
+/// let defaults = env::defaults("1234567890");
+/// assert!(defaults.contains(&"HOSTNAME=1234567890".to_string()));
+/// ```
+pub fn defaults(hostname: &str) -> Vec<String> {
+    vec![
+        format!("PATH={}", DEFAULT_PATH),
+        format!("HOSTNAME={}", hostname),
+        format!("HOME={}", DEFAULT_HOME),
+        String::from("TERM=xterm"),
+    ]
+}
+
+/// Resolve a `-e` style variable.
+///
+/// `KEY=VALUE` is used as is. A bare `KEY` takes its value from the host
+/// environment and is dropped if the host doesn't define it.
+pub fn resolve_var(spec: &str) -> Option<String> {
+    if spec.contains('=') {
+        return Some(spec.to_string());
+    }
+    std::env::var(spec)
+        .ok()
+        .map(|value| format!("{}={}", spec, value))
+}
+
+/// Read variables from an env file.
+///
+/// The file contains one `KEY=VALUE` or `KEY` entry per line (see
+/// [`resolve_var`]). Empty lines and lines starting with `#` are ignored.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or contains an entry with
+/// an empty variable name
+pub fn parse_env_file(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path).with_context(|| {
+        format!("Failed to read env file {}", path.display())
+    })?;
+
+    let mut vars = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('=') {
+            return Err(anyhow::anyhow!(
+                "Invalid variable on line {} of {}: {}",
+                number + 1,
+                path.display(),
+                line
+            ));
+        }
+        vars.extend(resolve_var(line));
+    }
+    Ok(vars)
+}
+
+/// Merge `KEY=VALUE` lists, later entries overriding earlier ones.
+///
+/// Variables keep the position of their first occurrence.
+///
+/// # Example
+///
+/// ```rust
+/// use container::env;
+///
+/// let merged = env::merge(&[
+///     vec!["A=1".to_string(), "B=2".to_string()],
+///     vec!["A=3".to_string()],
+/// ]);
+/// assert_eq!(merged, vec!["A=3".to_string(), "B=2".to_string()]);
+/// ```
+pub fn merge(layers: &[Vec<String>]) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    for var in layers.iter().flatten() {
+        let key = var.split('=').next().unwrap_or(var);
+        match merged
+            .iter_mut()
+            .find(|existing| existing.split('=').next() == Some(key))
+        {
+            Some(existing) => *existing = var.clone(),
+            None => merged.push(var.clone()),
+        }
+    }
+    merged
+}
+
+/// Look up a variable in a `KEY=VALUE` list.
+pub fn get<'a>(env: &'a [String], key: &str) -> Option<&'a str> {
+    env.iter().find_map(|var| {
+        var.strip_prefix(key)
+            .and_then(|rest| rest.strip_prefix('='))
+    })
+}
+
+/// Find the executable of a command like `execvp`.
+///
+/// Names containing a `/` are returned as is, other names are searched in
+/// the `PATH` of `env`, or [`DEFAULT_PATH`] if it isn't set. The name is
+/// returned unchanged if no executable is found, so that `execve` reports
+/// the error.
+///
+/// # Example
+///
+/// ```rust
+/// use container::env;
+///
+/// let env = vec!["PATH=/nonexistent:/bin".to_string()];
+/// assert_eq!(env::find_executable("sh", &env), "/bin/sh");
+/// assert_eq!(env::find_executable("./run", &env), "./run");
+/// ```
+pub fn find_executable(name: &str, env: &[String]) -> String {
+    if name.contains('/') {
+        return name.to_string();
+    }
+    get(env, "PATH")
+        .unwrap_or(DEFAULT_PATH)
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| {
+            fs::metadata(path).is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let env = defaults("abc");
+        assert_eq!(get(&env, "PATH"), Some(DEFAULT_PATH));
+        assert_eq!(get(&env, "HOSTNAME"), Some("abc"));
+        assert_eq!(get(&env, "HOME"), Some("/root"));
+        assert_eq!(get(&env, "TERM"), Some("xterm"));
+    }
+
+    #[test]
+    fn test_merge_overrides() {
+        let merged = merge(&[
+            defaults("abc"),
+            vec!["HOME=/home/app".to_string(), "A=1=2".to_string()],
+            vec!["A=".to_string()],
+        ]);
+        assert_eq!(get(&merged, "HOME"), Some("/home/app"));
+        assert_eq!(get(&merged, "A"), Some(""));
+        assert_eq!(merged.len(), 5);
+    }
+
+    #[test]
+    fn test_resolve_var() {
+        assert_eq!(resolve_var("A=b c"), Some("A=b c".to_string()));
+        assert_eq!(resolve_var("ROCKER_SURELY_UNDEFINED_VAR"), None);
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let path = std::env::temp_dir()
+            .join(format!("rocker-env-test-{}", std::process::id()));
+        fs::write(&path, "# comment\n\nA=1\n  B=two words\n").unwrap();
+        let vars = parse_env_file(&path).unwrap();
+        assert_eq!(vars, vec!["A=1".to_string(), "B=two words".to_string()]);
+
+        fs::write(&path, "=1\n").unwrap();
+        assert!(parse_env_file(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+//! Container health checks.
+//!
+//! A health check is a shell command that the container monitor runs
+//! inside the container every `interval`. The container is `healthy` once
+//! a probe exits with code 0, and `unhealthy` after `retries` consecutive
+//! failures. Until either happens it is `starting`. Probes that run longer
+//! than `timeout` are killed and count as failures.
+//!
+//! The check is configured with the `--health-*` options of `rocker run`
+//! or the `HEALTHCHECK` default of the image, and its state is recorded in
+//! the container metadata along with the last [`MAX_LOG_ENTRIES`] probes.
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::time::Duration;
+
+/// Time between two probes unless configured.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time a probe may take unless configured.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Consecutive failures making a container unhealthy unless configured.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Number of probe results kept in the container metadata.
+pub const MAX_LOG_ENTRIES: usize = 5;
+
+/// Longest probe output kept in the container metadata, in bytes.
+pub const MAX_OUTPUT_LEN: usize = 4096;
+
+/// Health check of a container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Shell command run with `/bin/sh -c` in the container
+    #[serde(rename = "cmd")]
+    pub command: String,
+
+    /// Time between two probes
+    #[serde(with = "duration_string")]
+    pub interval: Duration,
+
+    /// Time after which a probe is killed and fails
+    #[serde(with = "duration_string")]
+    pub timeout: Duration,
+
+    /// Consecutive failures making the container unhealthy
+    pub retries: u32,
+}
+
+/// Health state of a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HealthStatus {
+    /// No probe succeeded yet, and fewer than `retries` failed
+    #[serde(rename = "starting")]
+    Starting,
+
+    /// The last probe succeeded
+    #[serde(rename = "healthy")]
+    Healthy,
+
+    /// The last `retries` probes failed
+    #[serde(rename = "unhealthy")]
+    Unhealthy,
+}
+
+/// Result of a probe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthProbe {
+    /// Start time, same format as the container times
+    #[serde(rename = "start")]
+    pub start: String,
+
+    /// End time
+    #[serde(rename = "end")]
+    pub end: String,
+
+    /// Exit code of the command, -1 if it couldn't run or timed out
+    #[serde(rename = "exitCode")]
+    pub exit_code: i32,
+
+    /// Combined stdout and stderr, truncated to [`MAX_OUTPUT_LEN`] bytes
+    #[serde(rename = "output")]
+    pub output: String,
+}
+
+/// Health of a container, as recorded in its metadata.
+///
+/// # Example
+///
+/// ```rust
+/// use container::health::{Health, HealthProbe, HealthStatus};
+///
+/// let probe = |exit_code| HealthProbe {
+///     start: "2024-01-01 12:00:00".to_string(),
+///     end: "2024-01-01 12:00:01".to_string(),
+///     exit_code,
+///     output: String::new(),
+/// };
+///
+/// let mut health = Health::default();
+/// health.record(probe(1), 2);
+/// assert_eq!(health.status, HealthStatus::Starting);
+/// health.record(probe(1), 2);
+/// assert_eq!(health.status, HealthStatus::Unhealthy);
+/// health.record(probe(0), 2);
+/// assert_eq!(health.status, HealthStatus::Healthy);
+/// assert_eq!(health.failing_streak, 0);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Health {
+    #[serde(rename = "status")]
+    pub status: HealthStatus,
+
+    /// Number of consecutive failed probes
+    #[serde(rename = "failingStreak")]
+    pub failing_streak: u32,
+
+    /// Last probes, oldest first
+    #[serde(rename = "log")]
+    pub log: Vec<HealthProbe>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            status: HealthStatus::Starting,
+            failing_streak: 0,
+            log: Vec::new(),
+        }
+    }
+}
+
+impl Health {
+    /// Record the result of a probe and update the status.
+    ///
+    /// # Arguments
+    ///
+    /// * `probe` - Result of the probe
+    /// * `retries` - Consecutive failures making the container unhealthy
+    pub fn record(&mut self, probe: HealthProbe, retries: u32) {
+        if probe.exit_code == 0 {
+            self.status = HealthStatus::Healthy;
+            self.failing_streak = 0;
+        } else {
+            self.failing_streak += 1;
+            if self.failing_streak >= retries {
+                self.status = HealthStatus::Unhealthy;
+            }
+        }
+
+        self.log.push(probe);
+        if self.log.len() > MAX_LOG_ENTRIES {
+            self.log.drain(..self.log.len() - MAX_LOG_ENTRIES);
+        }
+    }
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthStatus::Starting => write!(f, "starting"),
+            HealthStatus::Healthy => write!(f, "healthy"),
+            HealthStatus::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+/// Parse a duration made of numbers with a `ms`, `s`, `m` or `h` unit,
+/// e.g. `30s`, `1m30s` or `500ms`.
+///
+/// # Errors
+///
+/// Returns an error if the duration is empty, has a number without unit
+/// or an unknown unit
+///
+/// # Example
+///
+/// ```rust
+/// use container::health::parse_duration;
+/// use std::time::Duration;
+///
+/// assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+/// assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+/// assert!(parse_duration("30").is_err());
+/// ```
+pub fn parse_duration(spec: &str) -> Result<Duration> {
+    let invalid =
+        || anyhow::anyhow!("Invalid duration {}, e.g. 30s or 1m30s", spec);
+    if spec.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = spec;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_len..];
+        total += unit
+            .checked_mul(u32::try_from(value).map_err(|_| invalid())?)
+            .ok_or_else(invalid)?;
+    }
+    Ok(total)
+}
+
+/// Format a duration the way [`parse_duration`] reads it.
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if !millis.is_multiple_of(1000) {
+        return format!("{}ms", millis);
+    }
+
+    let mut seconds = duration.as_secs();
+    let mut formatted = String::new();
+    for (unit, unit_seconds) in [("h", 3600), ("m", 60)] {
+        if seconds >= unit_seconds {
+            formatted.push_str(&format!("{}{}", seconds / unit_seconds, unit));
+            seconds %= unit_seconds;
+        }
+    }
+    if seconds > 0 || formatted.is_empty() {
+        formatted.push_str(&format!("{}s", seconds));
+    }
+    formatted
+}
+
+/// Serialize durations as strings such as `1m30s`.
+mod duration_string {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        let spec = String::deserialize(deserializer)?;
+        parse_duration(&spec).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(
+            parse_duration("1m500ms").unwrap(),
+            Duration::from_millis(60_500)
+        );
+        for invalid in ["", "s", "10", "10x", "1.5s", "-1s", "10 s"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        for spec in ["30s", "1m30s", "2h", "1h1s", "250ms", "0s"] {
+            assert_eq!(format_duration(parse_duration(spec).unwrap()), spec);
+        }
+    }
+
+    #[test]
+    fn test_health_log_is_bounded() {
+        let mut health = Health::default();
+        for exit_code in 0..10 {
+            health.record(
+                HealthProbe {
+                    start: String::new(),
+                    end: String::new(),
+                    exit_code,
+                    output: String::new(),
+                },
+                3,
+            );
+        }
+        assert_eq!(health.log.len(), MAX_LOG_ENTRIES);
+        assert_eq!(health.log[0].exit_code, 5);
+        assert_eq!(health.failing_streak, 9);
+        assert_eq!(health.status, HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn test_health_config_serialization() {
+        let config = HealthConfig {
+            command: "curl -f localhost".to_string(),
+            interval: Duration::from_secs(90),
+            timeout: Duration::from_millis(500),
+            retries: 3,
+        };
+        let serialized = serde_json::to_string(&config).unwrap();
+        assert!(serialized.contains("\"interval\":\"1m30s\""));
+        assert!(serialized.contains("\"timeout\":\"500ms\""));
+        assert_eq!(
+            serde_json::from_str::<HealthConfig>(&serialized).unwrap(),
+            config
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! OCI lifecycle hooks.
+//!
+//! Hooks are host programs that the `hooks` of a bundle configuration runs
+//! at points of the container lifecycle, e.g. to set up the network of the
+//! container. Each gets the [`State`] of the container as JSON on its stdin:
+//!
+//! - `prestart` and `createRuntime`: once the namespaces and cgroups of the
+//!   container are set up, before the container switches to its root
+//!   filesystem. A failing hook aborts the container.
+//! - `poststart`: once the command of the container is started.
+//! - `poststop`: once the command of the container exited.
+//!
+//! A failing `poststart` or `poststop` hook is only warned about. The
+//! `createContainer` and `startContainer` hooks, which run inside the
+//! container, are not supported and ignored.
+
+use crate::spec::State;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+/// How often a running hook is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Longest hook output kept for the error of a failed hook.
+const MAX_OUTPUT_LEN: usize = 4096;
+
+/// Hooks of a bundle configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hooks {
+    /// Deprecated in favor of `createRuntime`, and run right before it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prestart: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub create_runtime: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub poststart: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub poststop: Vec<Hook>,
+}
+
+impl Hooks {
+    /// Hooks run before the container switches to its root filesystem, in
+    /// order.
+    pub fn create(&self) -> impl Iterator<Item = &Hook> {
+        self.prestart.iter().chain(&self.create_runtime)
+    }
+}
+
+/// Host program run by a hook.
+///
+/// # Example
+///
+/// ```rust
+/// use container::hooks::Hook;
+///
+/// let hook: Hook = serde_json::from_str(
+///     r#"{"path": "/usr/bin/logger", "args": ["logger", "-t", "rocker"],
+///         "timeout": 5}"#,
+/// ).unwrap();
+/// assert_eq!(hook.path.to_str(), Some("/usr/bin/logger"));
+/// assert_eq!(hook.args[0], "logger");
+/// assert!(hook.env.is_empty());
+/// assert_eq!(hook.timeout, Some(5));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hook {
+    /// Absolute path of the program
+    pub path: PathBuf,
+
+    /// Arguments, starting with the name the program is run as
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+
+    /// Complete environment of the program, in `KEY=VALUE` form
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+
+    /// Seconds after which the program is killed and the hook fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+impl Hook {
+    /// Run the program with the container state on its stdin.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, with the output of the program, if it can't be
+    /// started, exits unsuccessfully or exceeds its timeout
+    pub fn run(&self, state: &State) -> Result<()> {
+        let state =
+            serde_json::to_vec(state).context("Failed to serialize state")?;
+        let (mut reader, writer) =
+            io::pipe().context("Failed to create pipe")?;
+
+        let mut command = Command::new(&self.path);
+        if let Some((arg0, args)) = self.args.split_first() {
+            command.arg0(arg0).args(args);
+        }
+        command
+            .env_clear()
+            .envs(self.env.iter().filter_map(|var| var.split_once('=')));
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(writer.try_clone().context("Failed to clone pipe")?)
+            .stderr(writer)
+            .spawn()
+            .with_context(|| {
+                format!("Failed to run hook {}", self.path.display())
+            })?;
+        // The command holds on to the pipe writers, which keeps the output
+        // from ending
+        drop(command);
+
+        // A hook that doesn't read its stdin closes it early, which is fine
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(&state);
+        }
+
+        let (output_sender, output) = mpsc::channel();
+        spawn(move || {
+            let mut output = Vec::new();
+            let _ = (&mut reader)
+                .take(MAX_OUTPUT_LEN as u64)
+                .read_to_end(&mut output);
+            let _ = output_sender.send(output);
+            let _ = io::copy(&mut reader, &mut io::sink());
+        });
+
+        let deadline = self
+            .timeout
+            .map(|timeout| Instant::now() + Duration::from_secs(timeout));
+        let status = loop {
+            if let Some(status) = child.try_wait().with_context(|| {
+                format!("Failed to wait for hook {}", self.path.display())
+            })? {
+                break status;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow::anyhow!(
+                    "Hook {} exceeded its timeout of {}s",
+                    self.path.display(),
+                    self.timeout.unwrap_or_default()
+                ));
+            }
+            sleep(POLL_INTERVAL);
+        };
+        if status.success() {
+            return Ok(());
+        }
+
+        // Output of programs the hook left running is not waited for
+        let output = output
+            .recv_timeout(POLL_INTERVAL * 5)
+            .map(|output| String::from_utf8_lossy(&output).trim().to_string())
+            .unwrap_or_default();
+        let reason = match (status.code(), status.signal()) {
+            (Some(code), _) => format!("exited with code {}", code),
+            (None, Some(signal)) => format!("was killed by signal {}", signal),
+            (None, None) => "failed".to_string(),
+        };
+        Err(anyhow::anyhow!(
+            "Hook {} {}{}{}",
+            self.path.display(),
+            reason,
+            if output.is_empty() { "" } else { ": " },
+            output
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn state() -> State {
+        State {
+            oci_version: crate::spec::OCI_VERSION.to_string(),
+            id: "web".to_string(),
+            status: "created".to_string(),
+            pid: Some(4422),
+            bundle: "/containers/web".to_string(),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    fn shell_hook(script: &str, timeout: Option<u64>) -> Hook {
+        Hook {
+            path: PathBuf::from("/bin/sh"),
+            args: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            env: vec!["GREETING=hello".to_string()],
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_hook_gets_state_and_env() {
+        let hook = shell_hook(
+            r#"read -r state; [ "$GREETING" = hello ] && [ -z "$HOME" ] &&
+               case "$state" in *'"id":"web"'*'"pid":4422'*) ;; *) exit 1;; esac"#,
+            None,
+        );
+        hook.run(&state()).unwrap();
+    }
+
+    #[test]
+    fn test_failed_hook() {
+        let err = shell_hook("echo no network >&2; exit 3", None)
+            .run(&state())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Hook /bin/sh exited with code 3: no network"
+        );
+
+        let err = shell_hook("sleep 5", Some(1)).run(&state()).unwrap_err();
+        assert_eq!(err.to_string(), "Hook /bin/sh exceeded its timeout of 1s");
+    }
+
+    #[test]
+    fn test_create_hooks_order() {
+        let hooks: Hooks = serde_json::from_str(
+            r#"{"createRuntime": [{"path": "/bin/b"}],
+                "prestart": [{"path": "/bin/a"}],
+                "createContainer": [{"path": "/bin/c"}]}"#,
+        )
+        .unwrap();
+        let paths: Vec<_> =
+            hooks.create().map(|hook| hook.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("/bin/a"), PathBuf::from("/bin/b")]
+        );
+        assert!(hooks.poststop.is_empty());
+    }
+}
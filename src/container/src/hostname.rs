@@ -0,0 +1,182 @@
+//! Container hostname and domainname.
+//!
+//! Every container has its own UTS namespace, in which `rocker init` sets
+//! the hostname (by default the container ID) and optionally the NIS
+//! domainname. Matching `/etc/hostname` and `/etc/hosts` files are
+//! generated in the container directory and bind-mounted over the ones of
+//! the image, so that the rootfs itself is left untouched.
+
+use crate::volume::Volume;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maximum length of a hostname or domainname accepted by the kernel.
+const MAX_NAME_LEN: usize = 64;
+
+/// Name of the generated hostname file in the container directory.
+const HOSTNAME_FILE: &str = "hostname";
+
+/// Name of the generated hosts file in the container directory.
+const HOSTS_FILE: &str = "hosts";
+
+/// Check that `name` is a valid hostname or domainname.
+///
+/// The name must be at most 64 characters of dot separated labels made of
+/// ASCII letters, digits and hyphens, not starting or ending with a hyphen
+/// (RFC 1123).
+///
+/// # Errors
+///
+/// Returns an error describing why the name is invalid
+pub fn validate(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(anyhow::anyhow!(
+            "Invalid name {:?}, it must be 1 to {} characters long",
+            name,
+            MAX_NAME_LEN
+        ));
+    }
+    for label in name.split('.') {
+        let valid = !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid {
+            return Err(anyhow::anyhow!(
+                "Invalid name {:?}, labels may only contain letters, digits \
+                 and inner hyphens",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Set the hostname and domainname of the current UTS namespace.
+pub fn set(hostname: &str, domainname: Option<&str>) -> Result<()> {
+    nix::unistd::sethostname(hostname)
+        .with_context(|| format!("Failed to set hostname {}", hostname))?;
+
+    if let Some(domainname) = domainname {
+        // SAFETY: the pointer and length describe a valid buffer that
+        // outlives the call
+        let ret = unsafe {
+            libc::setdomainname(
+                domainname.as_ptr() as *const libc::c_char,
+                domainname.len(),
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| {
+                format!("Failed to set domainname {}", domainname)
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Content of the generated `/etc/hosts`.
+///
+/// Besides the usual loopback entries, the container hostname (and its
+/// fully qualified name if a domainname is set) resolves to `127.0.1.1`.
+///
+/// # Example
+///
+/// ```rust
+/// use container::hostname;
+///
+/// let hosts = hostname::hosts_content("web", Some("example.com"));
+/// assert!(hosts.ends_with("127.0.1.1\tweb.example.com web\n"));
+/// ```
+pub fn hosts_content(hostname: &str, domainname: Option<&str>) -> String {
+    let names = match domainname {
+        Some(domainname) => {
+            format!("{}.{} {}", hostname, domainname, hostname)
+        }
+        None => hostname.to_string(),
+    };
+    format!(
+        "127.0.0.1\tlocalhost\n\
+         ::1\tlocalhost ip6-localhost ip6-loopback\n\
+         fe00::0\tip6-localnet\n\
+         ff00::0\tip6-mcastprefix\n\
+         ff02::1\tip6-allnodes\n\
+         ff02::2\tip6-allrouters\n\
+         127.0.1.1\t{}\n",
+        names
+    )
+}
+
+/// Generate `/etc/hostname` and `/etc/hosts` of a container.
+///
+/// The files are written to `dir`, normally the container directory, and
+/// returned as volumes to bind-mount into the container.
+///
+/// # Errors
+///
+/// Returns an error if a file can't be written
+pub fn write_files(
+    dir: &Path,
+    hostname: &str,
+    domainname: Option<&str>,
+) -> Result<Vec<Volume>> {
+    let files = [
+        (HOSTNAME_FILE, format!("{}\n", hostname), "/etc/hostname"),
+        (
+            HOSTS_FILE,
+            hosts_content(hostname, domainname),
+            "/etc/hosts",
+        ),
+    ];
+
+    let mut volumes = Vec::new();
+    for (name, content, container_path) in files {
+        let path = dir.join(name);
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        let mut volume = Volume {
+            host_path: path,
+            container_path: PathBuf::from(container_path),
+            read_only: false,
+        };
+        volume.prepare_host()?;
+        volumes.push(volume);
+    }
+    Ok(volumes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("web-1").is_ok());
+        assert!(validate("db.internal").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("-web").is_err());
+        assert!(validate("web_1").is_err());
+        assert!(validate("a..b").is_err());
+        assert!(validate(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_write_files() {
+        let dir = std::env::temp_dir()
+            .join(format!("rocker-hostname-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let volumes = write_files(&dir, "web", None).unwrap();
+        assert_eq!(volumes[0].container_path, PathBuf::from("/etc/hostname"));
+        assert_eq!(volumes[1].container_path, PathBuf::from("/etc/hosts"));
+        assert_eq!(fs::read_to_string(&volumes[0].host_path).unwrap(), "web\n");
+        assert!(
+            fs::read_to_string(&volumes[1].host_path)
+                .unwrap()
+                .contains("127.0.1.1\tweb\n")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
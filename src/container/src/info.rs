@@ -2,7 +2,10 @@
 //!
 //! This module defines the container information structure.
 
-use chrono::Utc;
+use crate::health::{Health, HealthConfig, HealthStatus};
+use crate::restart::RestartPolicy;
+use crate::seccomp::SeccompProfile;
+use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -18,16 +21,36 @@ use std::time::{SystemTime, UNIX_EPOCH};
 /// - `name`: Human-readable container name (defaults to ID if not provided)
 /// - `command`: The command running inside the container
 /// - `created_time`: Container creation timestamp in "2006-01-02 15:04:05" format (Go reference time)
-/// - `status`: Current container status (Running, Stopped, Exited)
-/// - `volume`: Optional volume mount specification (host_path:container_path)
+/// - `started_time`: Time the container command last started, same format as `created_time`
+/// - `status`: Current container status (Created, Running, Paused, Restarting, Stopped, Exited)
+/// - `exit_code`: Exit code of the container command once it exited
+/// - `signal`: Signal that killed the container command, if any
+/// - `oom_killed`: Whether the OOM killer killed a process of the container
+/// - `finished_time`: Time the container command exited, same format as `created_time`
+/// - `restart_policy`: When the monitor restarts the container command
+/// - `restart_count`: Number of times the container command was restarted
+/// - `healthcheck`: Health check run by the monitor, if any
+/// - `health`: Health state and last probe results, if there is a health check
+/// - `tty`: Whether the container has a terminal
+/// - `volumes`: Volume mount specifications (host_path:container_path:mode)
+/// - `env`: Environment of the container command (KEY=VALUE)
+/// - `seccomp`: Seccomp profile of the container, also applied to `rocker exec`
+/// - `capabilities`: Capabilities kept by the container, also applied to `rocker exec`
+/// - `user`: User of the container command, the default of `rocker exec`
+/// - `workdir`: Working directory of the container command, the default of `rocker exec`
 /// - `port_mapping`: List of port mapping specifications
 /// - `network`: Optional network name for container networking
-/// - `image_name`: Name of the container image
+/// - `image_name`: Name of the container image, or rootfs of its bundle
+/// - `bundle`: OCI bundle the container was created from, if any
+///
+/// Unset optional fields are serialized as `null`, so that `rocker inspect
+/// -f` finds every field.
 ///
 /// # Example
 ///
 /// ```rust
 /// use container::info::{ContainerInfo, ContainerStatus};
+/// use container::restart::RestartPolicy;
 ///
 /// let info = ContainerInfo {
 ///     pid: "12345".to_string(),
@@ -35,11 +58,27 @@ use std::time::{SystemTime, UNIX_EPOCH};
 ///     name: "my_container".to_string(),
 ///     command: "/bin/sh".to_string(),
 ///     created_time: ContainerInfo::current_time(),
+///     started_time: None,
 ///     status: ContainerStatus::Running,
-///     volume: Some("/host/path:/container/path".to_string()),
+///     exit_code: None,
+///     signal: None,
+///     oom_killed: false,
+///     finished_time: None,
+///     restart_policy: RestartPolicy::No,
+///     restart_count: 0,
+///     healthcheck: None,
+///     health: None,
+///     tty: false,
+///     volumes: vec!["/host/path:/container/path:rw".to_string()],
+///     env: vec!["PATH=/usr/bin:/bin".to_string()],
+///     seccomp: None,
+///     capabilities: None,
+///     user: None,
+///     workdir: None,
 ///     port_mapping: vec!["8080:80".to_string()],
 ///     network: Some("bridge".to_string()),
 ///     image_name: "busybox".to_string(),
+///     bundle: None,
 /// };
 /// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,13 +103,76 @@ pub struct ContainerInfo {
     #[serde(rename = "createTime")]
     pub created_time: String,
 
+    /// Time the container command last started, differs from the
+    /// creation time once it was restarted
+    #[serde(rename = "startTime", default)]
+    pub started_time: Option<String>,
+
     /// Container status
     #[serde(rename = "status")]
     pub status: ContainerStatus,
 
-    /// Volume mount specification (host_path:container_path)
-    #[serde(rename = "volume")]
-    pub volume: Option<String>,
+    /// Exit code of the container command, 128 plus the signal number if
+    /// it was killed by a signal
+    #[serde(rename = "exitCode", default)]
+    pub exit_code: Option<i32>,
+
+    /// Name of the signal that killed the container command, e.g. `SIGKILL`
+    #[serde(rename = "signal", default)]
+    pub signal: Option<String>,
+
+    /// Whether the OOM killer killed a process of the container
+    #[serde(rename = "oomKilled", default)]
+    pub oom_killed: bool,
+
+    /// Time the container command exited
+    #[serde(rename = "finishTime", default)]
+    pub finished_time: Option<String>,
+
+    /// When the monitor restarts the container command
+    #[serde(rename = "restartPolicy", default)]
+    pub restart_policy: RestartPolicy,
+
+    /// Number of times the container command was restarted
+    #[serde(rename = "restartCount", default)]
+    pub restart_count: u32,
+
+    /// Health check run by the monitor, `None` if there is none
+    #[serde(rename = "healthcheck", default)]
+    pub healthcheck: Option<HealthConfig>,
+
+    /// Health state, `None` if there is no health check
+    #[serde(rename = "health", default)]
+    pub health: Option<Health>,
+
+    /// Whether the container has a terminal
+    #[serde(rename = "tty", default)]
+    pub tty: bool,
+
+    /// Volume mount specifications (host_path:container_path:mode)
+    #[serde(rename = "volumes", default)]
+    pub volumes: Vec<String>,
+
+    /// Environment of the container command (KEY=VALUE)
+    #[serde(rename = "env", default)]
+    pub env: Vec<String>,
+
+    /// Seccomp profile, `None` if the container is unconfined
+    #[serde(rename = "seccomp", default)]
+    pub seccomp: Option<SeccompProfile>,
+
+    /// Capabilities kept by the container, `None` if it keeps all of them
+    #[serde(rename = "capabilities", default)]
+    pub capabilities: Option<Vec<String>>,
+
+    /// User of the container command (`name|uid[:group|gid]`), `None` for
+    /// root
+    #[serde(rename = "user", default)]
+    pub user: Option<String>,
+
+    /// Working directory of the container command, `None` for `/`
+    #[serde(rename = "workdir", default)]
+    pub workdir: Option<String>,
 
     /// Port mapping specifications
     #[serde(rename = "portmapping")]
@@ -83,17 +185,36 @@ pub struct ContainerInfo {
     /// Image name
     #[serde(rename = "imageName")]
     pub image_name: String,
+
+    /// Absolute path of the OCI bundle the container was created from
+    #[serde(rename = "bundle", default)]
+    pub bundle: Option<String>,
 }
 
+/// Format of `created_time` and `finished_time`.
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
 /// Container status enumeration.
 ///
 /// Represents the current state of a container.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ContainerStatus {
+    /// Container is set up and waits for `rocker start` to run its command
+    #[serde(rename = "created")]
+    Created,
+
     /// Container is currently running
     #[serde(rename = "running")]
     Running,
 
+    /// Container processes are frozen by `rocker pause`
+    #[serde(rename = "paused")]
+    Paused,
+
+    /// Container command has exited and is waiting to be restarted
+    #[serde(rename = "restarting")]
+    Restarting,
+
     /// Container has been stopped
     #[serde(rename = "stopped")]
     Stopped,
@@ -106,7 +227,10 @@ pub enum ContainerStatus {
 impl std::fmt::Display for ContainerStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            ContainerStatus::Created => write!(f, "created"),
             ContainerStatus::Running => write!(f, "running"),
+            ContainerStatus::Paused => write!(f, "paused"),
+            ContainerStatus::Restarting => write!(f, "restarting"),
             ContainerStatus::Stopped => write!(f, "stopped"),
             ContainerStatus::Exited => write!(f, "exited"),
         }
@@ -138,6 +262,36 @@ impl ContainerInfo {
         format!("{:010}", timestamp % 10_000_000_000)
     }
 
+    /// Check a container ID given by the user, as by `rocker create`.
+    ///
+    /// IDs are used as directory and cgroup names, so they may only contain
+    /// ASCII letters, digits, `_`, `-` and `.`, and must start with a
+    /// letter or digit.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use container::info::ContainerInfo;
+    ///
+    /// assert!(ContainerInfo::validate_id("web-1.prod").is_ok());
+    /// assert!(ContainerInfo::validate_id("../web").is_err());
+    /// ```
+    pub fn validate_id(id: &str) -> anyhow::Result<()> {
+        let valid = id.len() <= 64
+            && id.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+        if !valid {
+            return Err(anyhow::anyhow!(
+                "Invalid container ID {:?}, expected at most 64 letters, \
+                 digits, '_', '-' or '.', starting with a letter or digit",
+                id
+            ));
+        }
+        Ok(())
+    }
+
     /// Get current time formatted as "2006-01-02 15:04:05".
     ///
     /// This format is Go's reference time (01/02 03:04:05PM '06 -0700),
@@ -157,7 +311,110 @@ impl ContainerInfo {
     /// ```
     pub fn current_time() -> String {
         let now = Utc::now();
-        now.format("%Y-%m-%d %H:%M:%S").to_string()
+        now.format(TIME_FORMAT).to_string()
+    }
+
+    /// Describe the status like `docker ps`, e.g. `Up 2 hours (healthy)`,
+    /// `Up 5 minutes (Paused)`, `Restarting (1) 3 seconds ago` or
+    /// `Exited (137) 5 minutes ago`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use container::info::{ContainerInfo, ContainerStatus};
+    /// # let mut info: ContainerInfo = serde_json::from_str(
+    /// #     r#"{"pid": "", "id": "1234567890", "name": "web",
+    /// #         "command": "sh", "createTime": "2024-01-01 12:00:00",
+    /// #         "status": "running", "portmapping": [], "network": null,
+    /// #         "imageName": "busybox:latest"}"#,
+    /// # ).unwrap();
+    ///
+    /// info.status = ContainerStatus::Exited;
+    /// info.exit_code = Some(137);
+    /// info.finished_time = Some(ContainerInfo::current_time());
+    /// assert_eq!(info.status_summary(), "Exited (137) Less than a second ago");
+    /// ```
+    pub fn status_summary(&self) -> String {
+        match self.status {
+            ContainerStatus::Running | ContainerStatus::Paused => {
+                let started =
+                    self.started_time.as_deref().unwrap_or(&self.created_time);
+                let mut summary = match since(started) {
+                    Some(elapsed) => format!("Up {}", human_duration(elapsed)),
+                    None => "Up".to_string(),
+                };
+                if self.status == ContainerStatus::Paused {
+                    summary.push_str(" (Paused)");
+                    return summary;
+                }
+                match self.health.as_ref().map(|health| health.status) {
+                    Some(HealthStatus::Starting) => {
+                        summary.push_str(" (health: starting)")
+                    }
+                    Some(status) => summary.push_str(&format!(" ({})", status)),
+                    None => {}
+                }
+                summary
+            }
+            ContainerStatus::Created => "Created".to_string(),
+            ContainerStatus::Stopped => "Stopped".to_string(),
+            ContainerStatus::Restarting | ContainerStatus::Exited => {
+                let mut summary = match self.status {
+                    ContainerStatus::Restarting => "Restarting",
+                    _ => "Exited",
+                }
+                .to_string();
+                if let Some(code) = self.exit_code {
+                    summary.push_str(&format!(" ({})", code));
+                }
+                if let Some(elapsed) =
+                    self.finished_time.as_deref().and_then(since)
+                {
+                    summary
+                        .push_str(&format!(" {} ago", human_duration(elapsed)));
+                }
+                summary
+            }
+        }
+    }
+}
+
+/// Time elapsed since a `created_time` or `finished_time`, `None` if it
+/// can't be parsed.
+fn since(time: &str) -> Option<chrono::Duration> {
+    let time = NaiveDateTime::parse_from_str(time, TIME_FORMAT).ok()?;
+    Some(Utc::now().naive_utc() - time)
+}
+
+/// Round a duration the way Docker does, e.g. `About a minute` or
+/// `3 days`.
+fn human_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds();
+    let minutes = duration.num_minutes();
+    let hours = duration.num_hours();
+    let days = duration.num_days();
+    if seconds < 1 {
+        "Less than a second".to_string()
+    } else if seconds == 1 {
+        "1 second".to_string()
+    } else if seconds < 60 {
+        format!("{} seconds", seconds)
+    } else if minutes == 1 {
+        "About a minute".to_string()
+    } else if minutes < 60 {
+        format!("{} minutes", minutes)
+    } else if hours == 1 {
+        "About an hour".to_string()
+    } else if hours < 48 {
+        format!("{} hours", hours)
+    } else if days < 14 {
+        format!("{} days", days)
+    } else if days < 60 {
+        format!("{} weeks", days / 7)
+    } else if days < 365 * 2 {
+        format!("{} months", days / 30)
+    } else {
+        format!("{} years", days / 365)
     }
 }
 
@@ -189,6 +446,10 @@ mod tests {
         let running = ContainerStatus::Running;
         let serialized = serde_json::to_string(&running).unwrap();
         assert_eq!(serialized, "\"running\"");
+
+        let serialized =
+            serde_json::to_string(&ContainerStatus::Paused).unwrap();
+        assert_eq!(serialized, "\"paused\"");
     }
 
     #[test]
@@ -199,15 +460,60 @@ mod tests {
             name: "test".to_string(),
             command: "/bin/sh".to_string(),
             created_time: "2024-01-01 12:00:00".to_string(),
+            started_time: None,
             status: ContainerStatus::Running,
-            volume: None,
+            exit_code: None,
+            signal: None,
+            oom_killed: false,
+            finished_time: None,
+            restart_policy: RestartPolicy::No,
+            restart_count: 0,
+            healthcheck: None,
+            health: None,
+            tty: false,
+            volumes: vec![],
+            env: vec![],
+            seccomp: None,
+            capabilities: None,
+            user: None,
+            workdir: None,
             port_mapping: vec![],
             network: None,
             image_name: "busybox".to_string(),
+            bundle: None,
         };
 
         let serialized = serde_json::to_string_pretty(&info).unwrap();
         assert!(serialized.contains("\"pid\": \"12345\""));
         assert!(serialized.contains("\"status\": \"running\""));
+        // Unset fields are written too, so that all of them can be inspected
+        assert!(serialized.contains("\"exitCode\": null"));
+        assert!(serialized.contains("\"finishTime\": null"));
+    }
+
+    #[test]
+    fn test_validate_id() {
+        for id in ["1234567890", "web", "web_1.prod-2"] {
+            assert!(ContainerInfo::validate_id(id).is_ok(), "{}", id);
+        }
+        for id in ["", "-web", ".", "a/b", "web 1", &"a".repeat(65)] {
+            assert!(ContainerInfo::validate_id(id).is_err(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_human_duration() {
+        let duration = chrono::Duration::seconds;
+        assert_eq!(human_duration(duration(0)), "Less than a second");
+        assert_eq!(human_duration(duration(1)), "1 second");
+        assert_eq!(human_duration(duration(59)), "59 seconds");
+        assert_eq!(human_duration(duration(90)), "About a minute");
+        assert_eq!(human_duration(duration(5 * 60)), "5 minutes");
+        assert_eq!(human_duration(duration(3600)), "About an hour");
+        assert_eq!(human_duration(duration(47 * 3600)), "47 hours");
+        assert_eq!(human_duration(duration(3 * 86400)), "3 days");
+        assert_eq!(human_duration(duration(21 * 86400)), "3 weeks");
+        assert_eq!(human_duration(duration(90 * 86400)), "3 months");
+        assert_eq!(human_duration(duration(800 * 86400)), "2 years");
     }
 }
@@ -0,0 +1,104 @@
+//! Built-in init process.
+//!
+//! The command normally replaces `rocker init` as PID 1 of the container.
+//! PID 1 is special: the kernel doesn't apply the default action of
+//! signals it has no handler for, so most programs ignore the `SIGTERM` of
+//! `rocker stop`, and it inherits every orphaned process, which few
+//! programs expect and reap.
+//!
+//! With `rocker run --init`, `rocker init` stays PID 1 instead. It forks
+//! the command, forwards the signals it receives to it, reaps every child
+//! that exits and finally exits with the status of the command.
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::sys::signal::{SigSet, SigmaskHow, Signal, kill};
+use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
+use nix::unistd::{ForkResult, Pid, fork, getpid, setpgid, tcsetpgrp};
+
+/// Exit status of a command that couldn't be started.
+const EXEC_FAILED: i32 = 127;
+
+/// Run `exec` in a child process and supervise it as PID 1.
+///
+/// `exec` is expected to `execve` the command and only returns on error.
+/// With a terminal, the command gets a process group of its own in the
+/// foreground, so that keys such as `^C` reach it directly.
+///
+/// Doesn't return unless the fork fails: the calling process exits with
+/// the status of the command, or 128 plus the signal number if it was
+/// killed by a signal.
+pub fn run<F: FnOnce() -> Result<()>>(tty: bool, exec: F) -> Result<()> {
+    // Blocked before the fork so that no signal is lost in between. The
+    // kernel queues blocked signals even for PID 1, while signals with the
+    // default action would be dropped.
+    let signals = SigSet::all();
+    let original = signals
+        .thread_swap_mask(SigmaskHow::SIG_BLOCK)
+        .context("Failed to block signals")?;
+
+    // SAFETY: rocker init is single threaded
+    match unsafe { fork() }.context("Failed to fork the command")? {
+        ForkResult::Child => {
+            let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+            if tty {
+                // SIGTTOU is still blocked, which allows this from the
+                // background
+                let _ = tcsetpgrp(0, getpid());
+            }
+            let result = original
+                .thread_set_mask()
+                .context("Failed to restore the signal mask")
+                .and_then(|_| exec());
+            if let Err(e) = result {
+                eprintln!("rocker init: {:#}", e);
+            }
+            // SAFETY: leaves without running anything of the parent
+            unsafe { libc::_exit(EXEC_FAILED) }
+        }
+        ForkResult::Parent { child } => supervise(child, &signals),
+    }
+}
+
+/// Forward signals to `child` and reap exited children until `child`
+/// exits, then exit with its status.
+fn supervise(child: Pid, signals: &SigSet) -> ! {
+    loop {
+        match signals.wait() {
+            Ok(Signal::SIGCHLD) => {
+                if let Some(status) = reap(child) {
+                    std::process::exit(status);
+                }
+            }
+            Ok(signal) => {
+                trace!("forwarding {} to {}", signal, child);
+                let _ = kill(child, signal);
+            }
+            Err(e) => {
+                error!("Failed to wait for signals: {}", e);
+                std::process::exit(EXEC_FAILED);
+            }
+        }
+    }
+}
+
+/// Reap all exited children, returning the exit status of `child` if it
+/// is one of them.
+fn reap(child: Pid) -> Option<i32> {
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) if pid == child => {
+                return Some(code);
+            }
+            Ok(WaitStatus::Signaled(pid, signal, _)) if pid == child => {
+                return Some(128 + signal as i32);
+            }
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => return None,
+            Ok(status) => trace!("reaped {:?}", status),
+            Err(e) => {
+                warn!("Failed to reap children: {}", e);
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Configuration handed from `rocker run` to `rocker init`.
+//!
+//! The container init process is started as `/proc/self/exe init` and
+//! receives everything it needs to set up the container over a pipe
+//! instead of the command line. Passing the command as a single string
+//! used to mangle arguments containing spaces or quotes, and it gave no
+//! way to pass the environment or working directory.
+//!
+//! The pipe also acts as a synchronisation point: `rocker init` blocks
+//! reading it, so the parent can finish its own setup (cgroups, metadata)
+//! before the container starts running the user command.
+
+use crate::seccomp::SeccompProfile;
+use crate::tmpfs::Tmpfs;
+use crate::volume::Volume;
+use crate::workspace::Overlay;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::PathBuf;
+
+/// File descriptor of the read end of the init pipe inside the container.
+pub const INIT_PIPE_FD: RawFd = 3;
+
+/// File descriptor of the write end of the setup pipe inside a container
+/// created by `rocker create`, see [`crate::Container::wait_setup`].
+pub const INIT_SYNC_FD: RawFd = 5;
+
+/// Container init configuration.
+///
+/// # Example
+///
+/// ```rust
+/// use container::init_config::InitConfig;
+///
+/// let config = InitConfig {
+///     args: vec!["sh".to_string(), "-c".to_string(), "echo a b".to_string()],
+///     env: vec!["PATH=/bin:/usr/bin".to_string()],
+///     cwd: "/".to_string(),
+///     ..Default::default()
+/// };
+///
+/// let mut buf = Vec::new();
+/// config.write_to(&mut buf).unwrap();
+/// let decoded = InitConfig::read_from(buf.as_slice()).unwrap();
+/// assert_eq!(decoded.args, config.args);
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InitConfig {
+    /// Command and arguments to execute, exactly as given by the user
+    pub args: Vec<String>,
+
+    /// Environment of the command in `KEY=VALUE` form
+    pub env: Vec<String>,
+
+    /// Working directory of the command inside the container, created if
+    /// missing
+    pub cwd: String,
+
+    /// User of the command (`name|uid[:group|gid]`), resolved inside the
+    /// container, `None` for root
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Whether to allocate a terminal for the command, see [`crate::tty`]
+    #[serde(default)]
+    pub tty: bool,
+
+    /// Whether `rocker init` stays PID 1 and runs the command as its child,
+    /// see [`crate::init`]
+    #[serde(default)]
+    pub init: bool,
+
+    /// Hostname of the container, the host's name is kept if empty
+    #[serde(default)]
+    pub hostname: String,
+
+    /// NIS domainname of the container
+    #[serde(default)]
+    pub domainname: Option<String>,
+
+    /// Volumes to bind-mount into the container
+    pub volumes: Vec<Volume>,
+
+    /// Tmpfs filesystems to mount into the container
+    #[serde(default)]
+    pub tmpfs: Vec<Tmpfs>,
+
+    /// Whether to mount the container rootfs read-only
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Paths hidden from the container, see [`crate::system_paths`]
+    #[serde(default)]
+    pub masked_paths: Vec<String>,
+
+    /// Paths the container may only read
+    #[serde(default)]
+    pub readonly_paths: Vec<String>,
+
+    /// Overlay to mount as the container rootfs before `pivot_root`.
+    ///
+    /// Only set for rootless containers, whose rootfs can't be mounted on
+    /// the host. Otherwise the rootfs is already mounted.
+    #[serde(default)]
+    pub rootfs: Option<Overlay>,
+
+    /// Seccomp profile of the command, `None` for an unconfined container
+    #[serde(default)]
+    pub seccomp: Option<SeccompProfile>,
+
+    /// Capabilities kept by the command, `None` to keep all of them
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+
+    /// Named pipe the container waits on once it is set up, until `rocker
+    /// start` reads it. `None` to run the command right away.
+    #[serde(default)]
+    pub start_fifo: Option<PathBuf>,
+}
+
+impl InitConfig {
+    /// Serialize the configuration into `writer`.
+    ///
+    /// The reader detects the end of the configuration by EOF, so the
+    /// writer must be closed afterwards.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        serde_json::to_writer(&mut writer, self)
+            .context("Failed to serialize init config")?;
+        writer.flush().context("Failed to flush init config")?;
+        Ok(())
+    }
+
+    /// Read a configuration written by [`InitConfig::write_to`].
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut json = String::new();
+        reader
+            .read_to_string(&mut json)
+            .context("Failed to read init config")?;
+        serde_json::from_str(&json).context("Failed to deserialize init config")
+    }
+
+    /// Read the configuration from the init pipe.
+    ///
+    /// This must only be called once by `rocker init`; the pipe is closed
+    /// afterwards so it doesn't leak into the user command.
+    pub fn read_from_pipe() -> Result<Self> {
+        // SAFETY: INIT_PIPE_FD is set up by `Container::create_parent_process`
+        // and not used by anything else in the init process.
+        let pipe = unsafe { File::from_raw_fd(INIT_PIPE_FD) };
+        Self::read_from(pipe)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_config_round_trip() {
+        let config = InitConfig {
+            args: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo \"a  b\"".to_string(),
+                "".to_string(),
+            ],
+            env: vec!["A=1 2".to_string()],
+            cwd: "/tmp".to_string(),
+            user: Some("app:wheel".to_string()),
+            tty: true,
+            init: true,
+            hostname: "web".to_string(),
+            domainname: None,
+            volumes: vec![Volume::parse("/host:/data:ro").unwrap()],
+            tmpfs: vec![Tmpfs::parse("/run:size=1m").unwrap()],
+            read_only: true,
+            masked_paths: vec!["/proc/kcore".to_string()],
+            readonly_paths: vec!["/proc/sys".to_string()],
+            rootfs: None,
+            seccomp: Some(SeccompProfile::default()),
+            capabilities: Some(vec!["CAP_KILL".to_string()]),
+            start_fifo: Some(PathBuf::from("/var/run/rocker/web/start.fifo")),
+        };
+
+        let mut buf = Vec::new();
+        config.write_to(&mut buf).unwrap();
+        let decoded = InitConfig::read_from(buf.as_slice()).unwrap();
+
+        assert_eq!(decoded.args, config.args);
+        assert_eq!(decoded.env, config.env);
+        assert_eq!(decoded.cwd, "/tmp");
+        assert_eq!(decoded.user, config.user);
+        assert!(decoded.tty);
+        assert!(decoded.init);
+        assert_eq!(decoded.hostname, "web");
+        assert_eq!(decoded.volumes, config.volumes);
+        assert_eq!(decoded.tmpfs, config.tmpfs);
+        assert!(decoded.read_only);
+        assert_eq!(decoded.masked_paths, config.masked_paths);
+        assert_eq!(decoded.readonly_paths, config.readonly_paths);
+        assert_eq!(decoded.seccomp, config.seccomp);
+        assert_eq!(decoded.capabilities, config.capabilities);
+        assert_eq!(decoded.start_fifo, config.start_fifo);
+    }
+
+    #[test]
+    fn test_init_config_truncated() {
+        assert!(InitConfig::read_from(&b"{\"args\": [\"sh\""[..]).is_err());
+    }
+}
@@ -2,28 +2,80 @@
 //!
 //! This module provides the core container runtime implementation including:
 //! - Process creation with namespace isolation
+//! - Copy-on-write overlayfs workspace per container
 //! - Root filesystem setup with pivot_root
-//! - Mount operations for /proc and /dev
+//! - Container hostname with generated /etc/hostname and /etc/hosts
+//! - Mount operations for /proc, /sys and /dev, with the standard device
+//!   nodes
+//! - Masked and read-only system paths
+//! - Pseudo-terminal allocation for interactive containers
+//! - Rootless containers with user namespaces
+//! - Bind-mount volumes and tmpfs mounts
+//! - Read-only container rootfs
+//! - Seccomp syscall filtering
+//! - Capability dropping
+//! - Working directory and user of the container command
+//! - Optional built-in init process reaping zombies and forwarding signals
+//! - Container environment variables
 //! - Container metadata persistence
+//! - Restart policies
+//! - Health check state
+//! - OCI runtime-spec bundles
+//! - OCI lifecycle hooks
 
 // Module declarations
+pub mod capabilities;
+pub mod devices;
+pub mod env;
+pub mod health;
+pub mod hooks;
+pub mod hostname;
 pub mod info;
+pub mod init;
+pub mod init_config;
+pub mod restart;
+pub mod rootless;
+pub mod seccomp;
+pub mod spec;
 pub mod store;
+pub mod system_paths;
+pub mod tmpfs;
+pub mod tty;
+pub mod user;
+pub mod volume;
+pub mod workspace;
 
 // Re-export public types
+pub use capabilities::CapabilitySet;
+pub use health::{Health, HealthConfig, HealthStatus};
 pub use info::{ContainerInfo, ContainerStatus};
+pub use init_config::InitConfig;
+pub use restart::RestartPolicy;
+pub use seccomp::{SeccompFilter, SeccompProfile};
+pub use spec::Spec;
 pub use store::ContainerStore;
+pub use tmpfs::Tmpfs;
+pub use user::User;
+pub use volume::Volume;
+pub use workspace::{Overlay, Workspace};
 
 extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use init_config::{INIT_PIPE_FD, INIT_SYNC_FD};
 use nix::mount::*;
-use nix::unistd::{chdir, execve, pipe, pivot_root};
+use nix::unistd::{chdir, execve, pivot_root};
+use spec::{LinuxNamespace, NamespaceType};
 use std::ffi::CString;
-use std::os::unix::prelude::PermissionsExt;
-use std::path::PathBuf;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use tty::CONSOLE_SOCKET_FD;
 use unshare::{Child, Command, Fd, Namespace, Stdio};
 use users::{get_current_gid, get_current_uid};
 
@@ -47,58 +99,142 @@ impl Container {
     /// which will be overwritten by the process of the cmd program that will be run. In other words, when this system call is called,
     /// the user-specified cmd will be run, replacing the initial init command process through the clone namespace, so that when we enter the container,
     /// we will find that the first process inside the container is the cmd process we specified with the init command
-    pub fn init_process(cmd: &str, _args: &[&'static str]) -> Result<()> {
-        let cmd_vec = cmd.split(" ").collect::<Vec<&str>>();
+    ///
+    /// The command, its environment and the rest of the container setup are read from the init pipe
+    /// (see [`InitConfig`]), so arguments reach `execve` exactly as the user gave them.
+    pub fn init_process() -> Result<()> {
+        let config = InitConfig::read_from_pipe()?;
+        debug!("rocker init config:{:?}", config);
+
+        let cmd_vec = &config.args;
+        if cmd_vec.is_empty() {
+            return Err(anyhow::anyhow!("No command specified"));
+        }
+
+        // Opened while the host filesystem is still reachable, it is
+        // reopened through /proc once the container is set up
+        let start_fifo = config
+            .start_fifo
+            .as_ref()
+            .map(|path| {
+                OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_PATH)
+                    .open(path)
+                    .with_context(|| {
+                        format!("Failed to open {}", path.display())
+                    })
+            })
+            .transpose()?;
+
+        Self::setup_mount(&config)?;
+
+        if config.tty {
+            tty::setup_console()?;
+        }
+
+        if config.cwd != "/" {
+            chdir(config.cwd.as_str()).with_context(|| {
+                format!("Failed to change directory to {}", config.cwd)
+            })?;
+        }
 
-        Self::setup_mount();
+        // Resolved against the container's own /etc/passwd and /etc/group
+        let user = config
+            .user
+            .as_deref()
+            .map(|spec| User::resolve(spec, Path::new("/")))
+            .transpose()?;
+        if let Some(user) = &user {
+            user.check_mapped()?;
+        }
+        let mut env = config.env.clone();
+        if let Some(user) = &user
+            && env::get(&env, "HOME") == Some(env::DEFAULT_HOME)
+        {
+            env = env::merge(&[env, vec![format!("HOME={}", user.home)]]);
+        }
 
-        let argv: Vec<CString> =
-            cmd_vec.iter().map(|x| CString::new(*x).unwrap()).collect();
+        let argv = cmd_vec
+            .iter()
+            .map(|x| CString::new(x.as_str()))
+            .collect::<Result<Vec<CString>, _>>()
+            .context("Command arguments must not contain NUL bytes")?;
 
         // After pivot_root, we need to find the executable in the new root
-        // Try to resolve the path: if absolute path exists, use it; otherwise search in PATH
-        let bin_path = if cmd_vec[0].starts_with('/') {
-            // Absolute path - use as-is
-            cmd_vec[0].to_string()
-        } else {
-            // Relative path - search in standard locations
-            let search_paths = ["/bin", "/usr/bin", "/sbin", "/usr/sbin"];
-            let mut found_path = None;
-            for path in &search_paths {
-                let full_path = std::path::Path::new(path).join(cmd_vec[0]);
-                if full_path.exists() {
-                    found_path = Some(full_path);
-                    break;
-                }
-            }
-            found_path
-                .map(|p| p.to_str().unwrap().to_string())
-                .unwrap_or_else(|| cmd_vec[0].to_string())
-        };
+        let bin_path = env::find_executable(&cmd_vec[0], &env);
+        let path = CString::new(bin_path.as_str())
+            .context("Command must not contain NUL bytes")?;
+
+        let envs = env
+            .iter()
+            .map(|x| CString::new(x.as_str()))
+            .collect::<Result<Vec<CString>, _>>()
+            .context("Environment variables must not contain NUL bytes")?;
+
+        if let Some(start_fifo) = start_fifo {
+            Self::wait_for_start(start_fifo)?;
+        }
 
-        let path = CString::new(bin_path.as_str()).unwrap();
+        // Installed after the setup above, which uses syscalls the filter
+        // may deny (such as mount), but before the capabilities are
+        // dropped: without CAP_SYS_ADMIN the kernel refuses the filter
+        if let Some(profile) = &config.seccomp {
+            SeccompFilter::compile(profile, config.capabilities.as_deref())?
+                .install()
+                .context("Failed to install seccomp filter")?;
+        }
 
-        let envs: Vec<CString> = std::env::vars()
-            .map(|(k, v)| {
-                CString::new(format!("{}={}", k, v).as_str()).unwrap()
-            })
-            .collect();
-        execve(path.as_c_str(), &argv, &envs).map_err(|error| {
-            anyhow::anyhow!("Could not start the program with error: {}", error)
-        })?;
+        let capabilities = config
+            .capabilities
+            .as_deref()
+            .map(CapabilitySet::from_names)
+            .transpose()?;
+        user::drop_privileges(user.as_ref(), capabilities.as_ref())
+            .context("Failed to drop privileges")?;
+
+        let exec = || -> Result<()> {
+            execve(path.as_c_str(), &argv, &envs).map_err(|error| {
+                anyhow::anyhow!(
+                    "Could not start the program with error: {}",
+                    error
+                )
+            })?;
+            Ok(())
+        };
+        // The built-in init is forked last, so that it is as confined as
+        // the command
+        if config.init {
+            return init::run(config.tty, exec);
+        }
+        exec()
+    }
+
+    /// Report that the container is set up, then block until `rocker
+    /// start` opens the start FIFO and reads it.
+    fn wait_for_start(start_fifo: File) -> Result<()> {
+        // SAFETY: INIT_SYNC_FD is set up by `Container::create_parent_process`
+        // whenever there is a start FIFO, and not used by anything else
+        let mut sync = unsafe { File::from_raw_fd(INIT_SYNC_FD) };
+        sync.write_all(b"1")
+            .context("Failed to report the container setup")?;
+        drop(sync);
+
+        let mut fifo = OpenOptions::new()
+            .write(true)
+            .open(format!("/proc/self/fd/{}", start_fifo.as_raw_fd()))
+            .context("Failed to open the start FIFO")?;
+        fifo.write_all(b"0")
+            .context("Failed to wait for rocker start")?;
         Ok(())
     }
 
+    /// Switch the root filesystem to `new_root`.
+    ///
+    /// The old root stays mounted at `/.pivot_root` so that host paths
+    /// can still be reached; it must be detached with
+    /// [`Container::umount_old_root`] once the container root is set up.
     fn pivot_root(new_root: &std::path::Path) -> Result<()> {
-        mount(
-            None::<&str>,
-            "/",
-            None::<&str>,
-            MsFlags::MS_PRIVATE | MsFlags::MS_REC,
-            None::<&str>,
-        )
-        .expect("mount / as MS_PRIVATE");
-
         mount(
             Some(new_root.as_os_str().to_str().unwrap()),
             new_root.as_os_str().to_str().unwrap(),
@@ -110,6 +246,8 @@ impl Container {
 
         let old_root = new_root.join(".pivot_root");
         trace!("old root path in the container is {:?}", old_root);
+        std::fs::create_dir_all(&old_root)
+            .context("Failed to create old root directory")?;
 
         pivot_root(
             new_root.as_os_str().to_str().unwrap(),
@@ -118,14 +256,40 @@ impl Container {
         .expect("pivot_root new root");
         chdir("/").expect("change root to /");
 
+        Ok(())
+    }
+
+    fn umount_old_root() {
         let old_root = PathBuf::from("/").join(".pivot_root");
         umount2(old_root.as_os_str().to_str().unwrap(), MntFlags::MNT_DETACH)
             .expect("umount old root with detach");
-
-        Ok(())
     }
 
-    fn setup_mount() {
+    fn setup_mount(config: &InitConfig) -> Result<()> {
+        // The UTS namespace is private to the container, but still shows
+        // the host's names until they are replaced
+        if !config.hostname.is_empty() {
+            hostname::set(&config.hostname, config.domainname.as_deref())?;
+        }
+
+        // Keep the container mounts, including the rootfs overlay, from
+        // propagating to the host
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+            None::<&str>,
+        )
+        .expect("mount / as MS_PRIVATE");
+
+        if let Some(overlay) = &config.rootfs {
+            overlay.mount()?;
+            chdir(&overlay.merged).with_context(|| {
+                format!("Failed to enter rootfs {}", overlay.merged.display())
+            })?;
+        }
+
         let pwd = std::env::current_dir();
         if pwd.is_err() {
             error!("Could not get current directory in the container");
@@ -138,43 +302,83 @@ impl Container {
         );
 
         let _ = Self::pivot_root(&pwd);
+        let old_root = Path::new("/.pivot_root");
 
         // After pivot_root, create mount points before mounting
         std::fs::create_dir_all("/proc").expect("create /proc directory");
-        std::fs::create_dir_all("/dev").expect("create /dev directory");
 
-        // mount proc file system for checking resources from ps command
+        // mount proc file system for checking resources from ps command.
+        // Inside a user namespace the kernel only allows this while the
+        // host /proc is still visible, i.e. before the old root is detached.
         let flags = MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
         mount(Some("proc"), "/proc", Some("proc"), flags, Some(""))
             .expect("mount proc to /proc");
 
-        mount(
-            Some("tmpfs"),
-            "/dev",
-            Some("tmpfs"),
-            MsFlags::MS_NOSUID | MsFlags::MS_STRICTATIME,
-            Some("mode=755"),
-        )
-        .expect("mount tmpfs to /dev");
+        system_paths::mount_sysfs(old_root)?;
+
+        // Rootless containers bind-mount the host device nodes
+        devices::setup(old_root)?;
+
+        // Host paths are only reachable through the old root, so volumes
+        // have to be mounted before it is detached
+        for volume in &config.volumes {
+            volume.mount(old_root)?;
+        }
+
+        for tmpfs in &config.tmpfs {
+            tmpfs.mount(old_root)?;
+        }
+
+        for path in &config.masked_paths {
+            system_paths::mask(Path::new(path))?;
+        }
+        for path in &config.readonly_paths {
+            system_paths::make_read_only(Path::new(path))?;
+        }
+
+        Self::umount_old_root();
+
+        // Last, so that mount points and the working directory could still
+        // be created in the rootfs
+        std::fs::create_dir_all(&config.cwd).with_context(|| {
+            format!("Failed to create working directory {}", config.cwd)
+        })?;
+        if config.read_only {
+            system_paths::remount_read_only(Path::new("/"))?;
+        }
+
+        Ok(())
     }
 
     /// create parent process ( init command container process)
+    ///
+    /// The init process blocks until it receives its configuration through
+    /// [`Container::send_init_config`].
+    ///
+    /// With a `console_socket` (see [`tty::console_socket`]) the container
+    /// allocates a terminal of its own and sends it back over the socket.
+    /// Until then, errors of the init process go to the inherited stderr.
+    /// Otherwise the container stdio are pipes.
+    ///
+    /// The process gets new `namespaces`, or joins those with a path (see
+    /// [`spec::DEFAULT_NAMESPACES`]).
+    ///
+    /// With `sync_setup`, which requires a start FIFO in the init
+    /// configuration, the process reports once the container is set up,
+    /// see [`Container::wait_setup`].
     pub fn create_parent_process(
-        tty: bool,
-        cmd: &str,
+        console_socket: Option<UnixStream>,
         rootfs_path: &std::path::Path,
+        namespaces: &[LinuxNamespace],
+        sync_setup: bool,
     ) -> Result<Child> {
-        let args = ["init", cmd];
-
-        let fd = pipe();
-        let read_pipe_fd = fd.unwrap().0;
-        let write_pipe_fd = fd.unwrap().1;
+        let args = ["init"];
 
         let mut stdin_cfg = Stdio::piped();
         let mut stdout_cfg = Stdio::piped();
         let mut stderr_cfg = Stdio::piped();
-        if tty {
-            stdin_cfg = Stdio::inherit();
+        if console_socket.is_some() {
+            stdin_cfg = Stdio::null();
             stdout_cfg = Stdio::inherit();
             stderr_cfg = Stdio::inherit();
         }
@@ -187,15 +391,6 @@ impl Container {
             ));
         }
 
-        let old_root = rootfs_path.join(".pivot_root");
-        if !old_root.exists() {
-            std::fs::create_dir_all(old_root.clone())
-                .expect("create old_root dir out of container");
-        }
-        old_root.metadata().unwrap().permissions().set_mode(0o777);
-
-        trace!("old root path out of the container is {:?}", old_root);
-
         let current_uid = get_current_uid();
         let current_gid = get_current_gid();
         trace!(
@@ -209,26 +404,68 @@ impl Container {
             .expect("Failed to set current directory");
 
         //   fork a new namespace-isolated process to call current rocker process self  from "/proc/self/exe"
-        // rocker init <cmd>
-        //
-        // Note: User namespace is excluded because it requires careful capability handling.
-        // The child process needs CAP_SYS_ADMIN for mount operations, which gets lost
-        // during user namespace unshare. For a working container, User namespace can be
-        // added later with proper capability management.
-        let handle = Command::new("/proc/self/exe")
+        // rocker init
+        let mut command = Command::new("/proc/self/exe");
+
+        // Without root, the container gets a user namespace in which the
+        // invoking user is root. The other namespaces are created in the
+        // same clone and owned by it, which gives rocker init the
+        // capabilities it needs to mount its rootfs, /proc and /dev.
+        let rootless = rootless::is_rootless();
+        if rootless {
+            rootless::enter_user_namespace(&mut command);
+        }
+
+        let mut unshared = Vec::new();
+        for namespace in namespaces {
+            let kind = match namespace.kind {
+                NamespaceType::Pid => Namespace::Pid,
+                NamespaceType::Network => Namespace::Net,
+                NamespaceType::Mount => Namespace::Mount,
+                NamespaceType::Ipc => Namespace::Ipc,
+                NamespaceType::Uts => Namespace::Uts,
+                NamespaceType::Cgroup => Namespace::Cgroup,
+                // Rootless containers always get one, with rocker's own
+                // id mappings
+                NamespaceType::User if rootless => continue,
+                NamespaceType::User => {
+                    return Err(anyhow::anyhow!(
+                        "User namespaces are only supported in rootless mode"
+                    ));
+                }
+                NamespaceType::Time => {
+                    return Err(anyhow::anyhow!(
+                        "Time namespaces are not supported"
+                    ));
+                }
+            };
+            match &namespace.path {
+                Some(path) => {
+                    let file = File::open(path).with_context(|| {
+                        format!("Failed to open namespace {}", path.display())
+                    })?;
+                    command.set_namespace(&file, kind).with_context(|| {
+                        format!("Failed to join namespace {}", path.display())
+                    })?;
+                }
+                None => unshared.push(kind),
+            }
+        }
+
+        if let Some(socket) = console_socket {
+            command.file_descriptor(CONSOLE_SOCKET_FD, Fd::from_file(socket));
+        }
+        if sync_setup {
+            command.file_descriptor(INIT_SYNC_FD, Fd::WritePipe);
+        }
+
+        let handle = command
             .args(&args)
             .stdin(stdin_cfg)
             .stdout(stdout_cfg)
             .stderr(stderr_cfg)
-            .unshare(&[
-                Namespace::Uts,
-                Namespace::Ipc,
-                Namespace::Pid,
-                Namespace::Mount,
-                Namespace::Net,
-            ])
-            .file_descriptor(read_pipe_fd, Fd::ReadPipe)
-            .file_descriptor(write_pipe_fd, Fd::WritePipe)
+            .unshare(&unshared)
+            .file_descriptor(INIT_PIPE_FD, Fd::ReadPipe)
             .spawn()
             .map_err(|e| {
                 anyhow::anyhow!("Failed to spawn container process: {}", e)
@@ -236,4 +473,41 @@ impl Container {
 
         Ok(handle)
     }
+
+    /// Wait until a process created by [`Container::create_parent_process`]
+    /// with `sync_setup` has set up the container: its namespaces, mounts
+    /// and rootfs. The command then waits for `rocker start`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process exits first, its error is in the
+    /// container log
+    pub fn wait_setup(child: &mut Child) -> Result<()> {
+        let mut reader = child
+            .take_pipe_reader(INIT_SYNC_FD)
+            .ok_or_else(|| anyhow::anyhow!("Setup pipe is already closed"))?;
+        let mut buf = [0u8; 1];
+        match reader.read(&mut buf) {
+            Ok(1) => Ok(()),
+            Ok(_) => Err(anyhow::anyhow!(
+                "Container init exited before the container was set up"
+            )),
+            Err(e) => Err(e).context("Failed to wait for the container setup"),
+        }
+    }
+
+    /// Send the init configuration to a process created by
+    /// [`Container::create_parent_process`], letting it start the container.
+    ///
+    /// The write end of the init pipe is closed afterwards, so this can
+    /// only be called once per process.
+    pub fn send_init_config(
+        child: &mut Child,
+        config: &InitConfig,
+    ) -> Result<()> {
+        let writer = child
+            .take_pipe_writer(INIT_PIPE_FD)
+            .ok_or_else(|| anyhow::anyhow!("Init pipe is already closed"))?;
+        config.write_to(writer)
+    }
 }
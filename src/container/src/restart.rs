@@ -0,0 +1,207 @@
+//! Restart policies.
+//!
+//! The monitor of a detached container restarts its command according to
+//! the policy given to `rocker run --restart`, in the same container: same
+//! ID, workspace, cgroup and log file. Restarts are delayed by an
+//! exponential backoff, see [`backoff`].
+//!
+//! `rocker stop` disables restarting. Docker only tells `always` and
+//! `unless-stopped` apart when its daemon restarts, and rocker has no
+//! daemon, so both restart a container until it is stopped.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// Delay before the first restart.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest delay between two restarts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a command has to run for the backoff to start over.
+pub const BACKOFF_RESET: Duration = Duration::from_secs(10);
+
+/// Restart policy of a container.
+///
+/// # Example
+///
+/// ```rust
+/// use container::restart::RestartPolicy;
+///
+/// let policy = RestartPolicy::parse("on-failure:3").unwrap();
+/// assert!(policy.should_restart(1, 2));
+/// assert!(!policy.should_restart(1, 3));
+/// assert!(!policy.should_restart(0, 0));
+/// assert_eq!(policy.to_string(), "on-failure:3");
+/// ```
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub enum RestartPolicy {
+    /// Never restart
+    #[default]
+    No,
+
+    /// Restart when the command exits with a non-zero code, at most
+    /// `max_retries` times if given
+    OnFailure { max_retries: Option<u32> },
+
+    /// Always restart
+    Always,
+
+    /// Always restart, unless the container was stopped
+    UnlessStopped,
+}
+
+impl RestartPolicy {
+    /// Parse a `no|on-failure[:max_retries]|always|unless-stopped` policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy is unknown, or if a maximum retry
+    /// count is not a number or is given to another policy than
+    /// `on-failure`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (name, max_retries) = match spec.split_once(':') {
+            Some((name, count)) => (name, Some(count)),
+            None => (spec, None),
+        };
+        match (name, max_retries) {
+            ("no", None) => Ok(RestartPolicy::No),
+            ("always", None) => Ok(RestartPolicy::Always),
+            ("unless-stopped", None) => Ok(RestartPolicy::UnlessStopped),
+            ("on-failure", None) => {
+                Ok(RestartPolicy::OnFailure { max_retries: None })
+            }
+            ("on-failure", Some(count)) => match count.parse() {
+                Ok(count) => Ok(RestartPolicy::OnFailure {
+                    max_retries: Some(count),
+                }),
+                Err(_) => Err(anyhow::anyhow!(
+                    "Invalid restart policy {}, maximum retry count {} is \
+                     not a number",
+                    spec,
+                    count
+                )),
+            },
+            _ => Err(anyhow::anyhow!(
+                "Invalid restart policy {}, expected no, \
+                 on-failure[:max-retries], always or unless-stopped",
+                spec
+            )),
+        }
+    }
+
+    /// Whether a command that exited with `exit_code` is restarted after
+    /// `restart_count` restarts.
+    ///
+    /// This doesn't know whether the container was stopped, which always
+    /// prevents a restart.
+    pub fn should_restart(&self, exit_code: i32, restart_count: u32) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure { max_retries } => {
+                exit_code != 0
+                    && max_retries.is_none_or(|max| restart_count < max)
+            }
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+        }
+    }
+}
+
+/// Delay before a restart, after `failures` consecutive restarts without
+/// the command running for [`BACKOFF_RESET`].
+///
+/// Like Docker, the delay starts at 100ms and doubles with each restart,
+/// up to one minute.
+pub fn backoff(failures: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(2u32.saturating_pow(failures))
+        .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartPolicy::No => write!(f, "no"),
+            RestartPolicy::OnFailure { max_retries: None } => {
+                write!(f, "on-failure")
+            }
+            RestartPolicy::OnFailure {
+                max_retries: Some(count),
+            } => write!(f, "on-failure:{}", count),
+            RestartPolicy::Always => write!(f, "always"),
+            RestartPolicy::UnlessStopped => write!(f, "unless-stopped"),
+        }
+    }
+}
+
+impl TryFrom<String> for RestartPolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: String) -> Result<Self> {
+        Self::parse(&spec)
+    }
+}
+
+impl From<RestartPolicy> for String {
+    fn from(policy: RestartPolicy) -> Self {
+        policy.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_restart_policy() {
+        for spec in [
+            "no",
+            "on-failure",
+            "on-failure:5",
+            "always",
+            "unless-stopped",
+        ] {
+            assert_eq!(RestartPolicy::parse(spec).unwrap().to_string(), spec);
+        }
+        assert!(RestartPolicy::parse("sometimes").is_err());
+        assert!(RestartPolicy::parse("on-failure:").is_err());
+        assert!(RestartPolicy::parse("on-failure:-1").is_err());
+        assert!(RestartPolicy::parse("always:3").is_err());
+    }
+
+    #[test]
+    fn test_should_restart() {
+        let on_failure = RestartPolicy::OnFailure { max_retries: None };
+        assert!(on_failure.should_restart(137, 100));
+        assert!(!on_failure.should_restart(0, 0));
+        assert!(RestartPolicy::Always.should_restart(0, 100));
+        assert!(RestartPolicy::UnlessStopped.should_restart(1, 0));
+        assert!(!RestartPolicy::No.should_restart(1, 0));
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), Duration::from_millis(100));
+        assert_eq!(backoff(3), Duration::from_millis(800));
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_restart_policy_serialization() {
+        let policy = RestartPolicy::OnFailure {
+            max_retries: Some(3),
+        };
+        let serialized = serde_json::to_string(&policy).unwrap();
+        assert_eq!(serialized, "\"on-failure:3\"");
+        assert_eq!(
+            serde_json::from_str::<RestartPolicy>(&serialized).unwrap(),
+            policy
+        );
+    }
+}
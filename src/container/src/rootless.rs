@@ -0,0 +1,276 @@
+//! Rootless containers.
+//!
+//! When rocker is run by an unprivileged user, the container gets a user
+//! namespace of its own. The invoking user is mapped to root inside the
+//! container, and the subordinate ids delegated to the user in
+//! `/etc/subuid` and `/etc/subgid` (if any) are mapped to ids 1 and up, so
+//! that images with several users keep working.
+//!
+//! Writing a mapping with more than the caller's own id requires the setuid
+//! `newuidmap` / `newgidmap` helpers from shadow-utils. Without subordinate
+//! ids only the user itself is mapped, which the kernel allows directly.
+//!
+//! Rootless state and images live under `$XDG_DATA_HOME/rocker` (by default
+//! `~/.local/share/rocker`) instead of `/var/run/rocker` and
+//! `/var/lib/rocker`.
+
+use anyhow::{Context, Result};
+use nix::unistd::{Gid, Uid, User};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use unshare::Namespace;
+
+/// Helper writing uid mappings with subordinate ids.
+pub const NEWUIDMAP: &str = "newuidmap";
+
+/// Helper writing gid mappings with subordinate ids.
+pub const NEWGIDMAP: &str = "newgidmap";
+
+pub use paths::{data_dir, is_rootless};
+
+/// A range of ids mapped into the container user namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdMap {
+    /// First id inside the container
+    pub container_id: u32,
+
+    /// First id on the host
+    pub host_id: u32,
+
+    /// Number of ids in the range
+    pub size: u32,
+}
+
+/// User and group mappings of a rootless container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdMappings {
+    pub uid_map: Vec<IdMap>,
+    pub gid_map: Vec<IdMap>,
+}
+
+impl IdMappings {
+    /// Mappings for the current user.
+    ///
+    /// The user and its primary group become root in the container,
+    /// followed by the first subordinate range of `/etc/subuid` and
+    /// `/etc/subgid`. Subordinate ranges are skipped if `newuidmap` or
+    /// `newgidmap` is not installed.
+    pub fn for_current_user() -> Self {
+        let uid = Uid::effective();
+        let gid = Gid::effective();
+        let name = User::from_uid(uid)
+            .ok()
+            .flatten()
+            .map(|user| user.name)
+            .unwrap_or_default();
+
+        let mut sub_uids =
+            read_subid_range(Path::new("/etc/subuid"), &name, uid);
+        let mut sub_gids =
+            read_subid_range(Path::new("/etc/subgid"), &name, uid);
+        if (sub_uids.is_some() || sub_gids.is_some())
+            && !(in_path(NEWUIDMAP) && in_path(NEWGIDMAP))
+        {
+            warn!(
+                "{} and {} are not installed, only mapping the current user",
+                NEWUIDMAP, NEWGIDMAP
+            );
+            sub_uids = None;
+            sub_gids = None;
+        }
+
+        IdMappings {
+            uid_map: Self::with_subordinate(uid.as_raw(), sub_uids),
+            gid_map: Self::with_subordinate(gid.as_raw(), sub_gids),
+        }
+    }
+
+    fn with_subordinate(id: u32, subordinate: Option<IdMap>) -> Vec<IdMap> {
+        let mut map = vec![IdMap {
+            container_id: 0,
+            host_id: id,
+            size: 1,
+        }];
+        map.extend(subordinate);
+        map
+    }
+
+    /// Whether the mappings only cover the current user and group.
+    fn is_single(&self) -> bool {
+        self.uid_map.len() == 1 && self.gid_map.len() == 1
+    }
+
+    /// Write the mappings of the user namespace of `pid`.
+    ///
+    /// This has to be done from outside the namespace, before the process
+    /// relies on its ids.
+    pub fn write(&self, pid: i32) -> Result<()> {
+        if self.is_single() {
+            // An unprivileged process may map its own ids, but only once
+            // setgroups(2) has been disabled in the namespace
+            fs::write(format!("/proc/{}/setgroups", pid), "deny")
+                .context("Failed to disable setgroups")?;
+            fs::write(
+                format!("/proc/{}/uid_map", pid),
+                format_map(&self.uid_map),
+            )
+            .context("Failed to write uid_map")?;
+            fs::write(
+                format!("/proc/{}/gid_map", pid),
+                format_map(&self.gid_map),
+            )
+            .context("Failed to write gid_map")?;
+            return Ok(());
+        }
+
+        run_map_helper(NEWUIDMAP, pid, &self.uid_map)?;
+        run_map_helper(NEWGIDMAP, pid, &self.gid_map)?;
+        Ok(())
+    }
+}
+
+/// Run `cmd` in a new user namespace with the current user's mappings.
+///
+/// The mappings are written before the command is executed, so it starts
+/// as root of the new namespace. Other namespaces unshared by `cmd` are
+/// owned by the new user namespace.
+pub fn enter_user_namespace(cmd: &mut unshare::Command) {
+    let mappings = IdMappings::for_current_user();
+    trace!("rootless id mappings {:?}", mappings);
+
+    cmd.unshare(&[Namespace::User])
+        .before_unfreeze(move |pid| Ok(mappings.write(pid as i32)?));
+}
+
+/// Remove a directory tree written by a container.
+///
+/// For rootless containers the tree may contain files owned by
+/// subordinate ids, which the user can only remove from inside a user
+/// namespace where these ids are mapped.
+pub fn remove_dir_all(path: &Path) -> Result<()> {
+    let error = match fs::remove_dir_all(path) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    if !is_rootless() || error.kind() != std::io::ErrorKind::PermissionDenied {
+        return Err(error).with_context(|| {
+            format!("Failed to remove directory {}", path.display())
+        });
+    }
+
+    debug!("removing {} in a user namespace", path.display());
+    let mut cmd = unshare::Command::new("/bin/rm");
+    cmd.arg("-rf").arg(path);
+    enter_user_namespace(&mut cmd);
+    let status = cmd
+        .status()
+        .map_err(|e| anyhow::anyhow!("Failed to run rm: {}", e))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to remove directory {}: rm {}",
+            path.display(),
+            status
+        ));
+    }
+    Ok(())
+}
+
+fn format_map(map: &[IdMap]) -> String {
+    map.iter()
+        .map(|m| format!("{} {} {}\n", m.container_id, m.host_id, m.size))
+        .collect()
+}
+
+fn run_map_helper(helper: &str, pid: i32, map: &[IdMap]) -> Result<()> {
+    let mut cmd = Command::new(helper);
+    cmd.arg(pid.to_string());
+    for m in map {
+        cmd.args([
+            m.container_id.to_string(),
+            m.host_id.to_string(),
+            m.size.to_string(),
+        ]);
+    }
+
+    let status = cmd
+        .status()
+        .with_context(|| format!("Failed to run {}", helper))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("{} failed with {}", helper, status));
+    }
+    Ok(())
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|path| {
+            std::env::split_paths(&path).any(|dir| dir.join(program).exists())
+        })
+        .unwrap_or(false)
+}
+
+/// Find the first subordinate range of a user in a subuid/subgid file.
+///
+/// Entries are `name:start:count`, where `name` may also be the numeric uid.
+fn read_subid_range(path: &Path, name: &str, uid: Uid) -> Option<IdMap> {
+    let content = fs::read_to_string(path).ok()?;
+    parse_subid_range(&content, name, uid)
+}
+
+fn parse_subid_range(content: &str, name: &str, uid: Uid) -> Option<IdMap> {
+    let uid = uid.to_string();
+    content.lines().find_map(|line| {
+        let mut fields = line.trim().split(':');
+        let owner = fields.next()?;
+        if owner != name && owner != uid {
+            return None;
+        }
+        let start = fields.next()?.parse().ok()?;
+        let size = fields.next()?.parse().ok()?;
+        Some(IdMap {
+            container_id: 1,
+            host_id: start,
+            size,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subid_range() {
+        let content = "# comment\nalice:100000:65536\n1001:200000:1000\n";
+        let uid = Uid::from_raw(1000);
+
+        assert_eq!(
+            parse_subid_range(content, "alice", uid),
+            Some(IdMap {
+                container_id: 1,
+                host_id: 100000,
+                size: 65536,
+            })
+        );
+        assert_eq!(
+            parse_subid_range(content, "bob", Uid::from_raw(1001))
+                .map(|m| m.host_id),
+            Some(200000)
+        );
+        assert_eq!(parse_subid_range(content, "carol", uid), None);
+    }
+
+    #[test]
+    fn test_format_map() {
+        let map = IdMappings::with_subordinate(
+            1000,
+            Some(IdMap {
+                container_id: 1,
+                host_id: 100000,
+                size: 65536,
+            }),
+        );
+        assert_eq!(format_map(&map), "0 1000 1\n1 100000 65536\n");
+    }
+}
@@ -0,0 +1,498 @@
+//! Compile seccomp profiles into classic BPF programs.
+//!
+//! The generated program checks the architecture, loads the syscall number
+//! and then tests the rules one syscall at a time:
+//!
+//! ```text
+//!     ld  [arch]
+//!     jeq #AUDIT_ARCH, 1, 0
+//!     ret #KILL_PROCESS
+//!     ld  [nr]
+//!     jeq #nr_1, 0, <skip>     ; one block per rule and syscall
+//!     <argument checks>        ; jump to <skip> on mismatch
+//!     ret #action_1
+//! <skip>:
+//!     ld  [nr]                 ; only if the argument checks clobbered A
+//!     ...
+//!     ret #default_action
+//! ```
+//!
+//! Arguments are 64 bit while BPF registers are 32 bit, so every argument
+//! comparison is done on the high word first and then on the low word.
+
+use super::profile::{Action, ArgCondition, ArgOp, RuleFilter, SeccompProfile};
+use super::syscalls;
+use anyhow::Result;
+use libc::{
+    BPF_ABS, BPF_ALU, BPF_AND, BPF_JEQ, BPF_JGE, BPF_JGT, BPF_JMP, BPF_K,
+    BPF_LD, BPF_RET, BPF_W, sock_filter,
+};
+
+/// Offsets into `struct seccomp_data`.
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+const ARGS_OFFSET: u32 = 16;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e; // AUDIT_ARCH_X86_64
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7; // AUDIT_ARCH_AARCH64
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: u32 = 0;
+
+/// Docker names of the build architecture, used by rule filters.
+#[cfg(target_arch = "x86_64")]
+const ARCH_NAMES: &[&str] = &["amd64", "x86_64"];
+#[cfg(target_arch = "aarch64")]
+const ARCH_NAMES: &[&str] = &["arm64", "aarch64"];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const ARCH_NAMES: &[&str] = &[];
+
+/// Syscall number bit of the x32 ABI, which shares AUDIT_ARCH_X86_64.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Maximum number of instructions accepted by the kernel.
+const MAX_INSNS: usize = libc::BPF_MAXINSNS as usize;
+
+/// Jump target of an instruction inside a rule block.
+#[derive(Clone, Copy)]
+enum Target {
+    /// Relative offset within the block
+    Rel(u8),
+    /// The end of the block, i.e. the rule doesn't match
+    Fail,
+}
+
+use Target::{Fail, Rel};
+
+/// Instruction of a rule block with unresolved jumps.
+struct Insn {
+    code: u32,
+    k: u32,
+    jt: Target,
+    jf: Target,
+}
+
+fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// Compile `profile` for the build architecture.
+///
+/// Syscalls unknown on this architecture and rules whose `includes` /
+/// `excludes` filters don't match are skipped.
+///
+/// # Arguments
+///
+/// * `capabilities` - Capabilities of the container, used for the `caps`
+///   rule filters. `None` means the container keeps all capabilities.
+///
+/// # Errors
+///
+/// Returns an error if the architecture is not supported, an argument index
+/// is out of range or the program exceeds the kernel size limit
+pub fn compile(
+    profile: &SeccompProfile,
+    capabilities: Option<&[String]>,
+) -> Result<Vec<sock_filter>> {
+    if cfg!(not(any(target_arch = "x86_64", target_arch = "aarch64"))) {
+        return Err(anyhow::anyhow!(
+            "seccomp is not supported on this architecture"
+        ));
+    }
+
+    let mut program = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(BPF_LD | BPF_W | BPF_ABS, NR_OFFSET),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    program.extend([
+        jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+
+    for rule in &profile.syscalls {
+        if !rule_applies(&rule.includes, &rule.excludes, capabilities) {
+            continue;
+        }
+        let ret = action_value(rule.action, rule.errno_ret);
+
+        for name in rule.syscall_names() {
+            let Some(nr) = syscalls::resolve(name) else {
+                debug!("seccomp: skipping unknown syscall {}", name);
+                continue;
+            };
+            program.extend(compile_block(nr as u32, &rule.args, ret)?);
+        }
+    }
+
+    program.push(stmt(
+        BPF_RET | BPF_K,
+        action_value(profile.default_action, profile.default_errno_ret),
+    ));
+
+    if program.len() > MAX_INSNS {
+        return Err(anyhow::anyhow!(
+            "seccomp profile is too large ({} instructions, at most {})",
+            program.len(),
+            MAX_INSNS
+        ));
+    }
+    Ok(program)
+}
+
+fn rule_applies(
+    includes: &RuleFilter,
+    excludes: &RuleFilter,
+    capabilities: Option<&[String]>,
+) -> bool {
+    let has_cap = |cap: &String| match capabilities {
+        Some(caps) => caps.iter().any(|c| c.eq_ignore_ascii_case(cap)),
+        None => true,
+    };
+    let is_arch = |arch: &String| ARCH_NAMES.contains(&arch.as_str());
+
+    (includes.arches.is_empty() || includes.arches.iter().any(is_arch))
+        && includes.caps.iter().all(has_cap)
+        && !excludes.arches.iter().any(is_arch)
+        && !excludes.caps.iter().any(has_cap)
+}
+
+fn action_value(action: Action, errno: Option<u32>) -> u32 {
+    let data = errno.unwrap_or(libc::EPERM as u32) & libc::SECCOMP_RET_DATA;
+    match action {
+        Action::Allow => libc::SECCOMP_RET_ALLOW,
+        Action::Errno => libc::SECCOMP_RET_ERRNO | data,
+        Action::Kill => libc::SECCOMP_RET_KILL_THREAD,
+        Action::KillProcess => libc::SECCOMP_RET_KILL_PROCESS,
+        Action::Trap => libc::SECCOMP_RET_TRAP,
+        Action::Trace => libc::SECCOMP_RET_TRACE | data,
+        Action::Log => libc::SECCOMP_RET_LOG,
+    }
+}
+
+/// Compile the block matching one syscall of a rule.
+fn compile_block(
+    nr: u32,
+    args: &[ArgCondition],
+    ret: u32,
+) -> Result<Vec<sock_filter>> {
+    let mut block = Vec::new();
+    for arg in args {
+        block.extend(compile_condition(arg)?);
+    }
+    block.push(Insn {
+        code: BPF_RET | BPF_K,
+        k: ret,
+        jt: Rel(0),
+        jf: Rel(0),
+    });
+
+    let len = block.len();
+    if len > u8::MAX as usize {
+        return Err(anyhow::anyhow!("seccomp rule has too many conditions"));
+    }
+
+    let mut program = vec![jump(BPF_JMP | BPF_JEQ | BPF_K, nr, 0, len as u8)];
+    for (i, insn) in block.into_iter().enumerate() {
+        // Fail jumps to the first instruction after the block
+        let resolve = |target| match target {
+            Rel(offset) => offset,
+            Fail => (len - i - 1) as u8,
+        };
+        program.push(sock_filter {
+            code: insn.code as u16,
+            jt: resolve(insn.jt),
+            jf: resolve(insn.jf),
+            k: insn.k,
+        });
+    }
+
+    // The argument checks load the arguments into A
+    if !args.is_empty() {
+        program.push(stmt(BPF_LD | BPF_W | BPF_ABS, NR_OFFSET));
+    }
+    Ok(program)
+}
+
+/// Compile one argument condition. Falling through the generated code means
+/// the condition holds.
+fn compile_condition(arg: &ArgCondition) -> Result<Vec<Insn>> {
+    if arg.index > 5 {
+        return Err(anyhow::anyhow!(
+            "Invalid seccomp argument index {}",
+            arg.index
+        ));
+    }
+
+    let (lo_offset, hi_offset) = arg_offsets(arg.index);
+    let load = |offset| Insn {
+        code: BPF_LD | BPF_W | BPF_ABS,
+        k: offset,
+        jt: Rel(0),
+        jf: Rel(0),
+    };
+    let cmp = |op, k, jt, jf| Insn {
+        code: BPF_JMP | op | BPF_K,
+        k,
+        jt,
+        jf,
+    };
+    let and = |k| Insn {
+        code: BPF_ALU | BPF_AND | BPF_K,
+        k,
+        jt: Rel(0),
+        jf: Rel(0),
+    };
+
+    let hi = |value: u64| (value >> 32) as u32;
+    let lo = |value: u64| value as u32;
+    let value = arg.value;
+
+    let insns = match arg.op {
+        ArgOp::Equal => vec![
+            load(hi_offset),
+            cmp(BPF_JEQ, hi(value), Rel(0), Fail),
+            load(lo_offset),
+            cmp(BPF_JEQ, lo(value), Rel(0), Fail),
+        ],
+        ArgOp::NotEqual => vec![
+            load(hi_offset),
+            cmp(BPF_JEQ, hi(value), Rel(0), Rel(2)),
+            load(lo_offset),
+            cmp(BPF_JEQ, lo(value), Fail, Rel(0)),
+        ],
+        ArgOp::MaskedEq => vec![
+            load(hi_offset),
+            and(hi(value)),
+            cmp(BPF_JEQ, hi(arg.value_two), Rel(0), Fail),
+            load(lo_offset),
+            and(lo(value)),
+            cmp(BPF_JEQ, lo(arg.value_two), Rel(0), Fail),
+        ],
+        ArgOp::GreaterThan | ArgOp::GreaterOrEqual => vec![
+            load(hi_offset),
+            cmp(BPF_JGT, hi(value), Rel(3), Rel(0)),
+            cmp(BPF_JEQ, hi(value), Rel(0), Fail),
+            load(lo_offset),
+            cmp(
+                if arg.op == ArgOp::GreaterThan {
+                    BPF_JGT
+                } else {
+                    BPF_JGE
+                },
+                lo(value),
+                Rel(0),
+                Fail,
+            ),
+        ],
+        ArgOp::LessThan | ArgOp::LessOrEqual => vec![
+            load(hi_offset),
+            cmp(BPF_JGT, hi(value), Fail, Rel(0)),
+            cmp(BPF_JEQ, hi(value), Rel(0), Rel(2)),
+            load(lo_offset),
+            cmp(
+                if arg.op == ArgOp::LessThan {
+                    BPF_JGE
+                } else {
+                    BPF_JGT
+                },
+                lo(value),
+                Fail,
+                Rel(0),
+            ),
+        ],
+    };
+    Ok(insns)
+}
+
+/// Offsets of the low and high word of a syscall argument.
+fn arg_offsets(index: u32) -> (u32, u32) {
+    let offset = ARGS_OFFSET + index * 8;
+    if cfg!(target_endian = "little") {
+        (offset, offset + 4)
+    } else {
+        (offset + 4, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seccomp::profile::SyscallRule;
+
+    /// Minimal classic BPF interpreter for the instructions generated above.
+    fn run(program: &[sock_filter], nr: i64, args: [u64; 6]) -> u32 {
+        let mut data = Vec::new();
+        data.extend((nr as u32).to_ne_bytes());
+        data.extend(AUDIT_ARCH.to_ne_bytes());
+        data.extend(0u64.to_ne_bytes());
+        for arg in args {
+            data.extend(arg.to_ne_bytes());
+        }
+
+        let mut a: u32 = 0;
+        let mut pc = 0;
+        loop {
+            let insn = &program[pc];
+            let code = insn.code as u32;
+            pc += 1;
+            match code {
+                c if c == BPF_LD | BPF_W | BPF_ABS => {
+                    let k = insn.k as usize;
+                    a = u32::from_ne_bytes(data[k..k + 4].try_into().unwrap());
+                }
+                c if c == BPF_ALU | BPF_AND | BPF_K => a &= insn.k,
+                c if c == BPF_RET | BPF_K => return insn.k,
+                c => {
+                    let taken = match c & !(BPF_JMP | BPF_K) {
+                        op if op == BPF_JEQ => a == insn.k,
+                        op if op == BPF_JGT => a > insn.k,
+                        op if op == BPF_JGE => a >= insn.k,
+                        _ => panic!("unexpected instruction {:#x}", c),
+                    };
+                    pc += if taken { insn.jt } else { insn.jf } as usize;
+                }
+            }
+        }
+    }
+
+    const ERRNO_EPERM: u32 = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    #[test]
+    fn test_default_profile() {
+        let mut caps: Vec<String> = crate::capabilities::DEFAULT_CAPABILITIES
+            .iter()
+            .map(|cap| cap.to_string())
+            .collect();
+        let program = compile(&SeccompProfile::default(), Some(&caps)).unwrap();
+        let allow = libc::SECCOMP_RET_ALLOW;
+
+        assert_eq!(run(&program, libc::SYS_read, [0; 6]), allow);
+        assert_eq!(run(&program, libc::SYS_mount, [0; 6]), ERRNO_EPERM);
+        assert_eq!(run(&program, libc::SYS_ptrace, [0; 6]), ERRNO_EPERM);
+        assert_eq!(run(&program, libc::SYS_kexec_load, [0; 6]), ERRNO_EPERM);
+
+        let fork_flags = libc::SIGCHLD as u64;
+        let ns_flags = (libc::CLONE_NEWUSER | libc::SIGCHLD) as u64;
+        assert_eq!(
+            run(&program, libc::SYS_clone, [fork_flags, 0, 0, 0, 0, 0]),
+            allow
+        );
+        assert_eq!(
+            run(&program, libc::SYS_clone, [ns_flags, 0, 0, 0, 0, 0]),
+            ERRNO_EPERM
+        );
+        assert_eq!(
+            run(&program, libc::SYS_clone3, [0; 6]),
+            libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32
+        );
+
+        // --cap-add SYS_ADMIN allows mount and namespaces, but not the
+        // syscalls guarded by other capabilities
+        caps.push("CAP_SYS_ADMIN".to_string());
+        let program = compile(&SeccompProfile::default(), Some(&caps)).unwrap();
+        assert_eq!(run(&program, libc::SYS_mount, [0; 6]), allow);
+        assert_eq!(run(&program, libc::SYS_unshare, [0; 6]), allow);
+        assert_eq!(
+            run(&program, libc::SYS_clone, [ns_flags, 0, 0, 0, 0, 0]),
+            allow
+        );
+        assert_eq!(run(&program, libc::SYS_ptrace, [0; 6]), ERRNO_EPERM);
+        assert_eq!(run(&program, libc::SYS_kexec_load, [0; 6]), ERRNO_EPERM);
+    }
+
+    #[test]
+    fn test_arg_comparisons() {
+        let big = 0x1_0000_0005u64;
+        let cases = [
+            (ArgOp::Equal, [(big, true), (5, false), (big + 1, false)]),
+            (ArgOp::NotEqual, [(big, false), (5, true), (big + 1, true)]),
+            (
+                ArgOp::GreaterThan,
+                [(big, false), (5, false), (big + 1, true)],
+            ),
+            (
+                ArgOp::GreaterOrEqual,
+                [(big, true), (6, false), (big + 1, true)],
+            ),
+            (ArgOp::LessThan, [(big, false), (6, true), (big + 1, false)]),
+            (
+                ArgOp::LessOrEqual,
+                [(big, true), (6, true), (big + 1, false)],
+            ),
+        ];
+
+        for (op, checks) in cases {
+            let profile = SeccompProfile {
+                default_action: Action::Allow,
+                default_errno_ret: None,
+                syscalls: vec![SyscallRule {
+                    args: vec![ArgCondition {
+                        index: 2,
+                        value: big,
+                        value_two: 0,
+                        op,
+                    }],
+                    ..SyscallRule::new(&["write"], Action::Errno)
+                }],
+            };
+            let program = compile(&profile, None).unwrap();
+            for (arg, denied) in checks {
+                let result =
+                    run(&program, libc::SYS_write, [0, 0, arg, 0, 0, 0]);
+                assert_eq!(
+                    result == ERRNO_EPERM,
+                    denied,
+                    "{:?} {:#x}",
+                    op,
+                    arg
+                );
+            }
+            // Other syscalls are not affected by the argument checks
+            assert_eq!(
+                run(&program, libc::SYS_read, [0, 0, big, 0, 0, 0]),
+                libc::SECCOMP_RET_ALLOW
+            );
+        }
+    }
+
+    #[test]
+    fn test_rule_filters() {
+        let mut rule = SyscallRule::new(&["mount"], Action::Allow);
+        rule.includes.caps = vec!["CAP_SYS_ADMIN".to_string()];
+        let profile = SeccompProfile {
+            default_action: Action::Errno,
+            default_errno_ret: None,
+            syscalls: vec![rule],
+        };
+
+        let caps = vec!["CAP_CHOWN".to_string()];
+        let program = compile(&profile, Some(&caps)).unwrap();
+        assert_eq!(run(&program, libc::SYS_mount, [0; 6]), ERRNO_EPERM);
+
+        let program = compile(&profile, None).unwrap();
+        assert_eq!(
+            run(&program, libc::SYS_mount, [0; 6]),
+            libc::SECCOMP_RET_ALLOW
+        );
+    }
+}
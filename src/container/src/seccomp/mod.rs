@@ -0,0 +1,99 @@
+//! Seccomp syscall filtering.
+//!
+//! The container command runs with a seccomp-BPF filter that restricts the
+//! syscalls it may use. By default rocker installs a built-in profile
+//! denying syscalls that affect the host or could be used to escape the
+//! container (see [`SeccompProfile::default`]). Docker compatible JSON
+//! profiles can be used instead with `--security-opt seccomp=profile.json`,
+//! and `--security-opt seccomp=unconfined` disables filtering.
+//!
+//! The filter is installed by `rocker init` as the last step before
+//! `execve`, after the mounts that the filter would otherwise deny.
+
+mod bpf;
+pub mod profile;
+mod syscalls;
+
+pub use profile::SeccompProfile;
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+impl SeccompProfile {
+    /// Load a Docker compatible JSON profile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or is not a valid profile
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).with_context(|| {
+            format!("Failed to read seccomp profile {}", path.display())
+        })?;
+        serde_json::from_str(&json).with_context(|| {
+            format!("Failed to parse seccomp profile {}", path.display())
+        })
+    }
+}
+
+/// Seccomp filter compiled from a [`SeccompProfile`].
+///
+/// # Example
+///
+/// ```rust
+/// use container::seccomp::{SeccompFilter, SeccompProfile};
+///
+/// let filter = SeccompFilter::compile(&SeccompProfile::default(), None);
+/// assert!(filter.is_ok());
+/// ```
+pub struct SeccompFilter {
+    program: Vec<libc::sock_filter>,
+}
+
+impl SeccompFilter {
+    /// Compile `profile` for the build architecture.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - The seccomp profile
+    /// * `capabilities` - Capabilities of the container, used by rules
+    ///   that only apply with (or without) certain capabilities. `None`
+    ///   means the container keeps all capabilities.
+    pub fn compile(
+        profile: &SeccompProfile,
+        capabilities: Option<&[String]>,
+    ) -> Result<Self> {
+        let program = bpf::compile(profile, capabilities)?;
+        trace!("seccomp filter has {} instructions", program.len());
+        Ok(SeccompFilter { program })
+    }
+
+    /// Install the filter on the calling thread.
+    ///
+    /// The filter is inherited across `fork` and `execve` and can't be
+    /// removed. Installing it requires `CAP_SYS_ADMIN` in the current user
+    /// namespace or `no_new_privs`.
+    ///
+    /// This only performs a `prctl` call, so it can be used between `fork`
+    /// and `execve`.
+    pub fn install(&self) -> std::io::Result<()> {
+        let prog = libc::sock_fprog {
+            len: self.program.len() as libc::c_ushort,
+            filter: self.program.as_ptr() as *mut libc::sock_filter,
+        };
+
+        // SAFETY: prog points to a valid program that outlives the call,
+        // the kernel copies it.
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER as libc::c_ulong,
+                &prog as *const libc::sock_fprog,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
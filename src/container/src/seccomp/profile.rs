@@ -0,0 +1,280 @@
+//! Docker compatible seccomp profiles.
+//!
+//! Profiles use the JSON format of Docker and containerd
+//! (`defaultAction`, `syscalls[].names`, `args`, `includes`/`excludes`), so
+//! existing policies can be passed to `rocker run --security-opt
+//! seccomp=profile.json` unchanged. Fields rocker doesn't use, such as
+//! `archMap` or `comment`, are ignored.
+
+use serde::{Deserialize, Serialize};
+
+/// Seccomp profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompProfile {
+    /// Action for syscalls not matched by any rule
+    pub default_action: Action,
+
+    /// Errno returned by the default action, EPERM if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_errno_ret: Option<u32>,
+
+    /// Syscall rules, the first matching rule decides
+    #[serde(default)]
+    pub syscalls: Vec<SyscallRule>,
+}
+
+/// Action taken for a syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    #[serde(rename = "SCMP_ACT_ALLOW")]
+    Allow,
+    #[serde(rename = "SCMP_ACT_ERRNO")]
+    Errno,
+    #[serde(rename = "SCMP_ACT_KILL", alias = "SCMP_ACT_KILL_THREAD")]
+    Kill,
+    #[serde(rename = "SCMP_ACT_KILL_PROCESS")]
+    KillProcess,
+    #[serde(rename = "SCMP_ACT_TRAP")]
+    Trap,
+    #[serde(rename = "SCMP_ACT_TRACE")]
+    Trace,
+    #[serde(rename = "SCMP_ACT_LOG")]
+    Log,
+}
+
+/// Rule for a group of syscalls.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyscallRule {
+    /// Syscall names
+    #[serde(default)]
+    pub names: Vec<String>,
+
+    /// Single syscall name, used by old profiles instead of `names`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    pub action: Action,
+
+    /// Errno returned by [`Action::Errno`], EPERM if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno_ret: Option<u32>,
+
+    /// Conditions on the syscall arguments, all of which must hold
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<ArgCondition>,
+
+    /// The rule only applies if this filter matches
+    #[serde(default, skip_serializing_if = "RuleFilter::is_empty")]
+    pub includes: RuleFilter,
+
+    /// The rule doesn't apply if this filter matches
+    #[serde(default, skip_serializing_if = "RuleFilter::is_empty")]
+    pub excludes: RuleFilter,
+}
+
+/// Condition on a syscall argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArgCondition {
+    /// Argument index, 0 to 5
+    pub index: u32,
+
+    /// Value compared against, or the mask for [`ArgOp::MaskedEq`]
+    pub value: u64,
+
+    /// Value compared against the masked argument for [`ArgOp::MaskedEq`]
+    #[serde(default)]
+    pub value_two: u64,
+
+    pub op: ArgOp,
+}
+
+/// Comparison of an [`ArgCondition`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArgOp {
+    #[serde(rename = "SCMP_CMP_NE")]
+    NotEqual,
+    #[serde(rename = "SCMP_CMP_LT")]
+    LessThan,
+    #[serde(rename = "SCMP_CMP_LE")]
+    LessOrEqual,
+    #[serde(rename = "SCMP_CMP_EQ")]
+    Equal,
+    #[serde(rename = "SCMP_CMP_GE")]
+    GreaterOrEqual,
+    #[serde(rename = "SCMP_CMP_GT")]
+    GreaterThan,
+    #[serde(rename = "SCMP_CMP_MASKED_EQ")]
+    MaskedEq,
+}
+
+/// Architecture and capability filter of a [`SyscallRule`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleFilter {
+    /// Docker architecture names (e.g. `amd64`, `arm64`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arches: Vec<String>,
+
+    /// Capability names (e.g. `CAP_SYS_ADMIN`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub caps: Vec<String>,
+}
+
+impl RuleFilter {
+    pub fn is_empty(&self) -> bool {
+        self.arches.is_empty() && self.caps.is_empty()
+    }
+}
+
+/// Syscalls always denied by the default profile.
+///
+/// These either affect the whole host (swap, kexec, old or obsolete
+/// syscalls) or would let the container undo its own isolation (keyrings,
+/// userfaultfd).
+const DEFAULT_DENIED: &[&str] = &[
+    "_sysctl",
+    "add_key",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "nfsservctl",
+    "request_key",
+    "swapoff",
+    "swapon",
+    "sysfs",
+    "uselib",
+    "userfaultfd",
+    "ustat",
+    "vm86",
+    "vm86old",
+];
+
+/// Syscalls denied by the default profile unless the container has the
+/// capability they need, like Docker's default profile: they are useless
+/// without it, and only widen the kernel attack surface.
+const DEFAULT_DENIED_WITHOUT_CAP: &[(&str, &[&str])] = &[
+    (
+        "CAP_SYS_ADMIN",
+        &[
+            "bpf",
+            "fsconfig",
+            "fsmount",
+            "fsopen",
+            "fspick",
+            "lookup_dcookie",
+            "mount",
+            "mount_setattr",
+            "move_mount",
+            "name_to_handle_at",
+            "open_tree",
+            "perf_event_open",
+            "pivot_root",
+            "quotactl",
+            "quotactl_fd",
+            "setns",
+            "umount2",
+            "unshare",
+        ],
+    ),
+    ("CAP_SYS_BOOT", &["reboot"]),
+    ("CAP_DAC_READ_SEARCH", &["open_by_handle_at"]),
+    (
+        "CAP_SYS_MODULE",
+        &[
+            "create_module",
+            "delete_module",
+            "finit_module",
+            "get_kernel_syms",
+            "init_module",
+            "query_module",
+        ],
+    ),
+    ("CAP_SYS_PACCT", &["acct"]),
+    (
+        "CAP_SYS_PTRACE",
+        &["kcmp", "process_vm_readv", "process_vm_writev", "ptrace"],
+    ),
+    ("CAP_SYS_RAWIO", &["ioperm", "iopl"]),
+    (
+        "CAP_SYS_TIME",
+        &["clock_adjtime", "clock_settime", "settimeofday"],
+    ),
+];
+
+/// clone(2) flags creating new namespaces.
+const NAMESPACE_FLAGS: u64 = (libc::CLONE_NEWNS
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWCGROUP) as u64;
+
+impl Default for SeccompProfile {
+    /// The built-in profile used unless `--security-opt seccomp=...` is
+    /// given.
+    ///
+    /// Everything is allowed except the syscalls in the deny lists, which
+    /// fail with EPERM; those guarded by a capability are allowed when the
+    /// container has it (e.g. `mount` with `--cap-add SYS_ADMIN`). Without
+    /// `CAP_SYS_ADMIN`, clone(2) may not create namespaces. clone3(2)
+    /// always fails with ENOSYS since its flags can't be inspected, which
+    /// makes libc fall back to clone(2).
+    fn default() -> Self {
+        let mut syscalls =
+            vec![SyscallRule::new(DEFAULT_DENIED, Action::Errno)];
+        for (cap, names) in DEFAULT_DENIED_WITHOUT_CAP {
+            let mut rule = SyscallRule::new(names, Action::Errno);
+            rule.excludes.caps = vec![cap.to_string()];
+            syscalls.push(rule);
+        }
+        let mut clone_denied = SyscallRule::new(&["clone"], Action::Errno);
+        clone_denied.excludes.caps = vec!["CAP_SYS_ADMIN".to_string()];
+
+        syscalls.extend([
+            SyscallRule {
+                args: vec![ArgCondition {
+                    index: 0,
+                    value: NAMESPACE_FLAGS,
+                    value_two: 0,
+                    op: ArgOp::MaskedEq,
+                }],
+                ..SyscallRule::new(&["clone"], Action::Allow)
+            },
+            clone_denied,
+            SyscallRule {
+                errno_ret: Some(libc::ENOSYS as u32),
+                ..SyscallRule::new(&["clone3"], Action::Errno)
+            },
+        ]);
+
+        SeccompProfile {
+            default_action: Action::Allow,
+            default_errno_ret: None,
+            syscalls,
+        }
+    }
+}
+
+impl SyscallRule {
+    /// Create an unconditional rule.
+    pub fn new(names: &[&str], action: Action) -> Self {
+        SyscallRule {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            name: None,
+            action,
+            errno_ret: None,
+            args: Vec::new(),
+            includes: RuleFilter::default(),
+            excludes: RuleFilter::default(),
+        }
+    }
+
+    /// All syscall names of the rule.
+    pub fn syscall_names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().chain(&self.name).map(String::as_str)
+    }
+}
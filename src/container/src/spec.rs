@@ -0,0 +1,739 @@
+//! OCI runtime-spec bundles.
+//!
+//! A bundle is a directory holding a `config.json` in the format of the
+//! [OCI runtime specification](https://github.com/opencontainers/runtime-spec)
+//! and, usually, the root filesystem it refers to. `rocker spec` writes a
+//! template configuration and `rocker run --bundle` runs a container from
+//! one, so the same bundle can be handed to rocker and runc.
+//!
+//! Rocker reads the parts of the configuration it has a counterpart for:
+//!
+//! - `process`: args, env, cwd, terminal, uid and gid, and the bounding
+//!   capabilities, which become the capabilities kept by the command
+//! - `root`: path, relative to the bundle, and readonly
+//! - `hostname` and `domainname`
+//! - `mounts`: bind mounts and tmpfs filesystems
+//! - `linux`: namespaces, memory and CPU resources, masked and read-only
+//!   paths, and a seccomp profile
+//! - `hooks`: the lifecycle hooks run on the host (see [`crate::hooks`])
+//!
+//! Other fields are ignored. Like runc, a configuration without a seccomp
+//! profile runs unconfined. The root filesystem is used as the lower layer
+//! of the container workspace, so it is never written to.
+//!
+//! `rocker state` reports containers in the [`State`] format of the
+//! specification.
+
+use crate::capabilities;
+use crate::hooks::Hooks;
+use crate::info::{ContainerInfo, ContainerStatus};
+use crate::seccomp::SeccompProfile;
+use crate::system_paths;
+use crate::tmpfs::Tmpfs;
+use crate::volume::Volume;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file of a bundle.
+pub const CONFIG_FILE: &str = "config.json";
+
+/// Runtime-spec version of the generated configurations.
+pub const OCI_VERSION: &str = "1.0.2";
+
+/// Namespaces of a container unless configured otherwise.
+pub const DEFAULT_NAMESPACES: &[NamespaceType] = &[
+    NamespaceType::Pid,
+    NamespaceType::Network,
+    NamespaceType::Ipc,
+    NamespaceType::Uts,
+    NamespaceType::Mount,
+];
+
+/// Filesystems that `rocker init` always mounts itself, so mounts of the
+/// configuration on these paths are ignored.
+const MANAGED_MOUNTS: &[&str] = &[
+    "/proc",
+    "/sys",
+    "/sys/fs/cgroup",
+    "/dev",
+    "/dev/pts",
+    "/dev/shm",
+    "/dev/mqueue",
+];
+
+/// Options of tmpfs mounts that don't apply to rocker's tmpfs mounts: mount
+/// propagation, which rocker doesn't configure, and podman's `tmpcopyup`.
+const IGNORED_TMPFS_OPTIONS: &[&str] = &[
+    "private",
+    "rprivate",
+    "shared",
+    "rshared",
+    "slave",
+    "rslave",
+    "unbindable",
+    "runbindable",
+    "tmpcopyup",
+];
+
+/// Container configuration of a bundle.
+///
+/// # Example
+///
+/// ```rust
+/// use container::spec::Spec;
+///
+/// let spec = Spec::new(vec!["sh".to_string()], true);
+/// let json = serde_json::to_string(&spec).unwrap();
+/// let decoded: Spec = serde_json::from_str(&json).unwrap();
+/// assert_eq!(decoded.process.args, vec!["sh"]);
+/// assert!(decoded.process.terminal);
+/// assert_eq!(decoded.root.path.to_str(), Some("rootfs"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spec {
+    pub oci_version: String,
+
+    pub process: Process,
+
+    pub root: Root,
+
+    /// Hostname of the container, the container ID if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domainname: Option<String>,
+
+    /// Mounts, in the order they are mounted
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+
+    /// Host programs run at points of the container lifecycle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<Hooks>,
+
+    #[serde(default)]
+    pub linux: Linux,
+}
+
+/// Command of the container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Process {
+    /// Whether the command gets a terminal
+    #[serde(default)]
+    pub terminal: bool,
+
+    #[serde(default)]
+    pub user: ProcessUser,
+
+    /// Command and arguments
+    pub args: Vec<String>,
+
+    /// Environment in `KEY=VALUE` form, on top of the rocker defaults
+    #[serde(default)]
+    pub env: Vec<String>,
+
+    /// Absolute working directory
+    pub cwd: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<ProcessCapabilities>,
+}
+
+/// User of the command, given by ids.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessUser {
+    pub uid: u32,
+
+    pub gid: u32,
+
+    /// Ignored, the supplementary groups are looked up in the container's
+    /// `/etc/group`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_gids: Vec<u32>,
+}
+
+/// Capability sets of the command.
+///
+/// Rocker keeps a single set of capabilities, the bounding set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessCapabilities {
+    #[serde(default)]
+    pub bounding: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effective: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permitted: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inheritable: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ambient: Vec<String>,
+}
+
+/// Root filesystem of the container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Root {
+    /// Directory, relative to the bundle unless absolute
+    pub path: PathBuf,
+
+    /// Whether the root filesystem is mounted read-only
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// Mount of the container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mount {
+    /// Absolute mount point in the container
+    pub destination: PathBuf,
+
+    /// Filesystem type, e.g. `tmpfs` or `bind`
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
+    /// Device or directory, relative to the bundle unless absolute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+/// Linux specific configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Linux {
+    /// Namespaces created, or joined when they have a path
+    #[serde(default)]
+    pub namespaces: Vec<LinuxNamespace>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+
+    /// Paths hidden from the container, see [`crate::system_paths`]
+    #[serde(default)]
+    pub masked_paths: Vec<String>,
+
+    /// Paths the container may only read
+    #[serde(default)]
+    pub readonly_paths: Vec<String>,
+
+    /// Seccomp profile, the container is unconfined if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seccomp: Option<SeccompProfile>,
+}
+
+/// Namespace of the container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinuxNamespace {
+    #[serde(rename = "type")]
+    pub kind: NamespaceType,
+
+    /// Namespace to join, e.g. `/var/run/netns/web`, instead of creating
+    /// one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+/// Namespace types of the runtime specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamespaceType {
+    Pid,
+    Network,
+    Mount,
+    Ipc,
+    Uts,
+    User,
+    Cgroup,
+    Time,
+}
+
+/// Resource limits of the container.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resources {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryResources>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuResources>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryResources {
+    /// Memory limit in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuResources {
+    /// Relative CPU time weight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shares: Option<u64>,
+
+    /// CPU cores, e.g. `0-1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+}
+
+/// State of a container, as printed by `rocker state`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct State {
+    pub oci_version: String,
+
+    pub id: String,
+
+    /// `created`, `running`, `paused` or `stopped`
+    pub status: String,
+
+    /// PID of the container process, unless it is stopped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+
+    /// Absolute path of the bundle, the container directory for containers
+    /// run from an image
+    pub bundle: String,
+
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl State {
+    /// State of a container, described by its metadata.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use container::info::{ContainerInfo, ContainerStatus};
+    /// use container::spec::State;
+    /// # let mut info: ContainerInfo = serde_json::from_str(
+    /// #     r#"{"pid": "4422", "id": "web", "name": "web",
+    /// #         "command": "sh", "createTime": "2024-01-01 12:00:00",
+    /// #         "status": "created", "portmapping": [], "network": null,
+    /// #         "imageName": "/containers/web/rootfs",
+    /// #         "bundle": "/containers/web"}"#,
+    /// # ).unwrap();
+    ///
+    /// let state = State::of(&info);
+    /// assert_eq!(state.status, "created");
+    /// assert_eq!(state.pid, Some(4422));
+    /// assert_eq!(state.bundle, "/containers/web");
+    ///
+    /// info.status = ContainerStatus::Exited;
+    /// assert_eq!(State::of(&info).status, "stopped");
+    /// assert_eq!(State::of(&info).pid, None);
+    /// ```
+    pub fn of(info: &ContainerInfo) -> Self {
+        let status = match info.status {
+            ContainerStatus::Created => "created",
+            ContainerStatus::Running | ContainerStatus::Restarting => "running",
+            ContainerStatus::Paused => "paused",
+            ContainerStatus::Stopped | ContainerStatus::Exited => "stopped",
+        };
+        State {
+            oci_version: OCI_VERSION.to_string(),
+            id: info.id.clone(),
+            status: status.to_string(),
+            pid: if status == "stopped" {
+                None
+            } else {
+                info.pid.parse().ok()
+            },
+            bundle: info.bundle.clone().unwrap_or_else(|| {
+                crate::store::ContainerStore::container_dir(&info.name)
+                    .display()
+                    .to_string()
+            }),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    /// The same state with another status, e.g. for the hooks run while
+    /// the status changes. A stopped container has no PID.
+    pub fn with_status(mut self, status: &str) -> Self {
+        if status == "stopped" {
+            self.pid = None;
+        }
+        self.status = status.to_string();
+        self
+    }
+}
+
+impl Spec {
+    /// Template configuration running `args`, with the defaults of `rocker
+    /// run`.
+    ///
+    /// The root filesystem is the `rootfs` directory of the bundle. The
+    /// mounts runc expects are listed too, so that runc can run the bundle
+    /// as well.
+    pub fn new(args: Vec<String>, terminal: bool) -> Self {
+        let default_capabilities: Vec<String> =
+            capabilities::DEFAULT_CAPABILITIES
+                .iter()
+                .map(|name| name.to_string())
+                .collect();
+        let strings = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+        let mount = |destination: &str, kind: &str, options: &[&str]| Mount {
+            destination: PathBuf::from(destination),
+            kind: Some(kind.to_string()),
+            source: Some(PathBuf::from(kind)),
+            options: strings(options),
+        };
+
+        Spec {
+            oci_version: OCI_VERSION.to_string(),
+            process: Process {
+                terminal,
+                user: ProcessUser::default(),
+                args,
+                env: vec![
+                    format!("PATH={}", crate::env::DEFAULT_PATH),
+                    String::from("TERM=xterm"),
+                ],
+                cwd: String::from("/"),
+                capabilities: Some(ProcessCapabilities {
+                    bounding: default_capabilities.clone(),
+                    effective: default_capabilities.clone(),
+                    permitted: default_capabilities,
+                    ..Default::default()
+                }),
+            },
+            root: Root {
+                path: PathBuf::from("rootfs"),
+                readonly: false,
+            },
+            hostname: None,
+            domainname: None,
+            mounts: vec![
+                mount("/proc", "proc", &["nosuid", "noexec", "nodev"]),
+                mount("/dev", "tmpfs", &["nosuid", "strictatime", "mode=755"]),
+                mount(
+                    "/dev/pts",
+                    "devpts",
+                    &["nosuid", "noexec", "newinstance", "ptmxmode=0666"],
+                ),
+                mount(
+                    "/dev/shm",
+                    "shm",
+                    &["nosuid", "noexec", "nodev", "mode=1777"],
+                ),
+                mount("/dev/mqueue", "mqueue", &["nosuid", "noexec", "nodev"]),
+                mount("/sys", "sysfs", &["nosuid", "noexec", "nodev", "ro"]),
+            ],
+            hooks: None,
+            linux: Linux {
+                namespaces: DEFAULT_NAMESPACES
+                    .iter()
+                    .map(|&kind| LinuxNamespace { kind, path: None })
+                    .collect(),
+                resources: None,
+                masked_paths: strings(system_paths::DEFAULT_MASKED_PATHS),
+                readonly_paths: strings(system_paths::DEFAULT_READONLY_PATHS),
+                seccomp: None,
+            },
+        }
+    }
+
+    /// Load and validate the configuration of a bundle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The configuration can't be read or parsed
+    /// - Its version is not 1.x
+    /// - It has no command, or a relative working directory
+    /// - It has no mount namespace of its own, which `pivot_root` needs
+    pub fn load(bundle: &Path) -> Result<Self> {
+        let path = bundle.join(CONFIG_FILE);
+        let file = File::open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let spec: Spec = serde_json::from_reader(file)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        spec.validate()
+            .with_context(|| format!("Invalid bundle {}", bundle.display()))?;
+        Ok(spec)
+    }
+
+    /// Write the configuration into a bundle, which must not have one yet.
+    pub fn save(&self, bundle: &Path) -> Result<()> {
+        let path = bundle.join(CONFIG_FILE);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        serde_json::to_writer_pretty(&mut file, self)
+            .context("Failed to serialize bundle config")?;
+        writeln!(file)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.oci_version.starts_with("1.") {
+            return Err(anyhow::anyhow!(
+                "Unsupported OCI version {}, expected 1.x",
+                self.oci_version
+            ));
+        }
+        if self.process.args.is_empty() {
+            return Err(anyhow::anyhow!("No command specified"));
+        }
+        if !self.process.cwd.starts_with('/') {
+            return Err(anyhow::anyhow!(
+                "Working directory {} must be absolute",
+                self.process.cwd
+            ));
+        }
+        if !self.linux.namespaces.iter().any(|namespace| {
+            namespace.kind == NamespaceType::Mount && namespace.path.is_none()
+        }) {
+            return Err(anyhow::anyhow!(
+                "The container needs a mount namespace of its own"
+            ));
+        }
+        if let Some(hooks) = &self.hooks
+            && let Some(hook) = hooks
+                .create()
+                .chain(&hooks.poststart)
+                .chain(&hooks.poststop)
+                .find(|hook| !hook.path.is_absolute())
+        {
+            return Err(anyhow::anyhow!(
+                "Hook path {} must be absolute",
+                hook.path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Root filesystem directory.
+    pub fn rootfs(&self, bundle: &Path) -> PathBuf {
+        bundle.join(&self.root.path)
+    }
+
+    /// User of the command as a `uid:gid` specification, `None` for root.
+    pub fn user(&self) -> Option<String> {
+        let user = &self.process.user;
+        if user.uid == 0 && user.gid == 0 {
+            return None;
+        }
+        Some(format!("{}:{}", user.uid, user.gid))
+    }
+
+    /// Capabilities kept by the command, `None` to keep all of them when
+    /// the configuration doesn't restrict them.
+    pub fn capabilities(&self) -> Result<Option<Vec<String>>> {
+        self.process
+            .capabilities
+            .as_ref()
+            .map(|capabilities| {
+                capabilities
+                    .bounding
+                    .iter()
+                    .map(|name| capabilities::normalize(name))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    /// Bind mounts and tmpfs filesystems of the configuration.
+    ///
+    /// Mounts of the filesystems that `rocker init` sets up itself, such as
+    /// `/proc` or `/dev`, are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a mount has another type, has no source or a
+    /// relative destination, or if a tmpfs option is unknown
+    pub fn volumes_and_tmpfs(
+        &self,
+        bundle: &Path,
+    ) -> Result<(Vec<Volume>, Vec<Tmpfs>)> {
+        let mut volumes = Vec::new();
+        let mut tmpfs = Vec::new();
+        for mount in &self.mounts {
+            let destination = mount.destination.display().to_string();
+            if !destination.starts_with('/') {
+                return Err(anyhow::anyhow!(
+                    "Mount destination {} must be absolute",
+                    destination
+                ));
+            }
+            if MANAGED_MOUNTS.contains(&destination.as_str()) {
+                trace!("skipping mount {}, set up by rocker", destination);
+                continue;
+            }
+
+            let is_bind = mount.kind.as_deref() == Some("bind")
+                || mount
+                    .options
+                    .iter()
+                    .any(|option| option == "bind" || option == "rbind");
+            if is_bind {
+                let source = mount.source.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("Bind mount {} has no source", destination)
+                })?;
+                volumes.push(Volume {
+                    host_path: bundle.join(source),
+                    container_path: mount.destination.clone(),
+                    read_only: mount
+                        .options
+                        .iter()
+                        .any(|option| option == "ro"),
+                });
+            } else if mount.kind.as_deref() == Some("tmpfs") {
+                let options: Vec<&str> = mount
+                    .options
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|option| !IGNORED_TMPFS_OPTIONS.contains(option))
+                    .collect();
+                tmpfs.push(Tmpfs::parse(&format!(
+                    "{}:{}",
+                    destination,
+                    options.join(",")
+                ))?);
+            } else {
+                return Err(anyhow::anyhow!(
+                    "Unsupported mount {} of type {}, only bind and tmpfs \
+                     mounts are supported",
+                    destination,
+                    mount.kind.as_deref().unwrap_or("none")
+                ));
+            }
+        }
+        Ok((volumes, tmpfs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_runc_config() {
+        let json = r#"{
+            "ociVersion": "1.0.2-dev",
+            "process": {
+                "terminal": false,
+                "user": {"uid": 1000, "gid": 1000, "additionalGids": [10]},
+                "args": ["sleep", "10"],
+                "env": ["PATH=/bin"],
+                "cwd": "/srv",
+                "capabilities": {"bounding": ["CAP_KILL", "net_raw"]},
+                "rlimits": [{"type": "RLIMIT_NOFILE", "hard": 1024, "soft": 1024}],
+                "noNewPrivileges": true
+            },
+            "root": {"path": "rootfs", "readonly": true},
+            "hostname": "web",
+            "mounts": [
+                {"destination": "/proc", "type": "proc", "source": "proc"},
+                {"destination": "/data", "type": "bind", "source": "data",
+                 "options": ["rbind", "ro"]},
+                {"destination": "/run", "type": "tmpfs", "source": "tmpfs",
+                 "options": ["size=1m", "nodev"]},
+                {"destination": "/tmp", "type": "tmpfs", "source": "tmpfs",
+                 "options": ["nosuid", "strictatime", "mode=755", "rprivate",
+                             "tmpcopyup"]}
+            ],
+            "linux": {
+                "namespaces": [
+                    {"type": "mount"},
+                    {"type": "network", "path": "/var/run/netns/web"}
+                ],
+                "resources": {"memory": {"limit": 104857600}},
+                "maskedPaths": ["/proc/kcore"],
+                "readonlyPaths": []
+            }
+        }"#;
+        let spec: Spec = serde_json::from_str(json).unwrap();
+        spec.validate().unwrap();
+
+        let bundle = Path::new("/bundle");
+        assert_eq!(spec.rootfs(bundle), Path::new("/bundle/rootfs"));
+        assert_eq!(spec.user().as_deref(), Some("1000:1000"));
+        assert_eq!(
+            spec.capabilities().unwrap().unwrap(),
+            vec!["CAP_KILL", "CAP_NET_RAW"]
+        );
+        assert_eq!(spec.linux.namespaces[1].kind, NamespaceType::Network);
+
+        let (volumes, tmpfs) = spec.volumes_and_tmpfs(bundle).unwrap();
+        assert_eq!(
+            volumes,
+            vec![Volume::parse("/bundle/data:/data:ro").unwrap()]
+        );
+        assert_eq!(
+            tmpfs,
+            vec![
+                Tmpfs::parse("/run:size=1m,nodev").unwrap(),
+                Tmpfs::parse("/tmp:nosuid,strictatime,mode=755").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut spec = Spec::new(vec!["sh".to_string()], false);
+        spec.validate().unwrap();
+
+        spec.linux
+            .namespaces
+            .retain(|namespace| namespace.kind != NamespaceType::Mount);
+        assert!(spec.validate().is_err());
+
+        let mut spec = Spec::new(Vec::new(), false);
+        assert!(spec.validate().is_err());
+        spec.process.args = vec!["sh".to_string()];
+        spec.process.cwd = "srv".to_string();
+        assert!(spec.validate().is_err());
+        spec.process.cwd = "/srv".to_string();
+        spec.oci_version = "0.5.0".to_string();
+        assert!(spec.validate().is_err());
+
+        let mut spec = Spec::new(vec!["sh".to_string()], false);
+        spec.hooks = Some(
+            serde_json::from_str(r#"{"poststop": [{"path": "cleanup.sh"}]}"#)
+                .unwrap(),
+        );
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_unsupported_mount() {
+        let mut spec = Spec::new(vec!["sh".to_string()], false);
+        spec.mounts.push(Mount {
+            destination: PathBuf::from("/mnt"),
+            kind: Some("nfs".to_string()),
+            source: Some(PathBuf::from("server:/export")),
+            options: Vec::new(),
+        });
+        assert!(spec.volumes_and_tmpfs(Path::new("/bundle")).is_err());
+    }
+
+    #[test]
+    fn test_template_skips_managed_mounts() {
+        let spec = Spec::new(vec!["sh".to_string()], false);
+        let (volumes, tmpfs) =
+            spec.volumes_and_tmpfs(Path::new("/bundle")).unwrap();
+        assert!(volumes.is_empty());
+        assert!(tmpfs.is_empty());
+        assert_eq!(spec.user(), None);
+    }
+}
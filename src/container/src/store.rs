@@ -7,24 +7,45 @@
 //! ```text
 //! /var/run/rocker/{container_name}/
 //!   ├── config.json       # Container metadata
-//!   └── container.log     # Container logs (for non-TTY containers)
+//!   ├── container.log     # Container logs
+//!   ├── attach.sock       # Socket of the monitor for `rocker attach`
+//!   ├── upper/            # Container writable layer
+//!   ├── work/             # overlayfs work directory
+//!   └── merged/           # Container rootfs mount point
 //! ```
+//!
+//! Rootless containers are stored in `~/.local/share/rocker/containers/`
+//! instead (see [`crate::rootless::data_dir`]).
 
+use crate::health::{Health, HealthProbe};
 use crate::info::ContainerInfo;
+use crate::rootless;
 use anyhow::{Context, Result};
+use nix::fcntl::{FlockArg, flock};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// Default location for container metadata directories.
 /// Format string: `/var/run/rocker/%s/` where %s is the container name.
 pub const DEFAULT_INFO_LOCATION: &str = "/var/run/rocker/%s/";
 
+/// Directory holding the container directories when running as root.
+pub const DEFAULT_STATE_ROOT: &str = "/var/run/rocker";
+
 /// Config file name that stores container metadata.
 pub const CONFIG_NAME: &str = "config.json";
 
 /// Container log file name.
 pub const CONTAINER_LOG_FILE: &str = "container.log";
 
+/// Name of the socket `rocker attach` connects to.
+pub const ATTACH_SOCKET: &str = "attach.sock";
+
+/// Name of the FIFO a created container waits on until `rocker start`.
+pub const START_FIFO: &str = "start.fifo";
+
 /// Container metadata storage manager.
 ///
 /// This struct provides static methods for persisting and retrieving
@@ -35,6 +56,7 @@ pub const CONTAINER_LOG_FILE: &str = "container.log";
 /// ```rust
 /// use container::store::ContainerStore;
 /// use container::info::{ContainerInfo, ContainerStatus};
+/// use container::restart::RestartPolicy;
 ///
 /// let info = ContainerInfo {
 ///     pid: "12345".to_string(),
@@ -42,11 +64,27 @@ pub const CONTAINER_LOG_FILE: &str = "container.log";
 ///     name: "my_container".to_string(),
 ///     command: "/bin/sh".to_string(),
 ///     created_time: ContainerInfo::current_time(),
+///     started_time: None,
 ///     status: ContainerStatus::Running,
-///     volume: None,
+///     exit_code: None,
+///     signal: None,
+///     oom_killed: false,
+///     finished_time: None,
+///     restart_policy: RestartPolicy::No,
+///     restart_count: 0,
+///     healthcheck: None,
+///     health: None,
+///     tty: false,
+///     volumes: vec![],
+///     env: vec![],
+///     seccomp: None,
+///     capabilities: None,
+///     user: None,
+///     workdir: None,
 ///     port_mapping: vec![],
 ///     network: None,
 ///     image_name: "busybox".to_string(),
+///     bundle: None,
 /// };
 ///
 /// ContainerStore::save(&info).unwrap();
@@ -75,6 +113,7 @@ impl ContainerStore {
     /// ```rust
     /// use container::store::ContainerStore;
     /// use container::info::{ContainerInfo, ContainerStatus};
+    /// use container::restart::RestartPolicy;
     ///
     /// let info = ContainerInfo {
     ///     pid: "12345".to_string(),
@@ -82,11 +121,27 @@ impl ContainerStore {
     ///     name: "test".to_string(),
     ///     command: "/bin/sh".to_string(),
     ///     created_time: ContainerInfo::current_time(),
+    ///     started_time: None,
     ///     status: ContainerStatus::Running,
-    ///     volume: None,
+    ///     exit_code: None,
+    ///     signal: None,
+    ///     oom_killed: false,
+    ///     finished_time: None,
+    ///     restart_policy: RestartPolicy::No,
+    ///     restart_count: 0,
+    ///     healthcheck: None,
+    ///     health: None,
+    ///     tty: false,
+    ///     volumes: vec![],
+    ///     env: vec![],
+    ///     seccomp: None,
+    ///     capabilities: None,
+    ///     user: None,
+    ///     workdir: None,
     ///     port_mapping: vec![],
     ///     network: None,
     ///     image_name: "busybox".to_string(),
+    ///     bundle: None,
     /// };
     ///
     /// ContainerStore::save(&info).unwrap();
@@ -103,9 +158,24 @@ impl ContainerStore {
         let json = serde_json::to_string_pretty(info)
             .context("Failed to serialize container info")?;
 
-        fs::write(&config_path, json).with_context(|| {
-            format!("Failed to write config to {}", config_path.display())
+        // Written to a file of its own and renamed over the config, so that
+        // the monitor and the CLI never read a partly written config
+        static SAVES: AtomicUsize = AtomicUsize::new(0);
+        let tmp_path = dir_path.join(format!(
+            ".{}.{}-{}",
+            CONFIG_NAME,
+            std::process::id(),
+            SAVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp_path, json).with_context(|| {
+            format!("Failed to write config to {}", tmp_path.display())
         })?;
+        if let Err(e) = fs::rename(&tmp_path, &config_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e).with_context(|| {
+                format!("Failed to write config to {}", config_path.display())
+            });
+        }
 
         Ok(())
     }
@@ -152,7 +222,8 @@ impl ContainerStore {
         Ok(info)
     }
 
-    /// List all containers by reading `/var/run/rocker/` directories.
+    /// List all containers by reading the [`ContainerStore::root_dir`]
+    /// directories.
     ///
     /// This method scans the container metadata directory and loads
     /// all container configurations. It skips the "network" subdirectory
@@ -180,7 +251,7 @@ impl ContainerStore {
     /// }
     /// ```
     pub fn list_all() -> Result<Vec<ContainerInfo>> {
-        let base_dir = Path::new("/var/run/rocker");
+        let base_dir = Self::root_dir();
 
         if !base_dir.exists() {
             return Ok(Vec::new());
@@ -188,9 +259,9 @@ impl ContainerStore {
 
         let mut containers = Vec::new();
 
-        for entry in fs::read_dir(base_dir)
-            .context("Failed to read /var/run/rocker directory")?
-        {
+        for entry in fs::read_dir(&base_dir).with_context(|| {
+            format!("Failed to read {} directory", base_dir.display())
+        })? {
             let entry = entry?;
             let container_name = entry.file_name();
             let container_name = container_name
@@ -241,6 +312,8 @@ impl ContainerStore {
         let dir_path = Self::container_dir(container_name);
 
         if dir_path.exists() {
+            // Waits for updates in progress, later ones find no container
+            let _lock = Self::lock(container_name).ok();
             fs::remove_dir_all(&dir_path).with_context(|| {
                 format!("Failed to remove directory {}", dir_path.display())
             })?;
@@ -249,6 +322,66 @@ impl ContainerStore {
         Ok(())
     }
 
+    /// Load, change and save the info of a container while holding an
+    /// exclusive lock on its directory.
+    ///
+    /// The monitor and `rocker` commands update a container from different
+    /// processes, the lock keeps them from saving over each other's changes.
+    /// The info is only saved if `change` returns true, which is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container doesn't exist, can't be locked or
+    /// can't be saved
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use container::store::ContainerStore;
+    ///
+    /// ContainerStore::update("my_container", |info| {
+    ///     info.restart_count = 0;
+    ///     true
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn update(
+        container_name: &str,
+        change: impl FnOnce(&mut ContainerInfo) -> bool,
+    ) -> Result<bool> {
+        let _lock = Self::lock(container_name)?;
+        let mut info = Self::load(container_name)?;
+        if !change(&mut info) {
+            return Ok(false);
+        }
+        Self::save(&info)?;
+        Ok(true)
+    }
+
+    /// Take an exclusive lock on the directory of a container, held until
+    /// the returned file is dropped.
+    fn lock(container_name: &str) -> Result<fs::File> {
+        let dir_path = Self::container_dir(container_name);
+        let dir = match fs::File::open(&dir_path) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(anyhow::anyhow!(
+                    "Container {} not found",
+                    container_name
+                ));
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to open {}", dir_path.display())
+                });
+            }
+        };
+        flock(dir.as_raw_fd(), FlockArg::LockExclusive).with_context(|| {
+            format!("Failed to lock {}", dir_path.display())
+        })?;
+        Ok(dir)
+    }
+
     /// Update container status in config.json.
     ///
     /// This loads the existing container info, updates the status,
@@ -278,9 +411,152 @@ impl ContainerStore {
         container_name: &str,
         status: crate::info::ContainerStatus,
     ) -> Result<()> {
-        let mut info = Self::load(container_name)?;
-        info.status = status;
-        Self::save(&info)
+        Self::update(container_name, |info| {
+            info.status = status;
+            true
+        })
+        .map(|_| ())
+    }
+
+    /// Update the status of a container if it still has the status `from`.
+    ///
+    /// Returns whether the status was changed. Keeps e.g. a container that
+    /// exited meanwhile from being marked running again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container doesn't exist or can't be saved
+    pub fn change_status(
+        container_name: &str,
+        from: crate::info::ContainerStatus,
+        to: crate::info::ContainerStatus,
+    ) -> Result<bool> {
+        Self::update(container_name, |info| {
+            if info.status != from {
+                return false;
+            }
+            info.status = to;
+            true
+        })
+    }
+
+    /// Mark a container exited and record how its command ended.
+    ///
+    /// The PID is cleared since it may be reused by another process.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container to update
+    /// * `exit_code` - Exit code of the command, 128 plus the signal number
+    ///   if it was killed by a signal
+    /// * `signal` - Name of the signal that killed the command, if any
+    /// * `oom_killed` - Whether the OOM killer killed a process of the
+    ///   container
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container doesn't exist or can't be saved
+    pub fn record_exit(
+        container_name: &str,
+        exit_code: i32,
+        signal: Option<String>,
+        oom_killed: bool,
+    ) -> Result<()> {
+        Self::update(container_name, |info| {
+            info.status = crate::info::ContainerStatus::Exited;
+            info.pid = String::new();
+            info.exit_code = Some(exit_code);
+            info.signal = signal;
+            info.oom_killed = oom_killed;
+            info.finished_time = Some(ContainerInfo::current_time());
+            true
+        })
+        .map(|_| ())
+    }
+
+    /// Mark a created container running once `rocker start` released it.
+    ///
+    /// Does nothing if the container is no longer created, e.g. because its
+    /// command already exited.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container doesn't exist or can't be saved
+    pub fn record_start(container_name: &str) -> Result<()> {
+        Self::update(container_name, |info| {
+            if info.status != crate::info::ContainerStatus::Created {
+                return false;
+            }
+            info.status = crate::info::ContainerStatus::Running;
+            info.started_time = Some(ContainerInfo::current_time());
+            true
+        })
+        .map(|_| ())
+    }
+
+    /// Mark a restarted container running again.
+    ///
+    /// The exit of the previous run stays recorded until the next one, the
+    /// health state starts over. Returns whether the container was still
+    /// restarting, it isn't if `rocker stop` was called meanwhile.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container to update
+    /// * `pid` - PID of the new container process
+    /// * `restart_count` - Number of restarts so far
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container doesn't exist or can't be saved
+    pub fn record_restart(
+        container_name: &str,
+        pid: i32,
+        restart_count: u32,
+    ) -> Result<bool> {
+        Self::update(container_name, |info| {
+            if info.status != crate::info::ContainerStatus::Restarting {
+                return false;
+            }
+            info.status = crate::info::ContainerStatus::Running;
+            info.pid = pid.to_string();
+            info.started_time = Some(ContainerInfo::current_time());
+            info.restart_count = restart_count;
+            if info.healthcheck.is_some() {
+                info.health = Some(Health::default());
+            }
+            true
+        })
+    }
+
+    /// Record the result of a health check probe.
+    ///
+    /// Probes finishing after the container stopped are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container to update
+    /// * `probe` - Result of the probe
+    /// * `retries` - Consecutive failures making the container unhealthy
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container doesn't exist or can't be saved
+    pub fn record_health(
+        container_name: &str,
+        probe: HealthProbe,
+        retries: u32,
+    ) -> Result<()> {
+        Self::update(container_name, |info| {
+            if info.status != crate::info::ContainerStatus::Running {
+                return false;
+            }
+            info.health
+                .get_or_insert_with(Health::default)
+                .record(probe, retries);
+            true
+        })
+        .map(|_| ())
     }
 
     /// Get container directory path.
@@ -292,8 +568,20 @@ impl ContainerStore {
     /// # Returns
     ///
     /// The full path to the container directory
-    fn container_dir(container_name: &str) -> PathBuf {
-        PathBuf::from(format!("/var/run/rocker/{}/", container_name))
+    pub fn container_dir(container_name: &str) -> PathBuf {
+        Self::root_dir().join(container_name)
+    }
+
+    /// Get the directory holding all container directories.
+    ///
+    /// This is `/var/run/rocker` for root and a directory in the user's
+    /// home for rootless containers.
+    pub fn root_dir() -> PathBuf {
+        if rootless::is_rootless() {
+            rootless::data_dir().join("containers")
+        } else {
+            PathBuf::from(DEFAULT_STATE_ROOT)
+        }
     }
 
     /// Get config file path.
@@ -330,17 +618,34 @@ impl ContainerStore {
     pub fn log_path(container_name: &str) -> PathBuf {
         Self::container_dir(container_name).join(CONTAINER_LOG_FILE)
     }
+
+    /// Get the path of the attach socket served by the container monitor.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    pub fn attach_socket_path(container_name: &str) -> PathBuf {
+        Self::container_dir(container_name).join(ATTACH_SOCKET)
+    }
+
+    /// Get the path of the FIFO a created container waits on.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    pub fn start_fifo_path(container_name: &str) -> PathBuf {
+        Self::container_dir(container_name).join(START_FIFO)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     #[test]
     fn test_container_dir_path() {
         let path = ContainerStore::container_dir("test_container");
-        assert_eq!(path, PathBuf::from("/var/run/rocker/test_container/"));
+        assert_eq!(path, ContainerStore::root_dir().join("test_container"));
     }
 
     #[test]
@@ -348,7 +653,7 @@ mod tests {
         let path = ContainerStore::config_path("test_container");
         assert_eq!(
             path,
-            PathBuf::from("/var/run/rocker/test_container/config.json")
+            ContainerStore::root_dir().join("test_container/config.json")
         );
     }
 
@@ -357,7 +662,7 @@ mod tests {
         let path = ContainerStore::log_path("test_container");
         assert_eq!(
             path,
-            PathBuf::from("/var/run/rocker/test_container/container.log")
+            ContainerStore::root_dir().join("test_container/container.log")
         );
     }
 
@@ -0,0 +1,151 @@
+//! Masked and read-only system paths.
+//!
+//! Even in its own namespaces, a container sees kernel interfaces under
+//! `/proc` and `/sys` that leak information about the host or affect it
+//! as a whole, such as `/proc/kcore` or `/proc/sysrq-trigger`. As in
+//! Docker, some of these paths are masked by mounting `/dev/null` (files)
+//! or an empty read-only tmpfs (directories) over them, and others are
+//! made read-only. `/sys` itself is mounted read-only.
+//!
+//! The lists default to [`DEFAULT_MASKED_PATHS`] and
+//! [`DEFAULT_READONLY_PATHS`] and can be changed per container.
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::mount::{MsFlags, mount};
+use nix::sys::statvfs::{FsFlags, statvfs};
+use std::fs;
+use std::path::Path;
+
+/// Paths hidden from the container by default.
+pub const DEFAULT_MASKED_PATHS: &[&str] = &[
+    "/proc/asound",
+    "/proc/acpi",
+    "/proc/interrupts",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+    "/sys/devices/virtual/powercap",
+];
+
+/// Paths the container may only read by default.
+pub const DEFAULT_READONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// Mount a read-only sysfs on `/sys`.
+///
+/// Mounting sysfs requires owning the network namespace. If the kernel
+/// refuses, the host `/sys` is bind-mounted read-only instead, which is why
+/// this must be called before the old root is detached.
+///
+/// # Arguments
+///
+/// * `old_root` - Where the host root is mounted inside the container
+pub fn mount_sysfs(old_root: &Path) -> Result<()> {
+    let sys = Path::new("/sys");
+    fs::create_dir_all(sys).context("Failed to create /sys")?;
+
+    let flags = MsFlags::MS_RDONLY
+        | MsFlags::MS_NOSUID
+        | MsFlags::MS_NODEV
+        | MsFlags::MS_NOEXEC;
+    match mount(Some("sysfs"), sys, Some("sysfs"), flags, None::<&str>) {
+        Ok(()) => Ok(()),
+        Err(Errno::EPERM) => {
+            debug!("bind mounting the host /sys");
+            mount(
+                Some(&old_root.join("sys")),
+                sys,
+                None::<&str>,
+                MsFlags::MS_BIND | MsFlags::MS_REC,
+                None::<&str>,
+            )
+            .context("Failed to bind mount /sys")?;
+            remount_read_only(sys)
+        }
+        Err(e) => Err(e).context("Failed to mount sysfs to /sys"),
+    }
+}
+
+/// Hide `path` from the container.
+///
+/// Paths that don't exist, for example because the kernel lacks the
+/// feature, are skipped.
+pub fn mask(path: &Path) -> Result<()> {
+    let Ok(metadata) = fs::metadata(path) else {
+        return Ok(());
+    };
+    trace!("mask {:?}", path);
+    if metadata.is_dir() {
+        mount(
+            Some("tmpfs"),
+            path,
+            Some("tmpfs"),
+            MsFlags::MS_RDONLY,
+            None::<&str>,
+        )
+    } else {
+        mount(
+            Some("/dev/null"),
+            path,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+    }
+    .with_context(|| format!("Failed to mask {}", path.display()))
+}
+
+/// Make `path` read-only in the container.
+///
+/// Paths that don't exist are skipped.
+pub fn make_read_only(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    trace!("make {:?} read-only", path);
+    mount(
+        Some(path),
+        path,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .with_context(|| format!("Failed to bind mount {}", path.display()))?;
+    remount_read_only(path)
+}
+
+/// Remount the mount at `path` read-only.
+///
+/// A remount inside a user namespace may not clear the flags the mount
+/// already has, so they are carried over.
+pub fn remount_read_only(path: &Path) -> Result<()> {
+    let current = statvfs(path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?;
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if current.flags().contains(fs_flag) {
+            flags.insert(ms_flag);
+        }
+    }
+    mount(None::<&str>, path, None::<&str>, flags, None::<&str>).with_context(
+        || format!("Failed to remount {} read-only", path.display()),
+    )
+}
@@ -0,0 +1,212 @@
+//! Tmpfs mounts.
+//!
+//! A tmpfs mount gives the container writable scratch space that lives in
+//! memory and disappears with the container, typically next to a read-only
+//! rootfs. Mounts are specified as `container_path[:options]` with comma
+//! separated options, e.g. `/run:size=64m,mode=755`.
+//!
+//! Like Docker, tmpfs mounts are `noexec`, `nosuid` and `nodev` unless the
+//! options say otherwise.
+
+use anyhow::{Context, Result};
+use nix::mount::{MsFlags, mount};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Options passed on to the tmpfs filesystem.
+const DATA_OPTIONS: &[&str] = &["size", "mode", "uid", "gid", "nr_inodes"];
+
+/// Tmpfs mount specification.
+///
+/// # Example
+///
+/// ```rust
+/// use container::tmpfs::Tmpfs;
+///
+/// let tmpfs = Tmpfs::parse("/run:size=64m,mode=755").unwrap();
+/// assert_eq!(tmpfs.options, vec!["size=64m", "mode=755"]);
+/// assert_eq!(tmpfs.to_string(), "/run:size=64m,mode=755");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tmpfs {
+    /// Mount point inside the container
+    pub container_path: PathBuf,
+
+    /// Mount options, in the order given by the user
+    pub options: Vec<String>,
+}
+
+impl Tmpfs {
+    /// Parse a `container_path[:options]` tmpfs specification.
+    ///
+    /// Besides the tmpfs options `size`, `mode`, `uid`, `gid` and
+    /// `nr_inodes`, the mount flags `ro`, `rw`, `exec`, `noexec`, `suid`,
+    /// `nosuid`, `dev`, `nodev` and the access time flags `atime`,
+    /// `noatime`, `diratime`, `nodiratime`, `relatime`, `norelatime`,
+    /// `strictatime` and `nostrictatime` are accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The path is not absolute
+    /// - An option is unknown or a tmpfs option has no value
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (path, options) = match spec.split_once(':') {
+            Some((path, options)) => (path, options),
+            None => (spec, ""),
+        };
+        if !path.starts_with('/') {
+            return Err(anyhow::anyhow!(
+                "Invalid tmpfs {}, path {} must be absolute",
+                spec,
+                path
+            ));
+        }
+
+        let options: Vec<String> = options
+            .split(',')
+            .filter(|option| !option.is_empty())
+            .map(String::from)
+            .collect();
+        for option in &options {
+            let valid = match option.split_once('=') {
+                Some((key, value)) => {
+                    DATA_OPTIONS.contains(&key) && !value.is_empty()
+                }
+                None => flag(option).is_some(),
+            };
+            if !valid {
+                return Err(anyhow::anyhow!(
+                    "Invalid tmpfs {}, unknown option {}",
+                    spec,
+                    option
+                ));
+            }
+        }
+
+        Ok(Tmpfs {
+            container_path: PathBuf::from(path),
+            options,
+        })
+    }
+
+    /// Mount flags and tmpfs data of the mount.
+    fn mount_options(&self) -> (MsFlags, String) {
+        let mut flags =
+            MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+        let mut data = Vec::new();
+        for option in &self.options {
+            match flag(option) {
+                Some((flag, true)) => flags.insert(flag),
+                Some((flag, false)) => flags.remove(flag),
+                None => data.push(option.as_str()),
+            }
+        }
+        (flags, data.join(","))
+    }
+
+    /// Mount the tmpfs in the new container root.
+    ///
+    /// This must be called inside the container after `pivot_root`, and
+    /// before the root is made read-only since missing mount points are
+    /// created in the container rootfs.
+    ///
+    /// # Arguments
+    ///
+    /// * `old_root` - Where the host root is mounted inside the container,
+    ///   which the mount point may not resolve into
+    pub fn mount(&self, old_root: &Path) -> Result<()> {
+        let target = &crate::volume::resolve_mount_point(
+            &self.container_path,
+            old_root,
+            true,
+        )?;
+
+        let (flags, data) = self.mount_options();
+        trace!("mount tmpfs {:?} with {:?} {:?}", target, flags, data);
+        mount(
+            Some("tmpfs"),
+            target,
+            Some("tmpfs"),
+            flags,
+            Some(data.as_str()),
+        )
+        .with_context(|| format!("Failed to mount tmpfs {}", self))?;
+        Ok(())
+    }
+}
+
+/// Mount flag named by `option`, and whether the option sets or clears it.
+fn flag(option: &str) -> Option<(MsFlags, bool)> {
+    match option {
+        "ro" => Some((MsFlags::MS_RDONLY, true)),
+        "rw" => Some((MsFlags::MS_RDONLY, false)),
+        "noexec" => Some((MsFlags::MS_NOEXEC, true)),
+        "exec" => Some((MsFlags::MS_NOEXEC, false)),
+        "nosuid" => Some((MsFlags::MS_NOSUID, true)),
+        "suid" => Some((MsFlags::MS_NOSUID, false)),
+        "nodev" => Some((MsFlags::MS_NODEV, true)),
+        "dev" => Some((MsFlags::MS_NODEV, false)),
+        "noatime" => Some((MsFlags::MS_NOATIME, true)),
+        "atime" => Some((MsFlags::MS_NOATIME, false)),
+        "nodiratime" => Some((MsFlags::MS_NODIRATIME, true)),
+        "diratime" => Some((MsFlags::MS_NODIRATIME, false)),
+        "relatime" => Some((MsFlags::MS_RELATIME, true)),
+        "norelatime" => Some((MsFlags::MS_RELATIME, false)),
+        "strictatime" => Some((MsFlags::MS_STRICTATIME, true)),
+        "nostrictatime" => Some((MsFlags::MS_STRICTATIME, false)),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Tmpfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.container_path.display())?;
+        if !self.options.is_empty() {
+            write!(f, ":{}", self.options.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tmpfs() {
+        let tmpfs = Tmpfs::parse("/tmp").unwrap();
+        assert_eq!(tmpfs.container_path, PathBuf::from("/tmp"));
+        assert!(tmpfs.options.is_empty());
+        assert_eq!(
+            tmpfs.mount_options(),
+            (
+                MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                String::new()
+            )
+        );
+
+        let tmpfs = Tmpfs::parse("/run:exec,size=64m,mode=1777").unwrap();
+        assert_eq!(
+            tmpfs.mount_options(),
+            (
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                "size=64m,mode=1777".to_string()
+            )
+        );
+        assert_eq!(Tmpfs::parse(&tmpfs.to_string()).unwrap(), tmpfs);
+
+        let tmpfs = Tmpfs::parse("/run:noatime").unwrap();
+        assert!(tmpfs.mount_options().0.contains(MsFlags::MS_NOATIME));
+    }
+
+    #[test]
+    fn test_parse_invalid_tmpfs() {
+        assert!(Tmpfs::parse("tmp").is_err());
+        assert!(Tmpfs::parse("/tmp:size").is_err());
+        assert!(Tmpfs::parse("/tmp:size=").is_err());
+        assert!(Tmpfs::parse("/tmp:bogus=1").is_err());
+        assert!(Tmpfs::parse("/tmp:nomtime").is_err());
+    }
+}
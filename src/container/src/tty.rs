@@ -0,0 +1,190 @@
+//! Container pseudo-terminals.
+//!
+//! With `rocker run -t` the container gets a terminal of its own instead of
+//! sharing the user's. `rocker init` allocates it from the container's
+//! devpts instance, so it shows up as `/dev/pts/0` inside the container,
+//! makes the slave the controlling terminal and standard streams of the
+//! command, and hands the master back to the monitor over the console
+//! socket.
+//!
+//! The monitor keeps the master open for the lifetime of the container,
+//! copies its output to the container log and, in the foreground, proxies
+//! it to the user's terminal which is switched to raw mode meanwhile.
+
+use anyhow::{Context, Result};
+use nix::fcntl::OFlag;
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt};
+use nix::sys::socket::{ControlMessage, ControlMessageOwned, MsgFlags};
+use nix::sys::socket::{recvmsg, sendmsg};
+use nix::sys::termios::{SetArg, Termios, cfmakeraw, tcgetattr, tcsetattr};
+use nix::sys::uio::IoVec;
+use nix::unistd::{dup2, isatty, setsid};
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+/// File descriptor of the console socket inside the container.
+pub const CONSOLE_SOCKET_FD: RawFd = 4;
+
+/// Allocate the container terminal and send its master to the monitor.
+///
+/// This must be called by `rocker init` once `/dev/pts` is mounted. The
+/// calling process becomes a session leader with the new terminal as its
+/// controlling terminal and standard streams. The console socket is
+/// closed afterwards.
+pub fn setup_console() -> Result<()> {
+    // SAFETY: CONSOLE_SOCKET_FD is set up by
+    // `Container::create_parent_process` and not used by anything else
+    let socket = unsafe { UnixStream::from_raw_fd(CONSOLE_SOCKET_FD) };
+
+    let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)
+        .context("Failed to open /dev/ptmx")?;
+    grantpt(&master).context("Failed to grant pseudo-terminal")?;
+    unlockpt(&master).context("Failed to unlock pseudo-terminal")?;
+    let slave_path =
+        ptsname_r(&master).context("Failed to get pseudo-terminal name")?;
+    let slave = File::options()
+        .read(true)
+        .write(true)
+        .open(&slave_path)
+        .with_context(|| format!("Failed to open {}", slave_path))?;
+
+    setsid().context("Failed to create session")?;
+    // SAFETY: TIOCSCTTY takes an integer argument
+    let ret =
+        unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSCTTY as _, 0) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to set controlling terminal");
+    }
+    for fd in 0..=2 {
+        dup2(slave.as_raw_fd(), fd)
+            .context("Failed to redirect stdio to the terminal")?;
+    }
+
+    let fds = [master.as_raw_fd()];
+    sendmsg(
+        socket.as_raw_fd(),
+        &[IoVec::from_slice(b"1")],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )
+    .context("Failed to send terminal to rocker")?;
+    Ok(())
+}
+
+/// Receive the terminal master sent by [`setup_console`].
+///
+/// # Errors
+///
+/// Returns an error if the container closed the socket without sending a
+/// terminal, which happens when its init fails
+pub fn receive_master(socket: &UnixStream) -> Result<File> {
+    let mut buf = [0u8; 1];
+    let mut cmsg_buffer = nix::cmsg_space!([RawFd; 1]);
+    let msg = recvmsg(
+        socket.as_raw_fd(),
+        &[IoVec::from_mut_slice(&mut buf)],
+        Some(&mut cmsg_buffer),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .context("Failed to receive container terminal")?;
+
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg
+            && let Some(fd) = fds.first()
+        {
+            // SAFETY: the fd was just received and is owned by nobody else
+            return Ok(unsafe { File::from_raw_fd(*fd) });
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Container exited before setting up its terminal"
+    ))
+}
+
+/// Window size of the terminal `fd` as `(rows, columns)`, `None` if `fd`
+/// is not a terminal.
+pub fn window_size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ writes a winsize struct
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+/// Resize the terminal `fd`.
+///
+/// Resizing the container terminal sends `SIGWINCH` to its foreground
+/// process group.
+pub fn set_window_size(fd: RawFd, rows: u16, columns: u16) -> Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCSWINSZ reads a winsize struct
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to resize container terminal");
+    }
+    Ok(())
+}
+
+/// Copy the window size of the terminal `from` to the terminal `to`.
+///
+/// Does nothing if `from` is not a terminal.
+pub fn copy_window_size(from: RawFd, to: RawFd) -> Result<()> {
+    match window_size(from) {
+        Some((rows, columns)) => set_window_size(to, rows, columns),
+        None => Ok(()),
+    }
+}
+
+/// A terminal switched to raw mode, restored when dropped.
+///
+/// In raw mode input is passed on byte by byte without echo or signal
+/// generation, so that line editing, `^C` and job control are handled by
+/// the container terminal instead.
+pub struct RawMode {
+    fd: RawFd,
+    original: Termios,
+}
+
+impl RawMode {
+    /// Switch `fd` to raw mode, `None` if it is not a terminal.
+    pub fn enable(fd: RawFd) -> Result<Option<Self>> {
+        if !isatty(fd).unwrap_or(false) {
+            return Ok(None);
+        }
+        let original =
+            tcgetattr(fd).context("Failed to get terminal attributes")?;
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(fd, SetArg::TCSANOW, &raw)
+            .context("Failed to switch terminal to raw mode")?;
+        Ok(Some(RawMode { fd, original }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(self.fd, SetArg::TCSANOW, &self.original);
+    }
+}
+
+/// Create the console socket pair.
+///
+/// The first socket stays with the monitor, the second one is passed to
+/// the container as [`CONSOLE_SOCKET_FD`].
+pub fn console_socket() -> Result<(UnixStream, UnixStream)> {
+    UnixStream::pair().context("Failed to create console socket")
+}
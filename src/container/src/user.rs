@@ -0,0 +1,336 @@
+//! User and groups of the container command.
+//!
+//! `rocker run --user` and `rocker exec --user` take `name|uid[:group|gid]`
+//! like Docker. Names are resolved against the container's own
+//! `/etc/passwd` and `/etc/group`, not the host's, so the lookup happens
+//! inside the container once its root is in place. Numeric ids don't need
+//! an entry in these files.
+
+use crate::capabilities::CapabilitySet;
+use anyhow::{Context, Result};
+use nix::unistd::{Gid, Uid, setgid, setuid};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// User the container command runs as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub uid: u32,
+
+    /// Primary group
+    pub gid: u32,
+
+    /// Supplementary groups, from the groups listing the user in
+    /// `/etc/group`
+    pub additional_gids: Vec<u32>,
+
+    /// Home directory, `/` if the user has no `/etc/passwd` entry
+    pub home: String,
+
+    /// Whether `setgroups(2)` is allowed, it isn't in the user namespace of
+    /// a rootless container without subordinate ids
+    set_groups: bool,
+}
+
+/// Entry of `/etc/passwd`.
+struct PasswdEntry<'a> {
+    name: &'a str,
+    uid: u32,
+    gid: u32,
+    home: &'a str,
+}
+
+/// Entry of `/etc/group`.
+struct GroupEntry<'a> {
+    name: &'a str,
+    gid: u32,
+    members: Vec<&'a str>,
+}
+
+impl User {
+    /// Resolve a `name|uid[:group|gid]` specification.
+    ///
+    /// Without a group the primary group of the user's `/etc/passwd` entry
+    /// is used, or group 0 if there is none.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - User specification
+    /// * `root` - Root directory of the container filesystem
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the specification is empty or names a user or
+    /// group missing from the container
+    pub fn resolve(spec: &str, root: &Path) -> Result<Self> {
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+        if user.is_empty() || group == Some("") {
+            return Err(anyhow::anyhow!("Invalid user {:?}", spec));
+        }
+
+        let passwd = read_optional(&root.join("etc/passwd"))?;
+        let groups = read_optional(&root.join("etc/group"))?;
+        let passwd: Vec<PasswdEntry> =
+            passwd.lines().filter_map(parse_passwd_line).collect();
+        let groups: Vec<GroupEntry> =
+            groups.lines().filter_map(parse_group_line).collect();
+
+        let entry = match user.parse::<u32>() {
+            Ok(uid) => passwd.iter().find(|entry| entry.uid == uid),
+            Err(_) => Some(
+                passwd.iter().find(|entry| entry.name == user).ok_or_else(
+                    || {
+                        anyhow::anyhow!(
+                            "User {} not found in the container",
+                            user
+                        )
+                    },
+                )?,
+            ),
+        };
+        let uid = match entry {
+            Some(entry) => entry.uid,
+            None => user.parse().unwrap_or_default(),
+        };
+
+        let gid = match group {
+            Some(group) => match group.parse::<u32>() {
+                Ok(gid) => gid,
+                Err(_) => {
+                    groups
+                        .iter()
+                        .find(|entry| entry.name == group)
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "Group {} not found in the container",
+                                group
+                            )
+                        })?
+                        .gid
+                }
+            },
+            None => entry.map(|entry| entry.gid).unwrap_or(0),
+        };
+
+        let mut additional_gids = Vec::new();
+        if let Some(entry) = entry {
+            for group in &groups {
+                if group.members.contains(&entry.name)
+                    && !additional_gids.contains(&group.gid)
+                {
+                    additional_gids.push(group.gid);
+                }
+            }
+        }
+
+        Ok(User {
+            uid,
+            gid,
+            additional_gids,
+            home: entry.map_or("/", |entry| entry.home).to_string(),
+            set_groups: setgroups_allowed(),
+        })
+    }
+
+    /// Check that the user and its group are mapped in the user namespace
+    /// of the calling process.
+    ///
+    /// Only the invoking user is mapped in a rootless container without
+    /// subordinate ids, so switching to any other user would fail.
+    pub fn check_mapped(&self) -> Result<()> {
+        for (kind, map_path, id, subid_file) in [
+            ("User", "/proc/self/uid_map", self.uid, "/etc/subuid"),
+            ("Group", "/proc/self/gid_map", self.gid, "/etc/subgid"),
+        ] {
+            let map = fs::read_to_string(map_path)
+                .with_context(|| format!("Failed to read {}", map_path))?;
+            if !is_mapped(&map, id) {
+                return Err(anyhow::anyhow!(
+                    "{} id {} is not mapped in the container, a rootless \
+                     container needs subordinate ids in {}",
+                    kind,
+                    id,
+                    subid_file
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch the calling process to the user.
+    ///
+    /// The supplementary groups are replaced first, then the group and the
+    /// user id. Switching from root to another user clears the effective
+    /// and permitted capabilities.
+    ///
+    /// This only performs `setgroups`, `setgid` and `setuid` calls, so it
+    /// can be used between `fork` and `execve`.
+    pub fn switch(&self) -> io::Result<()> {
+        if self.set_groups {
+            // SAFETY: the pointer and length describe the gid list, gid_t
+            // is a u32
+            let ret = unsafe {
+                libc::setgroups(
+                    self.additional_gids.len(),
+                    self.additional_gids.as_ptr(),
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        setgid(Gid::from_raw(self.gid))?;
+        setuid(Uid::from_raw(self.uid))?;
+        Ok(())
+    }
+}
+
+/// Reduce the capabilities of the calling process and switch to `user`.
+///
+/// Switching the user needs `CAP_SETUID` and `CAP_SETGID`, which may not be
+/// in the capability set, and dropping from the bounding set needs
+/// `CAP_SETPCAP`, which the user loses. So the bounding set is reduced
+/// first, then the user is switched, and the other sets are reduced last.
+///
+/// Like [`User::switch`] and [`CapabilitySet::apply`], this can be used
+/// between `fork` and `execve`.
+pub fn drop_privileges(
+    user: Option<&User>,
+    capabilities: Option<&CapabilitySet>,
+) -> io::Result<()> {
+    if let Some(capabilities) = capabilities {
+        capabilities.drop_bounding()?;
+    }
+    if let Some(user) = user {
+        user.switch()?;
+    }
+    if let Some(capabilities) = capabilities {
+        capabilities.apply_process_sets()?;
+    }
+    Ok(())
+}
+
+/// Read a file that may be missing from the image.
+fn read_optional(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to read {}", path.display()))
+        }
+    }
+}
+
+/// Parse a `name:password:uid:gid:gecos:home:shell` line.
+fn parse_passwd_line(line: &str) -> Option<PasswdEntry<'_>> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 6 || line.starts_with('#') {
+        return None;
+    }
+    Some(PasswdEntry {
+        name: fields[0],
+        uid: fields[2].parse().ok()?,
+        gid: fields[3].parse().ok()?,
+        home: fields[5],
+    })
+}
+
+/// Parse a `name:password:gid:members` line.
+fn parse_group_line(line: &str) -> Option<GroupEntry<'_>> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 4 || line.starts_with('#') {
+        return None;
+    }
+    Some(GroupEntry {
+        name: fields[0],
+        gid: fields[2].parse().ok()?,
+        members: fields[3]
+            .split(',')
+            .filter(|member| !member.is_empty())
+            .collect(),
+    })
+}
+
+/// Whether `id` is inside one of the ranges of an id map, as read from
+/// `/proc/<pid>/uid_map` or `/proc/<pid>/gid_map`.
+fn is_mapped(map: &str, id: u32) -> bool {
+    map.lines().any(|line| {
+        let fields: Vec<u64> = line
+            .split_whitespace()
+            .filter_map(|field| field.parse().ok())
+            .collect();
+        matches!(fields.as_slice(), [inside, _, count]
+            if (*inside..*inside + *count).contains(&(id as u64)))
+    })
+}
+
+/// Whether the user namespace of the calling process allows
+/// `setgroups(2)`.
+fn setgroups_allowed() -> bool {
+    fs::read_to_string("/proc/self/setgroups")
+        .map(|state| state.trim() != "deny")
+        .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_root() -> PathBuf {
+        let root = std::env::temp_dir()
+            .join(format!("rocker-user-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(
+            root.join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/sh\n\
+             app:x:1000:1000::/home/app:/bin/sh\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("etc/group"),
+            "root:x:0:\nwheel:x:10:root,app\napp:x:1000:\naudio:x:29:app\n",
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn test_resolve_user() {
+        let root = test_root();
+
+        let user = User::resolve("app", &root).unwrap();
+        assert_eq!((user.uid, user.gid), (1000, 1000));
+        assert_eq!(user.additional_gids, vec![10, 29]);
+        assert_eq!(user.home, "/home/app");
+
+        let user = User::resolve("1000:wheel", &root).unwrap();
+        assert_eq!((user.uid, user.gid), (1000, 10));
+
+        // Numeric ids don't need an entry
+        let user = User::resolve("4242:4343", &root).unwrap();
+        assert_eq!((user.uid, user.gid), (4242, 4343));
+        assert!(user.additional_gids.is_empty());
+        assert_eq!(user.home, "/");
+
+        assert!(User::resolve("nobody", &root).is_err());
+        assert!(User::resolve("app:nogroup", &root).is_err());
+        assert!(User::resolve("app:", &root).is_err());
+        assert!(User::resolve("", &root).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_is_mapped() {
+        let map = "         0       1000          1\n         1     100000      65536\n";
+        assert!(is_mapped(map, 0));
+        assert!(is_mapped(map, 65536));
+        assert!(!is_mapped(map, 65537));
+        assert!(!is_mapped("         0       1000          1\n", 65534));
+    }
+}
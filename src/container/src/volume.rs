@@ -0,0 +1,310 @@
+//! Bind-mount volumes.
+//!
+//! A volume makes a host directory (or file) visible inside the container.
+//! Volumes are specified as `host_path:container_path[:ro|rw]` and are
+//! mounted by the container init process right after `pivot_root`, while
+//! the host filesystem is still reachable through the old root.
+
+use anyhow::{Context, Result};
+use nix::mount::{MsFlags, mount};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Bind-mount volume specification.
+///
+/// # Example
+///
+/// ```rust
+/// use container::volume::Volume;
+///
+/// let volume = Volume::parse("/data:/mnt/data:ro").unwrap();
+/// assert!(volume.read_only);
+/// assert_eq!(volume.to_string(), "/data:/mnt/data:ro");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Volume {
+    /// Directory or file on the host
+    pub host_path: PathBuf,
+
+    /// Mount point inside the container
+    pub container_path: PathBuf,
+
+    /// Whether the container may only read the volume
+    pub read_only: bool,
+}
+
+impl Volume {
+    /// Parse a `host_path:container_path[:ro|rw]` volume specification.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The specification doesn't have two or three fields
+    /// - Either path is not absolute
+    /// - The mode is neither `ro` nor `rw`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let (host_path, container_path, mode) = match parts.as_slice() {
+            [host, container] => (*host, *container, "rw"),
+            [host, container, mode] => (*host, *container, *mode),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Invalid volume {}, expected host_path:container_path[:ro|rw]",
+                    spec
+                ));
+            }
+        };
+
+        for path in [host_path, container_path] {
+            if !path.starts_with('/') {
+                return Err(anyhow::anyhow!(
+                    "Invalid volume {}, path {} must be absolute",
+                    spec,
+                    path
+                ));
+            }
+        }
+
+        let read_only = match mode {
+            "ro" => true,
+            "rw" => false,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Invalid volume {}, unknown mode {}",
+                    spec,
+                    mode
+                ));
+            }
+        };
+
+        Ok(Volume {
+            host_path: PathBuf::from(host_path),
+            container_path: PathBuf::from(container_path),
+            read_only,
+        })
+    }
+
+    /// Prepare the host side of the volume.
+    ///
+    /// Creates the host directory if it doesn't exist and resolves the host
+    /// path, so that symlinks are not followed relative to the container
+    /// root once the volume is mounted from inside the container.
+    pub fn prepare_host(&mut self) -> Result<()> {
+        if !self.host_path.exists() {
+            fs::create_dir_all(&self.host_path).with_context(|| {
+                format!(
+                    "Failed to create volume directory {}",
+                    self.host_path.display()
+                )
+            })?;
+        }
+
+        self.host_path =
+            fs::canonicalize(&self.host_path).with_context(|| {
+                format!(
+                    "Failed to resolve volume path {}",
+                    self.host_path.display()
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Bind-mount the volume into the new container root.
+    ///
+    /// This must be called inside the container after `pivot_root` and
+    /// before the old root is detached, since the host path is only
+    /// reachable through `old_root` at that point. Missing mount points
+    /// are created in the container rootfs (see [`resolve_mount_point`]).
+    ///
+    /// # Arguments
+    ///
+    /// * `old_root` - Where the host root is mounted inside the container
+    pub fn mount(&self, old_root: &Path) -> Result<()> {
+        let source = old_root.join(
+            self.host_path
+                .strip_prefix("/")
+                .unwrap_or(self.host_path.as_path()),
+        );
+        let target = &resolve_mount_point(
+            &self.container_path,
+            old_root,
+            source.is_dir(),
+        )?;
+
+        trace!("bind mount volume {:?} to {:?}", source, target);
+        mount(
+            Some(&source),
+            target,
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        )
+        .with_context(|| format!("Failed to mount volume {}", self))?;
+
+        // MS_RDONLY is ignored on the initial bind mount, it has to be
+        // applied with a remount
+        if self.read_only {
+            crate::system_paths::remount_read_only(target).with_context(
+                || format!("Failed to remount volume {} read-only", self),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolve a mount point in the container root, creating its missing
+/// directories, and the mount point itself as a directory if `dir` or as a
+/// file otherwise.
+///
+/// Mount points are set up while the host root is still attached at
+/// `old_root`. Symlinks of the image are followed, but may not lead into
+/// the host root, or rocker would create and mount over host files.
+///
+/// # Errors
+///
+/// Returns an error if the mount point resolves into `old_root` or can't be
+/// created
+pub(crate) fn resolve_mount_point(
+    target: &Path,
+    old_root: &Path,
+    dir: bool,
+) -> Result<PathBuf> {
+    let mut resolved = PathBuf::from("/");
+    let mut components = target.components().peekable();
+    while let Some(component) = components.next() {
+        let name = match component {
+            Component::Normal(name) => name,
+            Component::ParentDir => {
+                resolved.pop();
+                continue;
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {
+                continue;
+            }
+        };
+
+        let path = resolved.join(name);
+        resolved = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                fs::canonicalize(&path).with_context(|| {
+                    format!("Failed to resolve mount point {}", path.display())
+                })?
+            }
+            Ok(_) => path,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let created = if components.peek().is_none() && !dir {
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                        .map(|_| ())
+                } else {
+                    fs::create_dir(&path)
+                };
+                created.with_context(|| {
+                    format!("Failed to create mount point {}", path.display())
+                })?;
+                path
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to resolve mount point {}", path.display())
+                });
+            }
+        };
+
+        if resolved.starts_with(old_root) {
+            return Err(anyhow::anyhow!(
+                "Mount point {} resolves to {} outside of the container",
+                target.display(),
+                resolved.display()
+            ));
+        }
+    }
+    Ok(resolved)
+}
+
+impl fmt::Display for Volume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.host_path.display(),
+            self.container_path.display(),
+            if self.read_only { "ro" } else { "rw" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_volume() {
+        let volume = Volume::parse("/host/data:/data").unwrap();
+        assert_eq!(volume.host_path, PathBuf::from("/host/data"));
+        assert_eq!(volume.container_path, PathBuf::from("/data"));
+        assert!(!volume.read_only);
+
+        let volume = Volume::parse("/host/data:/data:ro").unwrap();
+        assert!(volume.read_only);
+
+        let volume = Volume::parse("/host/data:/data:rw").unwrap();
+        assert!(!volume.read_only);
+    }
+
+    #[test]
+    fn test_parse_invalid_volume() {
+        assert!(Volume::parse("/host/data").is_err());
+        assert!(Volume::parse("host/data:/data").is_err());
+        assert!(Volume::parse("/host/data:data").is_err());
+        assert!(Volume::parse("/host/data:/data:rx").is_err());
+        assert!(Volume::parse("/a:/b:ro:extra").is_err());
+    }
+
+    #[test]
+    fn test_volume_display_round_trip() {
+        let volume = Volume::parse("/host/data:/data").unwrap();
+        assert_eq!(volume.to_string(), "/host/data:/data:rw");
+        assert_eq!(Volume::parse(&volume.to_string()).unwrap(), volume);
+    }
+
+    #[test]
+    fn test_resolve_mount_point() {
+        let root = std::env::temp_dir()
+            .join(format!("rocker-volume-test-{}", std::process::id()));
+        let old_root = root.join(".pivot_root");
+        fs::create_dir_all(old_root.join("etc")).unwrap();
+        fs::create_dir_all(root.join("srv")).unwrap();
+        std::os::unix::fs::symlink(root.join("srv"), root.join("data"))
+            .unwrap();
+        std::os::unix::fs::symlink(old_root.join("etc"), root.join("escape"))
+            .unwrap();
+
+        assert_eq!(
+            resolve_mount_point(&root.join("run/app"), &old_root, true)
+                .unwrap(),
+            root.join("run/app")
+        );
+        assert!(root.join("run/app").is_dir());
+
+        assert_eq!(
+            resolve_mount_point(&root.join("data/conf"), &old_root, false)
+                .unwrap(),
+            root.join("srv/conf")
+        );
+        assert!(root.join("srv/conf").is_file());
+
+        assert!(
+            resolve_mount_point(&root.join("escape/cron"), &old_root, false)
+                .is_err()
+        );
+        assert!(!old_root.join("etc/cron").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+//! Copy-on-write container workspace.
+//!
+//! Every container gets its own overlayfs mount: the image rootfs is used as
+//! the read-only lower layer and a private upper/work directory pair lives in
+//! the container's state directory. All writes made by the container end up
+//! in the upper layer, so several containers can share one image without
+//! touching the image directory.
+//!
+//! # Directory Structure
+//!
+//! ```text
+//! /var/run/rocker/{container_name}/
+//!   ├── upper/            # Container writable layer
+//!   ├── work/             # overlayfs internal work directory
+//!   └── merged/           # Mount point of the container rootfs
+//! ```
+//!
+//! An unprivileged user can't mount overlayfs on the host, so rootless
+//! containers mount their [`Overlay`] from inside their own user and mount
+//! namespaces instead, and the mount is only visible to the container.
+
+use crate::rootless;
+use crate::store::ContainerStore;
+use anyhow::{Context, Result};
+use nix::mount::{MntFlags, MsFlags, mount, umount2};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory holding the container writable layer.
+pub const UPPER_DIR: &str = "upper";
+
+/// Directory used by overlayfs to prepare files before they are moved
+/// into the upper layer.
+pub const WORK_DIR: &str = "work";
+
+/// Mount point of the merged container rootfs.
+pub const MERGED_DIR: &str = "merged";
+
+/// Container workspace manager.
+///
+/// This struct provides static methods for creating and tearing down
+/// the overlayfs rootfs of a container.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use container::workspace::Workspace;
+/// use std::path::Path;
+///
+/// let rootfs = Workspace::create(
+///     "my_container",
+///     Path::new("/var/lib/rocker/images/busybox/latest/rootfs"),
+/// )
+/// .unwrap();
+/// println!("Container rootfs: {:?}", rootfs);
+///
+/// Workspace::delete("my_container").unwrap();
+/// ```
+pub struct Workspace;
+
+/// overlayfs layers of a container rootfs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overlay {
+    /// Read-only image rootfs
+    pub lower: PathBuf,
+
+    /// Container writable layer
+    pub upper: PathBuf,
+
+    /// overlayfs work directory
+    pub work: PathBuf,
+
+    /// Mount point of the merged rootfs
+    pub merged: PathBuf,
+}
+
+impl Overlay {
+    /// Mount the overlay on its merged directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a layer path can't be passed to overlayfs or
+    /// the mount fails
+    pub fn mount(&self) -> Result<()> {
+        let options =
+            Workspace::overlay_options(&self.lower, &self.upper, &self.work)?;
+        trace!("mount overlay on {:?} with {}", self.merged, options);
+
+        mount(
+            Some("overlay"),
+            &self.merged,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(options.as_str()),
+        )
+        .with_context(|| {
+            format!("Failed to mount overlayfs on {}", self.merged.display())
+        })
+    }
+}
+
+impl Workspace {
+    /// Create the workspace of a container and mount its overlayfs rootfs.
+    ///
+    /// The mount is made in the caller's mount namespace, so it is visible
+    /// from the host (e.g. for `rocker commit`) until [`Workspace::delete`]
+    /// is called.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    /// * `lower` - The read-only image rootfs
+    ///
+    /// # Returns
+    ///
+    /// The path of the merged rootfs the container should pivot into
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The image rootfs doesn't exist
+    /// - Directory creation fails
+    /// - The overlayfs mount fails
+    pub fn create(container_name: &str, lower: &Path) -> Result<PathBuf> {
+        let overlay = Self::prepare(container_name, lower)?;
+        overlay.mount()?;
+        Ok(overlay.merged)
+    }
+
+    /// Create the workspace directories of a container without mounting
+    /// the rootfs.
+    ///
+    /// The returned overlay has to be mounted by the caller, which for
+    /// rootless containers is the container init process.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    /// * `lower` - The read-only image rootfs
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image rootfs doesn't exist or directory
+    /// creation fails
+    pub fn prepare(container_name: &str, lower: &Path) -> Result<Overlay> {
+        if !lower.exists() {
+            return Err(anyhow::anyhow!(
+                "Image rootfs does not exist: {}",
+                lower.display()
+            ));
+        }
+
+        let upper = Self::upper_path(container_name);
+        let work = Self::work_path(container_name);
+        let merged = Self::mount_path(container_name);
+
+        for dir in [&upper, &work, &merged] {
+            fs::create_dir_all(dir).with_context(|| {
+                format!("Failed to create directory {}", dir.display())
+            })?;
+        }
+
+        Ok(Overlay {
+            lower: lower.to_path_buf(),
+            upper,
+            work,
+            merged,
+        })
+    }
+
+    /// Unmount the container rootfs and remove the workspace directories.
+    ///
+    /// Missing directories are ignored, so this is safe to call for
+    /// containers whose workspace was never created.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if unmounting or directory removal fails
+    pub fn delete(container_name: &str) -> Result<()> {
+        let merged = Self::mount_path(container_name);
+
+        if Self::is_mounted(container_name) {
+            umount2(&merged, MntFlags::MNT_DETACH).with_context(|| {
+                format!("Failed to unmount {}", merged.display())
+            })?;
+        }
+
+        // Never remove the mount point recursively: if it is still mounted
+        // for any reason we would be deleting files through the overlay.
+        if merged.exists() {
+            fs::remove_dir(&merged).with_context(|| {
+                format!("Failed to remove directory {}", merged.display())
+            })?;
+        }
+
+        for dir in [
+            Self::upper_path(container_name),
+            Self::work_path(container_name),
+        ] {
+            if dir.exists() {
+                rootless::remove_dir_all(&dir)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the container rootfs is currently mounted.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    pub fn is_mounted(container_name: &str) -> bool {
+        // mountinfo reports resolved paths (/var/run is usually a symlink)
+        let Ok(merged) = fs::canonicalize(Self::mount_path(container_name))
+        else {
+            return false;
+        };
+        let Some(merged) = merged.to_str() else {
+            return false;
+        };
+
+        fs::read_to_string("/proc/self/mountinfo")
+            .map(|mountinfo| {
+                mountinfo
+                    .lines()
+                    .any(|line| line.split(' ').nth(4) == Some(merged))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Get the mount point of the merged container rootfs.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use container::workspace::Workspace;
+    ///
+    /// let rootfs = Workspace::mount_path("my_container");
+    /// println!("Container rootfs: {:?}", rootfs);
+    /// ```
+    pub fn mount_path(container_name: &str) -> PathBuf {
+        ContainerStore::container_dir(container_name).join(MERGED_DIR)
+    }
+
+    /// Get the writable layer directory of the container.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    pub fn upper_path(container_name: &str) -> PathBuf {
+        ContainerStore::container_dir(container_name).join(UPPER_DIR)
+    }
+
+    /// Get the overlayfs work directory of the container.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_name` - The name of the container
+    fn work_path(container_name: &str) -> PathBuf {
+        ContainerStore::container_dir(container_name).join(WORK_DIR)
+    }
+
+    /// Build the overlayfs mount data string.
+    ///
+    /// overlayfs uses `,` to separate options and `:` to separate lower
+    /// layers, so paths containing them are rejected.
+    fn overlay_options(
+        lower: &Path,
+        upper: &Path,
+        work: &Path,
+    ) -> Result<String> {
+        let mut parts = Vec::new();
+        for (key, path) in
+            [("lowerdir", lower), ("upperdir", upper), ("workdir", work)]
+        {
+            let path = path.to_str().ok_or_else(|| {
+                anyhow::anyhow!("Invalid overlay path {}", path.display())
+            })?;
+            if path.contains(',') || path.contains(':') {
+                return Err(anyhow::anyhow!(
+                    "Overlay path {} must not contain ',' or ':'",
+                    path
+                ));
+            }
+            parts.push(format!("{}={}", key, path));
+        }
+        Ok(parts.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_paths() {
+        assert_eq!(
+            Workspace::mount_path("test_container"),
+            ContainerStore::root_dir().join("test_container/merged")
+        );
+        assert_eq!(
+            Workspace::upper_path("test_container"),
+            ContainerStore::root_dir().join("test_container/upper")
+        );
+        assert_eq!(
+            Workspace::work_path("test_container"),
+            ContainerStore::root_dir().join("test_container/work")
+        );
+    }
+
+    #[test]
+    fn test_overlay_options() {
+        let options = Workspace::overlay_options(
+            Path::new("/images/busybox/rootfs"),
+            Path::new("/run/c1/upper"),
+            Path::new("/run/c1/work"),
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            "lowerdir=/images/busybox/rootfs,upperdir=/run/c1/upper,workdir=/run/c1/work"
+        );
+
+        assert!(
+            Workspace::overlay_options(
+                Path::new("/images/a:b"),
+                Path::new("/run/c1/upper"),
+                Path::new("/run/c1/work"),
+            )
+            .is_err()
+        );
+    }
+}
@@ -2,6 +2,7 @@
 //!
 //! Contains metadata about container images including name, tag, size, and creation time.
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -23,6 +24,44 @@ pub struct ImageInfo {
 
     /// Unique image ID (SHA256-like hash)
     pub id: String,
+
+    /// Defaults for containers created from the image
+    #[serde(default)]
+    pub config: ImageConfig,
+}
+
+/// Container defaults stored with an image
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageConfig {
+    /// Default environment variables in `KEY=VALUE` form
+    #[serde(default)]
+    pub env: Vec<String>,
+
+    /// Default health check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<Healthcheck>,
+}
+
+/// Health check stored with an image, as given to `HEALTHCHECK`
+///
+/// Durations are kept as written, e.g. `30s`, unset values fall back to
+/// the defaults of `rocker run`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Healthcheck {
+    /// Shell command
+    pub cmd: String,
+
+    /// Time between two probes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+
+    /// Time after which a probe fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+
+    /// Consecutive failures making the container unhealthy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
 }
 
 fn default_tag() -> String {
@@ -61,8 +100,181 @@ impl ImageInfo {
             size,
             created_time: Self::current_time(),
             id,
+            config: ImageConfig::default(),
+        }
+    }
+}
+
+impl ImageConfig {
+    /// Apply a Dockerfile style instruction given with
+    /// `rocker import --change`.
+    ///
+    /// `ENV` is supported either as `ENV KEY=VALUE [KEY=VALUE...]`, where
+    /// values may be quoted or escape characters with `\` like in a
+    /// Dockerfile, or in the legacy `ENV KEY VALUE` form where the value is
+    /// the rest of the line. Variables that are already set are overridden.
+    ///
+    /// `HEALTHCHECK` is supported as
+    /// `HEALTHCHECK [--interval=D] [--timeout=D] [--retries=N] CMD command`,
+    /// with a shell command, or `HEALTHCHECK NONE` to remove it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use image::ImageConfig;
+    ///
+    /// let mut config = ImageConfig::default();
+    /// config.apply_change("ENV LANG=C.UTF-8 EDITOR=vi").unwrap();
+    /// config.apply_change("ENV EDITOR nano -w").unwrap();
+    /// config.apply_change(r#"ENV MSG="hello world""#).unwrap();
+    /// assert_eq!(
+    ///     config.env,
+    ///     vec!["LANG=C.UTF-8", "EDITOR=nano -w", "MSG=hello world"]
+    /// );
+    ///
+    /// config
+    ///     .apply_change("HEALTHCHECK --interval=5s CMD wget -q -O- localhost")
+    ///     .unwrap();
+    /// let healthcheck = config.healthcheck.unwrap();
+    /// assert_eq!(healthcheck.cmd, "wget -q -O- localhost");
+    /// assert_eq!(healthcheck.interval.as_deref(), Some("5s"));
+    /// ```
+    pub fn apply_change(&mut self, change: &str) -> Result<()> {
+        let change = change.trim();
+        let (instruction, rest) = change
+            .split_once(char::is_whitespace)
+            .unwrap_or((change, ""));
+        if instruction.eq_ignore_ascii_case("HEALTHCHECK") {
+            return self.apply_healthcheck(change, rest);
+        }
+        if !instruction.eq_ignore_ascii_case("ENV") {
+            return Err(anyhow::anyhow!(
+                "Unsupported change {}, only ENV and HEALTHCHECK are supported",
+                change
+            ));
+        }
+
+        let rest = rest.trim();
+        let first = rest.split_whitespace().next().unwrap_or("");
+        let vars: Vec<String> = if first.contains('=') {
+            split_words(rest).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid ENV change {}, unterminated quote",
+                    change
+                )
+            })?
+        } else {
+            match rest.split_once(char::is_whitespace) {
+                Some((key, value)) => {
+                    vec![format!("{}={}", key, value.trim_start())]
+                }
+                None => Vec::new(),
+            }
+        };
+
+        if vars.is_empty() || vars.iter().any(|var| var.starts_with('=')) {
+            return Err(anyhow::anyhow!("Invalid ENV change {}", change));
+        }
+
+        for var in vars {
+            let key = var.split('=').next().unwrap_or(&var);
+            self.env
+                .retain(|existing| existing.split('=').next() != Some(key));
+            self.env.push(var);
+        }
+        Ok(())
+    }
+
+    /// Apply the arguments of a `HEALTHCHECK` change.
+    fn apply_healthcheck(&mut self, change: &str, args: &str) -> Result<()> {
+        let invalid =
+            || anyhow::anyhow!("Invalid HEALTHCHECK change {}", change);
+
+        let mut rest = args.trim();
+        if rest.eq_ignore_ascii_case("NONE") {
+            self.healthcheck = None;
+            return Ok(());
+        }
+
+        let mut healthcheck = Healthcheck::default();
+        while let Some(option) = rest.strip_prefix("--") {
+            let (option, next) = option
+                .split_once(char::is_whitespace)
+                .unwrap_or((option, ""));
+            match option.split_once('=') {
+                Some(("interval", value)) if !value.is_empty() => {
+                    healthcheck.interval = Some(value.to_string())
+                }
+                Some(("timeout", value)) if !value.is_empty() => {
+                    healthcheck.timeout = Some(value.to_string())
+                }
+                Some(("retries", value)) => {
+                    healthcheck.retries =
+                        Some(value.parse().map_err(|_| invalid())?)
+                }
+                _ => return Err(invalid()),
+            }
+            rest = next.trim_start();
+        }
+
+        let (cmd, command) =
+            rest.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        if !cmd.eq_ignore_ascii_case("CMD") || command.trim().is_empty() {
+            return Err(invalid());
+        }
+        healthcheck.cmd = command.trim().to_string();
+        self.healthcheck = Some(healthcheck);
+        Ok(())
+    }
+}
+
+/// Split `line` into words at whitespace, like a Dockerfile does for the
+/// `KEY=VALUE` form of `ENV`.
+///
+/// Single quotes keep everything up to the next one literally. A backslash
+/// escapes the next character, in double quotes only `"`, `\` and a line
+/// break. Returns `None` if a quote isn't closed.
+fn split_words(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => {
+                let word = word.get_or_insert_with(String::new);
+                word.push(chars.next().unwrap_or('\\'));
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '\n') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
         }
     }
+    words.extend(word);
+    Some(words)
 }
 
 impl fmt::Display for ImageInfo {
@@ -97,4 +309,77 @@ mod tests {
         assert_ne!(id1, id3); // Different name should produce different ID
         assert_eq!(id1.len(), 12); // ID should be 12 characters
     }
+
+    #[test]
+    fn test_apply_change() {
+        let mut config = ImageConfig::default();
+        config.apply_change("ENV A=1 B=2").unwrap();
+        config.apply_change("env A 3 4").unwrap();
+        assert_eq!(config.env, vec!["B=2", "A=3 4"]);
+
+        assert!(config.apply_change("CMD sh").is_err());
+        assert!(config.healthcheck.is_none());
+        assert!(config.apply_change("ENV").is_err());
+        assert!(config.apply_change("ENV A").is_err());
+        assert!(config.apply_change("ENV =1").is_err());
+        assert!(config.apply_change(r#"ENV A="open"#).is_err());
+    }
+
+    #[test]
+    fn test_apply_quoted_env_change() {
+        let mut config = ImageConfig::default();
+        config
+            .apply_change(
+                r#"ENV MSG="hello world" PS1='$ ' PATH=/a\ b Q="say \"hi\"\n""#,
+            )
+            .unwrap();
+        assert_eq!(
+            config.env,
+            vec!["MSG=hello world", "PS1=$ ", "PATH=/a b", r#"Q=say "hi"\n"#]
+        );
+    }
+
+    #[test]
+    fn test_apply_healthcheck_change() {
+        let mut config = ImageConfig::default();
+        config
+            .apply_change(
+                "HEALTHCHECK --timeout=3s --retries=5 CMD test -f /tmp/ready",
+            )
+            .unwrap();
+        assert_eq!(
+            config.healthcheck,
+            Some(Healthcheck {
+                cmd: "test -f /tmp/ready".to_string(),
+                interval: None,
+                timeout: Some("3s".to_string()),
+                retries: Some(5),
+            })
+        );
+
+        config.apply_change("healthcheck none").unwrap();
+        assert!(config.healthcheck.is_none());
+
+        assert!(config.apply_change("HEALTHCHECK").is_err());
+        assert!(config.apply_change("HEALTHCHECK CMD").is_err());
+        assert!(config.apply_change("HEALTHCHECK curl localhost").is_err());
+        assert!(
+            config
+                .apply_change("HEALTHCHECK --retries=x CMD true")
+                .is_err()
+        );
+        assert!(
+            config
+                .apply_change("HEALTHCHECK --start-period=1s CMD true")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_image_info_without_config() {
+        let json = r#"{"name":"busybox","tag":"latest","size":1,
+            "created_time":"2024-01-01 00:00:00","id":"abc"}"#;
+        let info: ImageInfo = serde_json::from_str(json).unwrap();
+        assert!(info.config.env.is_empty());
+    }
 }
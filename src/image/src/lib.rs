@@ -12,8 +12,23 @@ extern crate log;
 pub mod info;
 pub mod store;
 
-pub use info::ImageInfo;
+pub use info::{Healthcheck, ImageConfig, ImageInfo};
 pub use store::ImageStore;
 
+use std::path::PathBuf;
+
 /// Image storage directory
 pub const IMAGE_ROOT: &str = "/var/lib/rocker/images";
+
+/// Get the image storage directory.
+///
+/// This is [`IMAGE_ROOT`] for root. Unprivileged users can't write there,
+/// so their images are kept in the `images` directory of
+/// [`paths::data_dir`], next to their rootless containers.
+pub fn image_root() -> PathBuf {
+    if paths::is_rootless() {
+        paths::data_dir().join("images")
+    } else {
+        PathBuf::from(IMAGE_ROOT)
+    }
+}
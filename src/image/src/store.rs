@@ -6,8 +6,8 @@
 //! - Retrieving image metadata
 //! - Getting image rootfs path
 
-use crate::IMAGE_ROOT;
-use crate::info::ImageInfo;
+use crate::image_root;
+use crate::info::{ImageConfig, ImageInfo};
 use anyhow::{Context, Result};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -28,13 +28,20 @@ impl ImageStore {
     /// * `tar_file` - Path to the tar file to import
     /// * `name` - Image name
     /// * `tag` - Image tag (default: "latest")
+    /// * `config` - Defaults for containers created from the image
     ///
     /// # Example
     /// ```no_run
-    /// use image::ImageStore;
-    /// ImageStore::import("busybox.tar", "busybox", "latest").unwrap();
+    /// use image::{ImageConfig, ImageStore};
+    /// ImageStore::import("busybox.tar", "busybox", "latest", ImageConfig::default())
+    ///     .unwrap();
     /// ```
-    pub fn import(tar_file: &str, name: &str, tag: &str) -> Result<ImageInfo> {
+    pub fn import(
+        tar_file: &str,
+        name: &str,
+        tag: &str,
+        config: ImageConfig,
+    ) -> Result<ImageInfo> {
         info!("Importing image {}:{} from {}", name, tag, tar_file);
 
         // Validate tar file exists
@@ -49,7 +56,7 @@ impl ImageStore {
             .len();
 
         // Create image directory
-        let image_dir = PathBuf::from(IMAGE_ROOT).join(name).join(tag);
+        let image_dir = image_root().join(name).join(tag);
         fs::create_dir_all(&image_dir).with_context(|| {
             format!("Failed to create image directory {:?}", image_dir)
         })?;
@@ -78,8 +85,9 @@ impl ImageStore {
         let rootfs_size = Self::calculate_dir_size(&rootfs_dir)?;
 
         // Create image metadata
-        let image_info =
+        let mut image_info =
             ImageInfo::new(name.to_string(), tag.to_string(), rootfs_size);
+        image_info.config = config;
 
         // Save metadata
         let metadata_path = image_dir.join(Self::IMAGE_METADATA);
@@ -100,7 +108,7 @@ impl ImageStore {
     ///
     /// Returns a vector of ImageInfo for all imported images
     pub fn list_all() -> Result<Vec<ImageInfo>> {
-        let images_dir = Path::new(IMAGE_ROOT);
+        let images_dir = image_root();
 
         if !images_dir.exists() {
             return Ok(Vec::new());
@@ -109,7 +117,7 @@ impl ImageStore {
         let mut images = Vec::new();
 
         // Iterate over image name directories
-        for name_entry in fs::read_dir(images_dir).with_context(|| {
+        for name_entry in fs::read_dir(&images_dir).with_context(|| {
             format!("Failed to read images directory {:?}", images_dir)
         })? {
             let name_dir = name_entry?.path();
@@ -161,7 +169,7 @@ impl ImageStore {
     /// * `name` - Image name
     /// * `tag` - Image tag (default: "latest")
     pub fn load(name: &str, tag: &str) -> Result<ImageInfo> {
-        let image_dir = PathBuf::from(IMAGE_ROOT).join(name).join(tag);
+        let image_dir = image_root().join(name).join(tag);
         let metadata_path = image_dir.join(Self::IMAGE_METADATA);
 
         if !metadata_path.exists() {
@@ -186,10 +194,8 @@ impl ImageStore {
     /// * `name` - Image name
     /// * `tag` - Image tag (default: "latest")
     pub fn rootfs_path(name: &str, tag: &str) -> Result<PathBuf> {
-        let rootfs_dir = PathBuf::from(IMAGE_ROOT)
-            .join(name)
-            .join(tag)
-            .join(Self::ROOTFS_DIR);
+        let rootfs_dir =
+            image_root().join(name).join(tag).join(Self::ROOTFS_DIR);
 
         if !rootfs_dir.exists() {
             return Err(anyhow::anyhow!(
@@ -208,7 +214,7 @@ impl ImageStore {
     /// * `name` - Image name
     /// * `tag` - Image tag
     pub fn delete(name: &str, tag: &str) -> Result<()> {
-        let image_dir = PathBuf::from(IMAGE_ROOT).join(name).join(tag);
+        let image_dir = image_root().join(name).join(tag);
 
         if !image_dir.exists() {
             return Err(anyhow::anyhow!("Image {}:{} not found", name, tag));
@@ -219,7 +225,7 @@ impl ImageStore {
         })?;
 
         // Try to remove name directory if it's empty
-        let name_dir = PathBuf::from(IMAGE_ROOT).join(name);
+        let name_dir = image_root().join(name);
         if name_dir.exists() {
             let is_empty = name_dir
                 .read_dir()
@@ -0,0 +1,32 @@
+//! Directories shared by the rocker crates.
+//!
+//! Root keeps container state in `/var/run/rocker` and images in
+//! `/var/lib/rocker`. Unprivileged users can't write there, so their
+//! rootless containers and images live under [`data_dir`] instead.
+
+use nix::unistd::Uid;
+use std::path::PathBuf;
+
+/// Whether rocker runs without root privileges.
+pub fn is_rootless() -> bool {
+    !Uid::effective().is_root()
+}
+
+/// Base directory of rootless state and images, `$XDG_DATA_HOME/rocker`
+/// (by default `~/.local/share/rocker`).
+///
+/// Falls back to a per-user directory in the temporary directory if
+/// neither `XDG_DATA_HOME` nor `HOME` is set.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME")
+        && !dir.is_empty()
+    {
+        return PathBuf::from(dir).join("rocker");
+    }
+    match std::env::var_os("HOME") {
+        Some(home) if !home.is_empty() => {
+            PathBuf::from(home).join(".local/share/rocker")
+        }
+        _ => std::env::temp_dir().join(format!("rocker-{}", Uid::effective())),
+    }
+}
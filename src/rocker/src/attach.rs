@@ -0,0 +1,540 @@
+//! Attaching to running containers.
+//!
+//! The monitor of every container serves a unix socket in the container
+//! directory (see [`ContainerStore::attach_socket_path`]). `rocker attach`
+//! connects to it, receives the live container output and sends its stdin
+//! to the container, until the container exits or the user types the
+//! detach key sequence, which leaves the container running.
+//!
+//! Both directions of the socket carry frames made of a one byte
+//! [`FrameKind`], the payload length as a big-endian `u32`, and the
+//! payload, so that stdout and stderr of containers without a terminal
+//! stay apart and terminal resizes can be passed along with the input.
+
+use anyhow::{Context, Result};
+use container::ContainerStore;
+use container::tty::{self, RawMode};
+use nix::sys::signal::{SigSet, Signal};
+use nix::unistd::isatty;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+/// Detach key sequence used unless `--detach-keys` is given.
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// Largest frame payload accepted.
+pub const MAX_PAYLOAD_LEN: usize = 64 * 1024;
+
+/// How long writing output to a client may block before the client is
+/// dropped, since the monitor stops capturing the container output
+/// meanwhile.
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Kind of a frame on the attach socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Input for the container, sent by `rocker attach`
+    Stdin = 0,
+    /// Container stdout, or terminal output
+    Stdout = 1,
+    /// Container stderr
+    Stderr = 2,
+    /// New terminal size, rows and columns as big-endian `u16`
+    Resize = 3,
+    /// End of the input, sent by `rocker attach` once its stdin is closed
+    CloseStdin = 4,
+}
+
+impl FrameKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(FrameKind::Stdin),
+            1 => Some(FrameKind::Stdout),
+            2 => Some(FrameKind::Stderr),
+            3 => Some(FrameKind::Resize),
+            4 => Some(FrameKind::CloseStdin),
+            _ => None,
+        }
+    }
+}
+
+/// Write a frame.
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    kind: FrameKind,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(kind as u8);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+/// Read a frame, `None` at the end of the stream.
+///
+/// Frames with a payload larger than [`MAX_PAYLOAD_LEN`] are rejected.
+pub fn read_frame<R: Read>(
+    reader: &mut R,
+) -> io::Result<Option<(FrameKind, Vec<u8>)>> {
+    let mut header = [0u8; 5];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let kind = FrameKind::from_u8(header[0]).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "invalid frame kind")
+    })?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]])
+        as usize;
+    if len > MAX_PAYLOAD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame payload of {} bytes is too large", len),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((kind, payload)))
+}
+
+/// Clients attached to a container, shared by the output threads of its
+/// monitor.
+#[derive(Clone, Default)]
+pub struct Clients(Arc<Mutex<Vec<UnixStream>>>);
+
+impl Clients {
+    /// Send container output to every client, dropping the clients that
+    /// went away or don't keep up with the output.
+    ///
+    /// Dropped clients are disconnected, which ends their `rocker attach`.
+    pub fn broadcast(&self, kind: FrameKind, data: &[u8]) {
+        if let Ok(mut clients) = self.0.lock() {
+            clients.retain(|client| {
+                let mut client: &UnixStream = client;
+                if write_frame(&mut client, kind, data).is_ok() {
+                    return true;
+                }
+                let _ = client.shutdown(Shutdown::Both);
+                false
+            });
+        }
+    }
+
+    fn add(&self, client: UnixStream) -> io::Result<()> {
+        client.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+        if let Ok(mut clients) = self.0.lock() {
+            clients.push(client);
+        }
+        Ok(())
+    }
+}
+
+/// Input of a container, shared with the threads of the attached clients.
+///
+/// The input is replaced when the container is restarted, and the clients
+/// stay attached.
+#[derive(Clone, Default)]
+pub struct Input(Arc<Mutex<Option<Arc<File>>>>);
+
+impl Input {
+    /// Replace the container stdin or terminal master, `None` to ignore
+    /// client input.
+    pub fn set(&self, input: Option<File>) {
+        if let Ok(mut current) = self.0.lock() {
+            *current = input.map(Arc::new);
+        }
+    }
+
+    fn get(&self) -> Option<Arc<File>> {
+        self.0.lock().ok().and_then(|current| current.clone())
+    }
+
+    /// Close the stdin pipe of the container, so that its command reads the
+    /// end of the input. A terminal master stays open.
+    fn close(&self) {
+        if let Ok(mut current) = self.0.lock()
+            && current.as_ref().is_some_and(|input| {
+                !isatty(input.as_raw_fd()).unwrap_or(false)
+            })
+        {
+            *current = None;
+        }
+    }
+}
+
+/// Serve the attach socket of a container.
+///
+/// Accepted clients are added to `clients` and their input is written to
+/// `input`. Resize frames are only applied if the input is a terminal.
+///
+/// # Arguments
+///
+/// * `socket_path` - Path of the socket, replaced if it already exists
+/// * `input` - Container input
+/// * `clients` - Clients receiving the container output
+pub fn serve(socket_path: &Path, input: Input, clients: Clients) -> Result<()> {
+    let _ = fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path).with_context(|| {
+        format!("Failed to bind attach socket {}", socket_path.display())
+    })?;
+
+    spawn(move || {
+        for client in listener.incoming().flatten() {
+            match client.try_clone().and_then(|output| clients.add(output)) {
+                Ok(()) => {}
+                Err(e) => {
+                    warn!("Failed to accept attach client: {}", e);
+                    continue;
+                }
+            }
+            let input = input.clone();
+            spawn(move || handle_client_input(client, input));
+        }
+    });
+    Ok(())
+}
+
+fn handle_client_input(mut client: UnixStream, input: Input) {
+    while let Ok(Some((kind, payload))) = read_frame(&mut client) {
+        if kind == FrameKind::CloseStdin {
+            input.close();
+            continue;
+        }
+        let Some(input) = input.get() else {
+            continue;
+        };
+        match kind {
+            // Fails while a restarted container is down, the input of the
+            // next one is written to again
+            FrameKind::Stdin => {
+                let _ = (&*input).write_all(&payload);
+            }
+            FrameKind::Resize if payload.len() == 4 => {
+                let rows = u16::from_be_bytes([payload[0], payload[1]]);
+                let columns = u16::from_be_bytes([payload[2], payload[3]]);
+                // Fails for the stdin pipe of a container without terminal
+                let _ = tty::set_window_size(input.as_raw_fd(), rows, columns);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Detach key sequence.
+///
+/// Parsed from a comma separated list of keys, each either a single
+/// character or `ctrl-<key>` with `<key>` one of `a`-`z`, `@`, `[`, `\`,
+/// `]`, `^` or `_`.
+///
+/// # Example
+///
+/// ```text
+/// ctrl-p,ctrl-q
+/// ctrl-x,x
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachKeys(Vec<u8>);
+
+impl DetachKeys {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for key in spec.split(',') {
+            let byte = match key.to_ascii_lowercase().strip_prefix("ctrl-") {
+                Some(name) if name.len() == 1 => match name.as_bytes()[0] {
+                    c @ b'a'..=b'z' => c - b'a' + 1,
+                    c @ (b'@' | b'[' | b'\\' | b']' | b'^' | b'_') => c & 0x1f,
+                    _ => return Err(invalid_detach_key(key)),
+                },
+                Some(_) => return Err(invalid_detach_key(key)),
+                None if key.len() == 1 && key.is_ascii() => key.as_bytes()[0],
+                None => return Err(invalid_detach_key(key)),
+            };
+            keys.push(byte);
+        }
+        Ok(DetachKeys(keys))
+    }
+}
+
+fn invalid_detach_key(key: &str) -> anyhow::Error {
+    anyhow::anyhow!("Invalid detach key {:?}", key)
+}
+
+/// Recognizes the detach sequence in the input stream.
+///
+/// Bytes that could start the sequence are held back until it is clear
+/// whether the sequence follows, and passed on otherwise.
+struct DetachScanner {
+    keys: Vec<u8>,
+    /// Length of the longest proper prefix of `keys[..=i]` that is also a
+    /// suffix of it, where matching resumes when the sequence breaks off
+    fallback: Vec<usize>,
+    matched: usize,
+}
+
+impl DetachScanner {
+    fn new(keys: &DetachKeys) -> Self {
+        let keys = keys.0.clone();
+        let mut fallback = vec![0; keys.len()];
+        let mut len = 0;
+        for i in 1..keys.len() {
+            while len > 0 && keys[i] != keys[len] {
+                len = fallback[len - 1];
+            }
+            if keys[i] == keys[len] {
+                len += 1;
+            }
+            fallback[i] = len;
+        }
+        DetachScanner {
+            keys,
+            fallback,
+            matched: 0,
+        }
+    }
+
+    /// Scan `input`, returning the bytes to forward and whether the
+    /// sequence is complete.
+    fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut forward = Vec::new();
+        for &byte in input {
+            // Held back bytes that can't start the sequence anymore are
+            // passed on
+            while self.matched > 0 && byte != self.keys[self.matched] {
+                let next = self.fallback[self.matched - 1];
+                forward.extend_from_slice(&self.keys[..self.matched - next]);
+                self.matched = next;
+            }
+            if byte != self.keys[self.matched] {
+                forward.push(byte);
+                continue;
+            }
+            self.matched += 1;
+            if self.matched == self.keys.len() {
+                return (forward, true);
+            }
+        }
+        (forward, false)
+    }
+}
+
+/// Why `rocker attach` returned.
+enum AttachEnd {
+    Detached,
+    ContainerExited,
+}
+
+/// Attach the current terminal to a running container.
+///
+/// The terminal is switched to raw mode for containers with a terminal,
+/// whose size then follows the size of the user's terminal.
+pub fn attach(container_name: &str, detach_keys: &DetachKeys) -> Result<()> {
+    let info = ContainerStore::load(container_name).with_context(|| {
+        format!("Failed to load container {}", container_name)
+    })?;
+    if info.status != container::ContainerStatus::Running {
+        return Err(anyhow::anyhow!(
+            "Container {} is not running",
+            container_name
+        ));
+    }
+
+    let socket_path = ContainerStore::attach_socket_path(container_name);
+    let socket = UnixStream::connect(&socket_path).with_context(|| {
+        format!("Failed to attach to container {}", container_name)
+    })?;
+    let writer = Arc::new(Mutex::new(
+        socket
+            .try_clone()
+            .context("Failed to clone attach socket")?,
+    ));
+
+    let raw_mode = if info.tty { RawMode::enable(0)? } else { None };
+    if raw_mode.is_some() {
+        forward_window_size(writer.clone())?;
+    }
+
+    let (done, end) = mpsc::channel();
+
+    let output_done = done.clone();
+    spawn(move || {
+        let mut socket = socket;
+        while let Ok(Some((kind, payload))) = read_frame(&mut socket) {
+            let _ = match kind {
+                FrameKind::Stderr => {
+                    let mut stderr = io::stderr();
+                    stderr.write_all(&payload).and_then(|_| stderr.flush())
+                }
+                _ => {
+                    let mut stdout = io::stdout();
+                    stdout.write_all(&payload).and_then(|_| stdout.flush())
+                }
+            };
+        }
+        let _ = output_done.send(AttachEnd::ContainerExited);
+    });
+
+    let mut scanner = DetachScanner::new(detach_keys);
+    let tty = info.tty;
+    spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buffer = [0u8; 1024];
+        loop {
+            let n = match stdin.read(&mut buffer) {
+                // The container sees the end of the input as well, and the
+                // output is still received until it exits
+                Ok(0) => {
+                    if !tty && let Ok(mut writer) = writer.lock() {
+                        let _ = write_frame(
+                            &mut *writer,
+                            FrameKind::CloseStdin,
+                            &[],
+                        );
+                    }
+                    return;
+                }
+                Err(_) => return,
+                Ok(n) => n,
+            };
+            let (forward, detached) = scanner.feed(&buffer[..n]);
+            if !forward.is_empty()
+                && let Ok(mut writer) = writer.lock()
+                && write_frame(&mut *writer, FrameKind::Stdin, &forward)
+                    .is_err()
+            {
+                return;
+            }
+            if detached {
+                let _ = done.send(AttachEnd::Detached);
+                return;
+            }
+        }
+    });
+
+    let end = end.recv().unwrap_or(AttachEnd::ContainerExited);
+    drop(raw_mode);
+    if let AttachEnd::Detached = end {
+        eprintln!("\nDetached from container {}", container_name);
+    }
+    Ok(())
+}
+
+/// Send the size of the user's terminal now and on every `SIGWINCH`.
+///
+/// Like in the monitor, `SIGWINCH` has to be blocked before the other
+/// threads are spawned.
+fn forward_window_size(writer: Arc<Mutex<UnixStream>>) -> Result<()> {
+    let send_size = move || {
+        if let Some((rows, columns)) = tty::window_size(0)
+            && let Ok(mut writer) = writer.lock()
+        {
+            let mut payload = rows.to_be_bytes().to_vec();
+            payload.extend_from_slice(&columns.to_be_bytes());
+            let _ = write_frame(&mut *writer, FrameKind::Resize, &payload);
+        }
+    };
+    send_size();
+
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGWINCH);
+    signals.thread_block().context("Failed to block SIGWINCH")?;
+    spawn(move || {
+        while signals.wait().is_ok() {
+            send_size();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, FrameKind::Stderr, b"oops").unwrap();
+        write_frame(&mut buf, FrameKind::Stdout, b"").unwrap();
+        write_frame(&mut buf, FrameKind::CloseStdin, b"").unwrap();
+
+        let mut reader = buf.as_slice();
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some((FrameKind::Stderr, b"oops".to_vec()))
+        );
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some((FrameKind::Stdout, Vec::new()))
+        );
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some((FrameKind::CloseStdin, Vec::new()))
+        );
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut buf = vec![FrameKind::Stdin as u8];
+        buf.extend_from_slice(&u32::MAX.to_be_bytes());
+        let err = read_frame(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut buf = Vec::new();
+        let payload = vec![b'x'; MAX_PAYLOAD_LEN];
+        write_frame(&mut buf, FrameKind::Stdout, &payload).unwrap();
+        assert_eq!(
+            read_frame(&mut buf.as_slice()).unwrap(),
+            Some((FrameKind::Stdout, payload))
+        );
+    }
+
+    #[test]
+    fn test_parse_detach_keys() {
+        assert_eq!(
+            DetachKeys::parse(DEFAULT_DETACH_KEYS).unwrap(),
+            DetachKeys(vec![0x10, 0x11])
+        );
+        assert_eq!(
+            DetachKeys::parse("ctrl-@,x,ctrl-[").unwrap(),
+            DetachKeys(vec![0, b'x', 0x1b])
+        );
+        assert!(DetachKeys::parse("ctrl-1").is_err());
+        assert!(DetachKeys::parse("xy").is_err());
+        assert!(DetachKeys::parse("").is_err());
+    }
+
+    #[test]
+    fn test_detach_scanner() {
+        let keys = DetachKeys::parse(DEFAULT_DETACH_KEYS).unwrap();
+        let mut scanner = DetachScanner::new(&keys);
+
+        assert_eq!(scanner.feed(b"ls\r"), (b"ls\r".to_vec(), false));
+        // A held back ctrl-p is passed on when the sequence breaks off
+        assert_eq!(scanner.feed(b"a\x10"), (b"a".to_vec(), false));
+        assert_eq!(scanner.feed(b"b"), (b"\x10b".to_vec(), false));
+        assert_eq!(scanner.feed(b"\x10\x10"), (b"\x10".to_vec(), false));
+        assert_eq!(scanner.feed(b"\x11rest"), (Vec::new(), true));
+    }
+
+    #[test]
+    fn test_detach_scanner_repeated_prefix() {
+        let keys = DetachKeys::parse("a,a,b").unwrap();
+        let mut scanner = DetachScanner::new(&keys);
+        assert_eq!(scanner.feed(b"aaab"), (b"a".to_vec(), true));
+
+        let mut scanner = DetachScanner::new(&keys);
+        assert_eq!(scanner.feed(b"aa"), (Vec::new(), false));
+        assert_eq!(scanner.feed(b"ac"), (b"aaac".to_vec(), false));
+        assert_eq!(scanner.feed(b"aab"), (Vec::new(), true));
+    }
+}
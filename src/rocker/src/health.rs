@@ -0,0 +1,157 @@
+//! Health check probes.
+//!
+//! The container monitor runs the health check of a container in a thread
+//! of its own. Each probe is a `rocker exec` of `/bin/sh -c <cmd>`, which
+//! enters the container namespaces and applies the container user, seccomp
+//! profile and capabilities. It has to be a child process: the monitor is
+//! multi-threaded, and the kernel doesn't let a thread that shares its
+//! filesystem attributes join another mount namespace.
+//!
+//! The results are recorded in the container metadata (see
+//! [`ContainerStore::record_health`]).
+
+use anyhow::{Context, Result};
+use container::health::{HealthConfig, HealthProbe, MAX_OUTPUT_LEN};
+use container::info::ContainerInfo;
+use container::{ContainerStatus, ContainerStore};
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+use std::io::{self, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{JoinHandle, sleep, spawn};
+use std::time::{Duration, Instant};
+
+/// How often a running probe is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long the output of a finished probe is waited for. Processes it
+/// left running in the background may keep its output open.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Thread probing a running container.
+pub struct HealthChecker {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl HealthChecker {
+    /// Probe the container every `config.interval`, starting one interval
+    /// from now.
+    pub fn start(container_name: &str, config: HealthConfig) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let container_name = container_name.to_string();
+        let handle = spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(config.interval)
+            {
+                // The processes of a paused container can't answer
+                if ContainerStore::load(&container_name)
+                    .is_ok_and(|info| info.status == ContainerStatus::Paused)
+                {
+                    continue;
+                }
+                let probe = probe(&container_name, &config);
+                debug!(
+                    "health check of {} exited with {}",
+                    container_name, probe.exit_code
+                );
+                if let Err(e) = ContainerStore::record_health(
+                    &container_name,
+                    probe,
+                    config.retries,
+                ) {
+                    warn!(
+                        "Failed to record health of {}: {}",
+                        container_name, e
+                    );
+                }
+            }
+        });
+        HealthChecker { stop, handle }
+    }
+
+    /// Stop probing, after the running probe if any.
+    pub fn stop(self) {
+        drop(self.stop);
+        let _ = self.handle.join();
+    }
+}
+
+/// Run a probe.
+fn probe(container_name: &str, config: &HealthConfig) -> HealthProbe {
+    let start = ContainerInfo::current_time();
+    let (exit_code, output) = match run_probe(container_name, config) {
+        Ok(result) => result,
+        Err(e) => (-1, format!("{:#}", e)),
+    };
+    HealthProbe {
+        start,
+        end: ContainerInfo::current_time(),
+        exit_code,
+        output,
+    }
+}
+
+/// Run the health check command in the container, killing it after the
+/// timeout.
+///
+/// Returns its exit code, 128 plus the signal number if it was killed by a
+/// signal or -1 if it timed out, and its output.
+fn run_probe(
+    container_name: &str,
+    config: &HealthConfig,
+) -> Result<(i32, String)> {
+    let (mut reader, writer) = io::pipe().context("Failed to create pipe")?;
+    let mut child = Command::new("/proc/self/exe")
+        .args(["exec", container_name, "/bin/sh", "-c", &config.command])
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::null())
+        .stdout(writer.try_clone().context("Failed to clone pipe")?)
+        .stderr(writer)
+        // A group of its own, so that the command it runs in the
+        // container can be killed along with it
+        .process_group(0)
+        .spawn()
+        .context("Failed to start the health check")?;
+
+    let (output_sender, output) = mpsc::channel();
+    spawn(move || {
+        let mut output = Vec::new();
+        let _ = (&mut reader)
+            .take(MAX_OUTPUT_LEN as u64)
+            .read_to_end(&mut output);
+        let _ = output_sender.send(output);
+        // Drain the rest so that the command doesn't block on a full pipe
+        let _ = io::copy(&mut reader, &mut io::sink());
+    });
+
+    let deadline = Instant::now() + config.timeout;
+    let exit_code = loop {
+        if let Some(status) = child
+            .try_wait()
+            .context("Failed to wait for the health check")?
+        {
+            break status
+                .code()
+                .or_else(|| status.signal().map(|signal| 128 + signal))
+                .unwrap_or(-1);
+        }
+        if Instant::now() >= deadline {
+            let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+            let _ = child.wait();
+            return Ok((
+                -1,
+                format!(
+                    "Health check exceeded timeout ({})",
+                    container::health::format_duration(config.timeout)
+                ),
+            ));
+        }
+        sleep(POLL_INTERVAL);
+    };
+
+    let output = output.recv_timeout(OUTPUT_GRACE).unwrap_or_default();
+    Ok((exit_code, String::from_utf8_lossy(&output).into_owned()))
+}
@@ -6,14 +6,34 @@ extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
+mod attach;
+mod health;
+mod monitor;
+
 use anyhow::{Context, Result};
 use cgroups::cgroup_manager::CgroupManager;
 use cgroups::subsystems::subsystem::ResourceConfig;
 use clap::{Parser, Subcommand};
-use container::{Container, ContainerInfo, ContainerStatus, ContainerStore};
-use image::ImageStore;
+use container::hooks::{Hook, Hooks};
+use container::spec::LinuxNamespace;
+use container::tty::{self, RawMode};
+use container::{
+    CapabilitySet, Health, HealthConfig, Spec, User, capabilities, env,
+    hostname, restart, rootless, spec, system_paths,
+};
+use container::{
+    Container, ContainerInfo, ContainerStatus, ContainerStore, InitConfig,
+    RestartPolicy, SeccompFilter, SeccompProfile, Tmpfs, Volume, Workspace,
+};
+use image::{Healthcheck, ImageConfig, ImageStore};
+use nix::sys::stat::Mode;
+use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 /// Rocker - A simple container runtime implemented in Rust
 #[derive(Parser, Debug)]
@@ -27,23 +47,85 @@ struct Cli {
 }
 
 /// Available rocker commands
+// Parsed once per invocation, so the size of `Run` doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Run a container
     ///
     /// Example:
     /// sudo RUST_LOG=trace ./rocker run --image busybox /bin/sh
-    /// sudo RUST_LOG=trace ./rocker run --tty --image busybox:latest "ls -l"
-    /// sudo ./rocker run --image busybox /bin/sleep 1000
+    /// sudo RUST_LOG=trace ./rocker run --tty --image busybox:latest ls -l
+    /// sudo ./rocker run -d --image busybox /bin/sleep 1000
+    /// sudo ./rocker run -v /tmp/data:/data:ro --image busybox ls /data
+    /// sudo ./rocker run -e GREETING=hello --env-file app.env --image busybox env
+    /// sudo ./rocker run --security-opt seccomp=profile.json --image busybox sh
+    /// sudo ./rocker run --cap-drop ALL --cap-add NET_BIND_SERVICE --image busybox sh
+    /// sudo ./rocker run --hostname web --domainname example.com --image busybox hostname
+    /// sudo ./rocker run --read-only --tmpfs /tmp:size=64m --image busybox sh
+    /// sudo ./rocker run -d --init --image busybox sleep 1000
+    /// sudo ./rocker run -d --restart on-failure:5 --image busybox sh
+    /// sudo ./rocker run -d --bundle /containers/web
     Run {
         /// Image to run (e.g., busybox, busybox:latest)
         #[arg(long)]
         image: Option<String>,
 
+        /// Run the container described by the config.json of an OCI
+        /// bundle instead of an image and options (see rocker spec)
+        #[arg(
+            short = 'b',
+            long,
+            conflicts_with_all = [
+                "image", "tty", "memory", "cpushare", "cpuset", "hostname",
+                "domainname", "workdir", "user", "volumes", "tmpfs",
+                "read_only", "env", "env_files", "security_opts", "cap_add",
+                "cap_drop", "command",
+            ]
+        )]
+        bundle: Option<PathBuf>,
+
         /// Enable tty (allocate pseudo-terminal)
         #[arg(short = 't', long)]
         tty: bool,
 
+        /// Run a built-in init as PID 1, which forwards signals to the
+        /// command and reaps zombie processes
+        #[arg(long)]
+        init: bool,
+
+        /// Run the container in the background and print its ID. With
+        /// --tty the terminal is kept open by the container monitor.
+        #[arg(short = 'd', long)]
+        detach: bool,
+
+        /// Restart policy of a detached container:
+        /// no|on-failure[:max-retries]|always|unless-stopped
+        #[arg(long, default_value = "no")]
+        restart: String,
+
+        /// Health check, a shell command run in the container that exits
+        /// with 0 when it is healthy (default: the image's)
+        #[arg(long = "health-cmd")]
+        health_cmd: Option<String>,
+
+        /// Time between two health checks, e.g. 30s or 1m30s (default: 30s)
+        #[arg(long = "health-interval")]
+        health_interval: Option<String>,
+
+        /// Time after which a health check fails (default: 30s)
+        #[arg(long = "health-timeout")]
+        health_timeout: Option<String>,
+
+        /// Consecutive failed health checks making the container unhealthy
+        /// (default: 3)
+        #[arg(long = "health-retries", value_parser = clap::value_parser!(u32).range(1..))]
+        health_retries: Option<u32>,
+
+        /// Disable the health check of the image
+        #[arg(long = "no-healthcheck", conflicts_with = "health_cmd")]
+        no_healthcheck: bool,
+
         /// Memory limit (e.g., 100m, 1g)
         #[arg(short = 'm', long)]
         memory: Option<String>,
@@ -56,20 +138,175 @@ enum Commands {
         #[arg(long)]
         cpuset: Option<String>,
 
+        /// Container hostname (default: the container ID)
+        #[arg(long)]
+        hostname: Option<String>,
+
+        /// Container NIS domainname
+        #[arg(long)]
+        domainname: Option<String>,
+
+        /// Working directory of the command, created if missing
+        /// (default: /)
+        #[arg(short = 'w', long)]
+        workdir: Option<String>,
+
+        /// User of the command (name|uid[:group|gid]), resolved in the
+        /// container's /etc/passwd and /etc/group (default: root)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Bind mount a volume (host_path:container_path[:ro|rw]),
+        /// can be repeated
+        #[arg(short = 'v', long = "volume")]
+        volumes: Vec<String>,
+
+        /// Mount a tmpfs (container_path[:size=..,mode=..]), can be
+        /// repeated
+        #[arg(long = "tmpfs")]
+        tmpfs: Vec<String>,
+
+        /// Mount the container rootfs read-only
+        #[arg(long = "read-only")]
+        read_only: bool,
+
+        /// Set an environment variable (KEY=VALUE, or KEY to take the
+        /// value from the host), can be repeated
+        #[arg(short = 'e', long = "env")]
+        env: Vec<String>,
+
+        /// Read environment variables from a file, can be repeated
+        #[arg(long = "env-file")]
+        env_files: Vec<PathBuf>,
+
+        /// Security options: seccomp=<profile.json> to use a Docker
+        /// compatible seccomp profile, seccomp=unconfined to disable
+        /// syscall filtering, mask=<path>[:<path>...] to hide more paths,
+        /// unmask=<path>[:<path>...] or unmask=ALL to expose masked or
+        /// read-only paths, systempaths=unconfined as unmask=ALL
+        #[arg(long = "security-opt")]
+        security_opts: Vec<String>,
+
+        /// Add a Linux capability to the default set (e.g. NET_ADMIN, or
+        /// ALL), can be repeated
+        #[arg(long = "cap-add")]
+        cap_add: Vec<String>,
+
+        /// Drop a Linux capability from the default set (e.g. NET_RAW, or
+        /// ALL), can be repeated
+        #[arg(long = "cap-drop")]
+        cap_drop: Vec<String>,
+
         /// Command to run in the container (with arguments)
-        #[arg(required = true, num_args = 1..)]
+        #[arg(
+            required_unless_present = "bundle",
+            num_args = 1..,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
         command: Vec<String>,
     },
 
+    /// Write the config.json of an OCI bundle, to be completed with a
+    /// rootfs directory
+    ///
+    /// Example:
+    /// sudo rocker spec --bundle /containers/web
+    /// sudo rocker spec -t -b /containers/web /bin/sh -l
+    Spec {
+        /// Bundle directory
+        #[arg(short = 'b', long, default_value = ".")]
+        bundle: PathBuf,
+
+        /// Give the command a terminal
+        #[arg(short = 't', long)]
+        tty: bool,
+
+        /// Command to run in the container (default: sh)
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Create a container from an OCI bundle, set up and waiting for
+    /// rocker start to run its command
+    ///
+    /// Example:
+    /// sudo rocker create --bundle /containers/web web
+    /// sudo rocker start web
+    Create {
+        /// Bundle directory
+        #[arg(short = 'b', long, default_value = ".")]
+        bundle: PathBuf,
+
+        /// Write the PID of the container process to this file
+        #[arg(long = "pid-file")]
+        pid_file: Option<PathBuf>,
+
+        /// Container ID
+        #[arg(required = true)]
+        container_id: String,
+    },
+
+    /// Run the command of a created container
+    Start {
+        /// Container ID
+        #[arg(required = true)]
+        container_id: String,
+    },
+
+    /// Print the OCI state of a container as JSON
+    State {
+        /// Container ID
+        #[arg(required = true)]
+        container_id: String,
+    },
+
+    /// Send a signal to the process of a container
+    ///
+    /// Example:
+    /// sudo rocker kill web
+    /// sudo rocker kill -s SIGHUP web
+    /// sudo rocker kill --all web KILL
+    Kill {
+        /// Signal name or number, SIGTERM if not given
+        #[arg(
+            short = 's',
+            long = "signal",
+            value_name = "SIGNAL",
+            conflicts_with = "signal"
+        )]
+        signal_option: Option<String>,
+
+        /// Send the signal to every process of the container
+        #[arg(short = 'a', long)]
+        all: bool,
+
+        /// Container ID
+        #[arg(required = true)]
+        container_id: String,
+
+        /// Signal name or number, as runc takes it
+        signal: Option<String>,
+    },
+
+    /// Delete a stopped container
+    Delete {
+        /// Kill the container first if it is created or running
+        #[arg(short = 'f', long)]
+        force: bool,
+
+        /// Container ID
+        #[arg(required = true)]
+        container_id: String,
+    },
+
     /// Initialize container (internal use only)
     ///
     /// WARNING: This command cannot be called from external,
     /// it is only used internally by the container runtime.
-    Init {
-        /// Command to initialize
-        #[arg(required = true)]
-        command: String,
-    },
+    /// The command to run and the container setup are read from
+    /// the init pipe set up by `rocker run`.
+    Init,
 
     /// List all containers
     Ps,
@@ -81,8 +318,46 @@ enum Commands {
         container_name: String,
     },
 
+    /// Print the metadata of a container as JSON
+    ///
+    /// Example:
+    /// sudo rocker inspect <container>
+    /// sudo rocker inspect -f exitCode <container>
+    Inspect {
+        /// Print only this field, e.g. status, exitCode, signal, oomKilled
+        /// or finishTime
+        #[arg(short = 'f', long)]
+        format: Option<String>,
+
+        /// Container name
+        #[arg(required = true)]
+        container_name: String,
+    },
+
     /// Stop a running container
+    ///
+    /// Example:
+    /// sudo rocker stop web
+    /// sudo rocker stop -t 30 web
     Stop {
+        /// Seconds to wait for the container to exit before killing it
+        #[arg(short = 't', long = "time", default_value_t = 10)]
+        time: u64,
+
+        /// Container name
+        #[arg(required = true)]
+        container_name: String,
+    },
+
+    /// Freeze all processes of a running container
+    Pause {
+        /// Container name
+        #[arg(required = true)]
+        container_name: String,
+    },
+
+    /// Thaw the processes of a paused container
+    Unpause {
         /// Container name
         #[arg(required = true)]
         container_name: String,
@@ -111,16 +386,48 @@ enum Commands {
     /// Example:
     /// sudo rocker exec <container> /bin/ps aux
     /// sudo rocker exec <container> ls -la /
+    /// sudo rocker exec -u nobody -w /tmp <container> id
     Exec {
+        /// Working directory of the command (default: the one of the
+        /// container)
+        #[arg(short = 'w', long)]
+        workdir: Option<String>,
+
+        /// User of the command, name|uid[:group|gid] (default: the user of
+        /// the container)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
         /// Container name
         #[arg(required = true)]
         container_name: String,
 
         /// Command to execute (with arguments)
-        #[arg(required = true, num_args = 1..)]
+        #[arg(
+            required = true,
+            num_args = 1..,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
         command: Vec<String>,
     },
 
+    /// Attach to the input and output of a running container
+    ///
+    /// Example:
+    /// sudo rocker attach <container>
+    /// sudo rocker attach --detach-keys ctrl-x,x <container>
+    Attach {
+        /// Container name
+        #[arg(required = true)]
+        container_name: String,
+
+        /// Key sequence detaching from the container, which keeps running
+        /// (comma separated, e.g. ctrl-p,ctrl-q)
+        #[arg(long = "detach-keys", default_value = attach::DEFAULT_DETACH_KEYS)]
+        detach_keys: String,
+    },
+
     /// List all images
     Images,
 
@@ -129,6 +436,9 @@ enum Commands {
     /// Example:
     /// sudo rocker import busybox.tar busybox
     /// sudo rocker import alpine.tar alpine:3.18
+    /// sudo rocker import -c "ENV LANG=C.UTF-8" alpine.tar alpine
+    /// sudo rocker import -c "HEALTHCHECK --interval=10s CMD pidof nginx" \
+    ///     nginx.tar nginx
     Import {
         /// Tar file to import
         #[arg(required = true)]
@@ -137,6 +447,11 @@ enum Commands {
         /// Image name (optionally with tag, e.g., "busybox:latest")
         #[arg(required = true)]
         image: String,
+
+        /// Apply a Dockerfile instruction to the image (only ENV and
+        /// HEALTHCHECK are supported), can be repeated
+        #[arg(short = 'c', long = "change")]
+        changes: Vec<String>,
     },
 }
 
@@ -157,45 +472,521 @@ fn run_command(command: Commands) -> Result<()> {
     match command {
         Commands::Run {
             image,
+            bundle,
             tty,
+            init,
+            detach,
+            restart,
+            health_cmd,
+            health_interval,
+            health_timeout,
+            health_retries,
+            no_healthcheck,
             memory,
             cpushare,
             cpuset,
+            hostname,
+            domainname,
+            workdir,
+            user,
+            volumes,
+            tmpfs,
+            read_only,
+            env,
+            env_files,
+            security_opts,
+            cap_add,
+            cap_drop,
             command,
         } => {
+            let restart = RestartPolicy::parse(&restart)?;
+            if restart != RestartPolicy::No && !detach {
+                return Err(anyhow::anyhow!(
+                    "--restart {} requires --detach",
+                    restart
+                ));
+            }
+            let health = HealthOptions {
+                command: health_cmd,
+                interval: health_interval
+                    .as_deref()
+                    .map(container::health::parse_duration)
+                    .transpose()?,
+                timeout: health_timeout
+                    .as_deref()
+                    .map(container::health::parse_duration)
+                    .transpose()?,
+                retries: health_retries,
+                disabled: no_healthcheck,
+            };
+            if let Some(bundle) = bundle {
+                run(RunOptions::from_bundle(
+                    &bundle, init, detach, restart, health,
+                )?);
+                return Ok(());
+            }
+
             let res = ResourceConfig {
                 memory_limit: Some(memory.unwrap_or(String::from("1024m"))),
                 cpu_set: Some(cpushare.unwrap_or(String::from("1-2"))),
                 cpu_shares: Some(cpuset.unwrap_or(String::from("1024"))),
             };
-            // Join command arguments with spaces
-            let cmd_str = command.join(" ");
-            run(image.as_deref(), tty, &cmd_str, &res);
+            let volumes = volumes
+                .iter()
+                .map(|spec| Volume::parse(spec))
+                .collect::<Result<Vec<_>>>()?;
+            let tmpfs = tmpfs
+                .iter()
+                .map(|spec| Tmpfs::parse(spec))
+                .collect::<Result<Vec<_>>>()?;
+
+            // Env files first so that -e takes precedence
+            let mut user_env = Vec::new();
+            for path in &env_files {
+                user_env.extend(env::parse_env_file(path)?);
+            }
+            user_env.extend(env.iter().filter_map(|var| env::resolve_var(var)));
+
+            let security = SecurityOptions::parse(&security_opts)?;
+            let capabilities = capabilities::resolve(&cap_add, &cap_drop)?;
+            for name in hostname.iter().chain(&domainname) {
+                hostname::validate(name)?;
+            }
+            validate_workdir(workdir.as_deref())?;
+
+            run(RunOptions {
+                id: None,
+                created: false,
+                pid_file: None,
+                image,
+                tty,
+                init,
+                detach,
+                restart,
+                health,
+                command,
+                resources: res,
+                hostname,
+                domainname,
+                workdir,
+                user,
+                volumes,
+                tmpfs,
+                read_only,
+                env: user_env,
+                security,
+                capabilities: Some(capabilities),
+                bundle: None,
+                rootfs: None,
+                namespaces: default_namespaces(),
+                hooks: Hooks::default(),
+            });
             Ok(())
         }
-        Commands::Init { command } => init(&command),
+        Commands::Spec {
+            bundle,
+            tty,
+            command,
+        } => spec_bundle(&bundle, tty, command),
+        Commands::Create {
+            bundle,
+            pid_file,
+            container_id,
+        } => {
+            ContainerInfo::validate_id(&container_id)?;
+            if ContainerStore::container_dir(&container_id).exists() {
+                return Err(anyhow::anyhow!(
+                    "Container {} already exists",
+                    container_id
+                ));
+            }
+            let mut options = RunOptions::from_bundle(
+                &bundle,
+                false,
+                true,
+                RestartPolicy::No,
+                HealthOptions::default(),
+            )?;
+            options.id = Some(container_id);
+            options.created = true;
+            options.pid_file = pid_file;
+            run(options);
+            Ok(())
+        }
+        Commands::Start { container_id } => start_container(&container_id),
+        Commands::State { container_id } => print_state(&container_id),
+        Commands::Kill {
+            signal_option,
+            all,
+            container_id,
+            signal,
+        } => kill_container(
+            &container_id,
+            signal_option.or(signal).as_deref().unwrap_or("SIGTERM"),
+            all,
+        ),
+        Commands::Delete {
+            force,
+            container_id,
+        } => delete_container(&container_id, force),
+        Commands::Init => init(),
         Commands::Ps => list_containers(),
         Commands::Logs { container_name } => log_container(&container_name),
-        Commands::Stop { container_name } => stop_container(&container_name),
+        Commands::Inspect {
+            format,
+            container_name,
+        } => inspect_container(&container_name, format.as_deref()),
+        Commands::Stop {
+            time,
+            container_name,
+        } => stop_container(&container_name, Duration::from_secs(time)),
+        Commands::Pause { container_name } => pause_container(&container_name),
+        Commands::Unpause { container_name } => {
+            unpause_container(&container_name)
+        }
         Commands::Rm { container_name } => remove_container(&container_name),
         Commands::Commit {
             container_name,
             image_name,
         } => commit_container(&container_name, &image_name),
         Commands::Exec {
+            workdir,
+            user,
             container_name,
             command,
         } => {
-            let cmd_str = command.join(" ");
-            exec_container(&container_name, &cmd_str)
+            validate_workdir(workdir.as_deref())?;
+            exec_container(&container_name, &command, workdir, user)
         }
+        Commands::Attach {
+            container_name,
+            detach_keys,
+        } => attach::attach(
+            &container_name,
+            &attach::DetachKeys::parse(&detach_keys)?,
+        ),
         Commands::Images => list_images(),
-        Commands::Import { tar_file, image } => import_image(&tar_file, &image),
+        Commands::Import {
+            tar_file,
+            image,
+            changes,
+        } => import_image(&tar_file, &image, &changes),
+    }
+}
+
+/// Parsed options of `rocker run` and `rocker create`
+struct RunOptions {
+    /// Container ID, a random one if not given
+    id: Option<String>,
+    /// Whether the command waits for `rocker start`
+    created: bool,
+    /// File the PID of the container process is written to
+    pid_file: Option<PathBuf>,
+    /// Image to run, the local busybox directory if not given
+    image: Option<String>,
+    tty: bool,
+    /// Whether rocker init stays PID 1
+    init: bool,
+    detach: bool,
+    /// When the monitor restarts the command of a detached container
+    restart: RestartPolicy,
+    health: HealthOptions,
+    /// Command and arguments to run in the container
+    command: Vec<String>,
+    resources: ResourceConfig,
+    /// Hostname, the container ID if not given
+    hostname: Option<String>,
+    domainname: Option<String>,
+    /// Working directory, `/` if not given
+    workdir: Option<String>,
+    /// User specification, root if not given
+    user: Option<String>,
+    volumes: Vec<Volume>,
+    tmpfs: Vec<Tmpfs>,
+    /// Whether the rootfs is mounted read-only
+    read_only: bool,
+    /// Variables from --env-file and -e, in order of precedence
+    env: Vec<String>,
+    security: SecurityOptions,
+    /// Capabilities kept by the container command, all if `None`
+    capabilities: Option<Vec<String>>,
+    /// Absolute path of the bundle the container is run from
+    bundle: Option<PathBuf>,
+    /// Root filesystem of a bundle, used instead of an image
+    rootfs: Option<PathBuf>,
+    namespaces: Vec<LinuxNamespace>,
+    /// Lifecycle hooks of the bundle
+    hooks: Hooks,
+}
+
+impl RunOptions {
+    /// Options running the container described by an OCI bundle.
+    fn from_bundle(
+        bundle: &Path,
+        init: bool,
+        detach: bool,
+        restart: RestartPolicy,
+        health: HealthOptions,
+    ) -> Result<Self> {
+        let bundle = bundle.canonicalize().with_context(|| {
+            format!("Failed to find bundle {}", bundle.display())
+        })?;
+        let spec = Spec::load(&bundle)?;
+        let rootfs = spec.rootfs(&bundle);
+        if !rootfs.is_dir() {
+            return Err(anyhow::anyhow!(
+                "Bundle rootfs {} is not a directory",
+                rootfs.display()
+            ));
+        }
+        for name in spec.hostname.iter().chain(&spec.domainname) {
+            hostname::validate(name)?;
+        }
+        if let Some(profile) = &spec.linux.seccomp {
+            SeccompFilter::compile(profile, None)?;
+        }
+        let (volumes, tmpfs) = spec.volumes_and_tmpfs(&bundle)?;
+        let resources = spec.linux.resources.clone().unwrap_or_default();
+        let cpu = resources.cpu.unwrap_or_default();
+
+        Ok(RunOptions {
+            id: None,
+            created: false,
+            pid_file: None,
+            image: None,
+            tty: spec.process.terminal,
+            init,
+            detach,
+            restart,
+            health,
+            resources: ResourceConfig {
+                memory_limit: resources
+                    .memory
+                    .and_then(|memory| memory.limit)
+                    .map(|limit| limit.to_string()),
+                cpu_shares: cpu.shares.map(|shares| shares.to_string()),
+                cpu_set: cpu.cpus,
+            },
+            hostname: spec.hostname.clone(),
+            domainname: spec.domainname.clone(),
+            workdir: Some(spec.process.cwd.clone()),
+            user: spec.user(),
+            volumes,
+            tmpfs,
+            read_only: spec.root.readonly,
+            capabilities: spec.capabilities()?,
+            bundle: Some(bundle),
+            rootfs: Some(rootfs),
+            command: spec.process.args,
+            env: spec.process.env,
+            security: SecurityOptions {
+                seccomp: spec.linux.seccomp,
+                masked_paths: spec.linux.masked_paths,
+                readonly_paths: spec.linux.readonly_paths,
+            },
+            namespaces: spec.linux.namespaces,
+            hooks: spec.hooks.unwrap_or_default(),
+        })
+    }
+}
+
+/// Namespaces of a container run from an image.
+fn default_namespaces() -> Vec<LinuxNamespace> {
+    spec::DEFAULT_NAMESPACES
+        .iter()
+        .map(|&kind| LinuxNamespace { kind, path: None })
+        .collect()
+}
+
+/// Parsed `--health-*` options of `rocker run`
+#[derive(Default)]
+struct HealthOptions {
+    /// Health check command, the image's if not given
+    command: Option<String>,
+    interval: Option<Duration>,
+    timeout: Option<Duration>,
+    retries: Option<u32>,
+    /// Whether --no-healthcheck disables the health check of the image
+    disabled: bool,
+}
+
+impl HealthOptions {
+    /// Combine the options with the health check of the image, the options
+    /// taking precedence.
+    ///
+    /// Returns `None` if neither has a command or the health check is
+    /// disabled.
+    fn resolve(
+        self,
+        image: Option<&Healthcheck>,
+    ) -> Result<Option<HealthConfig>> {
+        if self.disabled {
+            return Ok(None);
+        }
+        let Some(command) = self
+            .command
+            .or_else(|| image.map(|image| image.cmd.clone()))
+        else {
+            return Ok(None);
+        };
+
+        let image_duration = |duration: Option<&String>| {
+            duration
+                .map(|duration| container::health::parse_duration(duration))
+                .transpose()
+        };
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => {
+                image_duration(image.and_then(|image| image.interval.as_ref()))?
+                    .unwrap_or(container::health::DEFAULT_INTERVAL)
+            }
+        };
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => {
+                image_duration(image.and_then(|image| image.timeout.as_ref()))?
+                    .unwrap_or(container::health::DEFAULT_TIMEOUT)
+            }
+        };
+        if interval.is_zero() || timeout.is_zero() {
+            return Err(anyhow::anyhow!(
+                "Health check interval and timeout must not be zero"
+            ));
+        }
+
+        Ok(Some(HealthConfig {
+            command,
+            interval,
+            timeout,
+            retries: self
+                .retries
+                .or_else(|| image.and_then(|image| image.retries))
+                .unwrap_or(container::health::DEFAULT_RETRIES),
+        }))
     }
 }
 
-fn run(image: Option<&str>, tty: bool, cmd: &str, res: &ResourceConfig) {
-    debug!("rocker run image:{:?}, tty:{}, cmd:{}", image, tty, cmd);
+/// Parsed `--security-opt` values
+struct SecurityOptions {
+    /// Seccomp profile, `None` for seccomp=unconfined
+    seccomp: Option<SeccompProfile>,
+    /// Paths hidden from the container
+    masked_paths: Vec<String>,
+    /// Paths the container may only read
+    readonly_paths: Vec<String>,
+}
+
+impl SecurityOptions {
+    fn parse(opts: &[String]) -> Result<Self> {
+        let mut security = SecurityOptions {
+            seccomp: Some(SeccompProfile::default()),
+            masked_paths: system_paths::DEFAULT_MASKED_PATHS
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+            readonly_paths: system_paths::DEFAULT_READONLY_PATHS
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+        };
+
+        for opt in opts {
+            match opt.split_once('=') {
+                Some(("seccomp", "unconfined")) => security.seccomp = None,
+                Some(("seccomp", path)) => {
+                    let profile = SeccompProfile::load(Path::new(path))?;
+                    // Catch profile errors here rather than in the container
+                    SeccompFilter::compile(&profile, None)?;
+                    security.seccomp = Some(profile);
+                }
+                Some(("systempaths", "unconfined"))
+                | Some(("unmask", "ALL")) => {
+                    security.masked_paths.clear();
+                    security.readonly_paths.clear();
+                }
+                Some(("mask", paths)) => {
+                    for path in Self::parse_paths(opt, paths)? {
+                        if !security.masked_paths.contains(&path) {
+                            security.masked_paths.push(path);
+                        }
+                    }
+                }
+                Some(("unmask", paths)) => {
+                    let paths = Self::parse_paths(opt, paths)?;
+                    security.masked_paths.retain(|path| !paths.contains(path));
+                    security
+                        .readonly_paths
+                        .retain(|path| !paths.contains(path));
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Invalid security option {}",
+                        opt
+                    ));
+                }
+            }
+        }
+        Ok(security)
+    }
+
+    /// Parse the colon separated absolute paths of a mask or unmask
+    /// option.
+    fn parse_paths(opt: &str, paths: &str) -> Result<Vec<String>> {
+        paths
+            .split(':')
+            .map(|path| {
+                if path.starts_with('/') {
+                    Ok(path.to_string())
+                } else {
+                    Err(anyhow::anyhow!(
+                        "Invalid security option {}, path {:?} must be \
+                         absolute",
+                        opt,
+                        path
+                    ))
+                }
+            })
+            .collect()
+    }
+}
+
+fn run(options: RunOptions) {
+    let RunOptions {
+        id,
+        created,
+        pid_file,
+        image,
+        tty,
+        init,
+        detach,
+        restart,
+        health,
+        command,
+        resources: res,
+        hostname,
+        domainname,
+        workdir,
+        user,
+        mut volumes,
+        tmpfs,
+        read_only,
+        env: user_env,
+        security,
+        capabilities,
+        bundle,
+        rootfs,
+        namespaces,
+        hooks,
+    } = options;
+    let image = image.as_deref();
+
+    // Joined command line, only used for display
+    let cmd = command.join(" ");
+    debug!(
+        "rocker run image:{:?}, tty:{}, detach:{}, cmd:{}, volumes:{:?}",
+        image, tty, detach, cmd, volumes
+    );
 
     // Parse image name and tag (default to "latest" if not specified)
     let (image_name, image_tag) = if let Some(img) = image {
@@ -210,8 +1001,10 @@ fn run(image: Option<&str>, tty: bool, cmd: &str, res: &ResourceConfig) {
         ("busybox", "latest")
     };
 
-    // Get rootfs path from image
-    let rootfs_path = if image.is_some() {
+    // Get rootfs path from the bundle or image
+    let image_rootfs = if let Some(rootfs) = &rootfs {
+        rootfs.clone()
+    } else if image.is_some() {
         match ImageStore::rootfs_path(image_name, image_tag) {
             Ok(path) => path,
             Err(e) => {
@@ -232,154 +1025,461 @@ fn run(image: Option<&str>, tty: bool, cmd: &str, res: &ResourceConfig) {
             })
     };
 
-    debug!("Using rootfs path: {:?}", rootfs_path);
+    debug!("Using image rootfs path: {:?}", image_rootfs);
 
-    // Generate container ID (10-digit random string)
-    let container_id = ContainerInfo::generate_id();
-    let container_name = container_id.clone();
+    let image_config = if image.is_some() {
+        match ImageStore::load(image_name, image_tag) {
+            Ok(image_info) => image_info.config,
+            Err(e) => {
+                warn!("Failed to load image metadata: {}", e);
+                ImageConfig::default()
+            }
+        }
+    } else {
+        ImageConfig::default()
+    };
+    let image_env = image_config.env;
+
+    let healthcheck = match health.resolve(image_config.healthcheck.as_ref()) {
+        Ok(healthcheck) => healthcheck,
+        Err(e) => {
+            error!("Invalid health check: {}", e);
+            std::process::exit(-1);
+        }
+    };
 
-    // Create parent process
-    let parent = Container::create_parent_process(tty, cmd, &rootfs_path);
-    if parent.is_err() {
-        error!("create parent process failed");
-        std::process::exit(-1);
+    for volume in volumes.iter_mut() {
+        if let Err(e) = volume.prepare_host() {
+            error!("Failed to prepare volume {}: {}", volume, e);
+            std::process::exit(-1);
+        }
     }
-    let mut parent = parent.unwrap();
 
-    let pid = parent.pid();
+    // Generate container ID (10-digit random string) unless given
+    let container_id = id.unwrap_or_else(ContainerInfo::generate_id);
+    let container_name = container_id.clone();
+    let hostname = hostname.unwrap_or_else(|| container_id.clone());
+
+    // The host environment is never passed through, only the defaults,
+    // the image defaults and what the user asked for
+    let container_env =
+        env::merge(&[env::defaults(&hostname), image_env, user_env]);
+
+    // In detached mode everything below runs in the forked monitor process
+    let notifier = if detach {
+        match monitor::detach(&container_id, !created) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                error!("Failed to detach container: {}", e);
+                std::process::exit(-1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Mount the copy-on-write workspace so the image rootfs stays untouched.
+    // Rootless containers can only mount it from inside their own user
+    // namespace, so it is handed to the container init instead.
+    let rootless = rootless::is_rootless();
+    let workspace = if rootless {
+        Workspace::prepare(&container_name, &image_rootfs)
+            .map(|overlay| (overlay.merged.clone(), Some(overlay)))
+    } else {
+        Workspace::create(&container_name, &image_rootfs)
+            .map(|path| (path, None))
+    };
+    let (rootfs_path, rootfs_overlay) = match workspace {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            error!("Failed to create container workspace: {}", e);
+            let _ = Workspace::delete(&container_name);
+            let _ = ContainerStore::delete(&container_name);
+            std::process::exit(-1);
+        }
+    };
+
+    debug!("Using rootfs path: {:?}", rootfs_path);
+
+    // Mounted before the user volumes, which may replace them
+    let etc_volumes = match hostname::write_files(
+        &ContainerStore::container_dir(&container_name),
+        &hostname,
+        domainname.as_deref(),
+    ) {
+        Ok(etc_volumes) => etc_volumes,
+        Err(e) => {
+            error!("Failed to generate /etc/hosts: {}", e);
+            let _ = Workspace::delete(&container_name);
+            let _ = ContainerStore::delete(&container_name);
+            std::process::exit(-1);
+        }
+    };
+
+    // A created container opens the FIFO for writing once it is set up,
+    // which blocks until rocker start opens it for reading
+    let start_fifo = if created {
+        let path = ContainerStore::start_fifo_path(&container_name);
+        if let Err(e) =
+            nix::unistd::mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR)
+        {
+            error!("Failed to create {}: {}", path.display(), e);
+            let _ = Workspace::delete(&container_name);
+            let _ = ContainerStore::delete(&container_name);
+            std::process::exit(-1);
+        }
+        Some(path)
+    } else {
+        None
+    };
 
-    // Record container info BEFORE starting cgroups/network
-    let container_info = ContainerInfo {
-        pid: pid.to_string(),
+    // Everything below the process itself is kept across restarts: the
+    // workspace, the cgroup, the log file and the attached clients
+    let now = ContainerInfo::current_time();
+    let mut container_info = ContainerInfo {
+        pid: String::new(),
         id: container_id.clone(),
         name: container_name.clone(),
-        command: cmd.to_string(),
-        created_time: ContainerInfo::current_time(),
-        status: ContainerStatus::Running,
-        volume: None,
+        command: cmd.clone(),
+        created_time: now.clone(),
+        started_time: (!created).then_some(now),
+        status: if created {
+            ContainerStatus::Created
+        } else {
+            ContainerStatus::Running
+        },
+        exit_code: None,
+        signal: None,
+        oom_killed: false,
+        finished_time: None,
+        restart_policy: restart,
+        restart_count: 0,
+        healthcheck: healthcheck.clone(),
+        health: healthcheck.as_ref().map(|_| Health::default()),
+        tty,
+        volumes: volumes.iter().map(|v| v.to_string()).collect(),
+        env: container_env.clone(),
+        seccomp: security.seccomp.clone(),
+        capabilities: capabilities.clone(),
+        user: user.clone(),
+        workdir: workdir.clone(),
         port_mapping: Vec::new(),
         network: None,
-        image_name: format!("{}:{}", image_name, image_tag),
+        image_name: match &rootfs {
+            Some(rootfs) => rootfs.display().to_string(),
+            None => format!("{}:{}", image_name, image_tag),
+        },
+        bundle: bundle.map(|bundle| bundle.display().to_string()),
+    };
+    let init_config = InitConfig {
+        args: command,
+        env: container_env,
+        cwd: workdir.unwrap_or_else(|| String::from("/")),
+        user,
+        tty,
+        init,
+        hostname,
+        domainname,
+        volumes: etc_volumes.into_iter().chain(volumes).collect(),
+        tmpfs,
+        read_only,
+        rootfs: rootfs_overlay,
+        seccomp: security.seccomp,
+        masked_paths: security.masked_paths,
+        readonly_paths: security.readonly_paths,
+        capabilities,
+        start_fifo,
     };
-
-    if let Err(e) = ContainerStore::save(&container_info) {
-        error!("Failed to save container info: {}", e);
-        std::process::exit(-1);
-    }
-
-    // Apply cgroups
     let cgroup_manager = CgroupManager::new(&container_id);
-    cgroup_manager.set(res).unwrap();
-    cgroup_manager.apply(pid).unwrap();
-
-    // For non-TTY mode, capture output to log file
-    if !tty {
-        let log_path = ContainerStore::log_path(&container_name);
-        let stdout_opt = parent.stdout.take();
-        let stderr_opt = parent.stderr.take();
-
-        use std::fs::File;
-        use std::io::{Read, Write};
-        use std::thread::{JoinHandle, spawn};
-
-        let mut handles: Vec<JoinHandle<()>> = Vec::new();
-
-        // Capture stdout
-        if let Some(mut stdout) = stdout_opt {
-            match File::create(&log_path) {
-                Ok(mut log_file) => {
-                    let handle = spawn(move || {
-                        let mut buffer = [0; 4096];
-                        loop {
-                            match stdout.read(&mut buffer) {
-                                Ok(0) => break,
-                                Ok(n) => {
-                                    let _ = log_file.write_all(&buffer[..n]);
-                                }
-                                Err(_) => break,
-                            }
-                        }
-                    });
-                    handles.push(handle);
+    let log_path = ContainerStore::log_path(&container_name);
+    let clients = attach::Clients::default();
+    let input = attach::Input::default();
+    let mut notifier = notifier;
+    let mut raw_mode = None;
+    let mut restart_count = 0;
+    // Restarts since the command last ran long enough to reset the backoff
+    let mut failures = 0;
+
+    let exit_code = loop {
+        let (mut parent, console) =
+            match spawn_container(tty, &rootfs_path, &namespaces, created) {
+                Ok(spawned) => spawned,
+                Err(e) if restart_count > 0 => {
+                    error!(
+                        "Failed to restart container {}: {}",
+                        container_name, e
+                    );
+                    let _ = ContainerStore::update_status(
+                        &container_name,
+                        ContainerStatus::Exited,
+                    );
+                    break container_info.exit_code.unwrap_or(-1);
                 }
                 Err(e) => {
-                    warn!(
-                        "Failed to create log file {}: {}",
-                        log_path.display(),
-                        e
+                    error!("{:#}", e);
+                    let _ = Workspace::delete(&container_name);
+                    let _ = ContainerStore::delete(&container_name);
+                    std::process::exit(-1);
+                }
+            };
+
+        let pid = parent.pid();
+        container_info.pid = pid.to_string();
+
+        if restart_count == 0 {
+            // From now on ^C or a SIGTERM only reach the container, which
+            // lets the foreground run clean up once it exits
+            if !detach && let Err(e) = monitor::forward_signals(pid) {
+                warn!("{}", e);
+            }
+
+            // Record container info BEFORE starting cgroups/network
+            if let Err(e) = ContainerStore::save(&container_info) {
+                error!("Failed to save container info: {}", e);
+                let _ = parent.kill();
+                let _ = parent.wait();
+                let _ = Workspace::delete(&container_name);
+                let _ = ContainerStore::delete(&container_name);
+                std::process::exit(-1);
+            }
+            if let Some(pid_file) = &pid_file
+                && let Err(e) = std::fs::write(pid_file, pid.to_string())
+            {
+                warn!("Failed to write {}: {}", pid_file.display(), e);
+            }
+        } else {
+            match ContainerStore::record_restart(
+                &container_name,
+                pid,
+                restart_count,
+            ) {
+                Ok(true) => {}
+                // rocker stop was called right before the restart
+                Ok(false) => {
+                    info!(
+                        "Container {} was stopped, not restarting it",
+                        container_name
                     );
+                    let _ = parent.kill();
+                    let _ = parent.wait();
+                    let _ = ContainerStore::update_status(
+                        &container_name,
+                        ContainerStatus::Exited,
+                    );
+                    break container_info.exit_code.unwrap_or(-1);
                 }
+                Err(e) => warn!(
+                    "Failed to update container {}: {}",
+                    container_name, e
+                ),
             }
         }
 
-        // Capture stderr - append to same log file
-        if let Some(mut stderr) = stderr_opt
-            && let Ok(mut log_file) =
-                File::options().create(false).append(true).open(&log_path)
+        // Apply cgroups. An unprivileged user usually has no write access
+        // to the cgroup hierarchy, so rootless containers run without
+        // limits.
+        let cgroups = match cgroup_manager
+            .set(&res)
+            .and_then(|_| cgroup_manager.apply(pid))
         {
-            let handle = spawn(move || {
-                let mut buffer = [0; 4096];
-                loop {
-                    match stderr.read(&mut buffer) {
-                        Ok(0) => break,
-                        Ok(n) => {
-                            let _ = log_file.write_all(&buffer[..n]);
-                        }
-                        Err(_) => break,
-                    }
+            Err(e) if rootless => {
+                warn!(
+                    "Resource limits are not applied in rootless mode: {}",
+                    e
+                );
+                Ok(())
+            }
+            result => result.context("Failed to apply cgroups"),
+        };
+        // The cgroup is reused by restarts, only kills of this run count
+        let oom_kills = cgroup_manager.oom_kill_count().unwrap_or_default();
+
+        // The hooks of a bundle set up the container from the host once
+        // its namespaces and cgroups exist. Then the command is handed over
+        // to the container init, which has been waiting for it so that it
+        // only starts once everything is in place. rocker create returns
+        // once the container is set up.
+        let created_state =
+            spec::State::of(&container_info).with_status("created");
+        let setup = cgroups
+            .and_then(|_| {
+                hooks.create().try_for_each(|hook| hook.run(&created_state))
+            })
+            .and_then(|_| {
+                Container::send_init_config(&mut parent, &init_config)
+                    .context("Failed to send init config to container")?;
+                if created {
+                    Container::wait_setup(&mut parent)
+                } else {
+                    Ok(())
                 }
             });
-            handles.push(handle);
+
+        // A container that failed to set up leaves nothing behind
+        if let Err(e) = setup {
+            error!("Failed to set up container {}: {:#}", container_name, e);
+            let _ = parent.kill();
+            let _ = parent.wait();
+            run_hooks(
+                &hooks.poststop,
+                &spec::State::of(&container_info).with_status("stopped"),
+            );
+            if restart_count > 0 {
+                let _ = ContainerStore::update_status(
+                    &container_name,
+                    ContainerStatus::Exited,
+                );
+                break container_info.exit_code.unwrap_or(-1);
+            }
+            let _ = cgroup_manager.destroy();
+            let _ = Workspace::delete(&container_name);
+            let _ = ContainerStore::delete(&container_name);
+            std::process::exit(-1);
         }
 
-        // Don't wait for log threads - let them run independently
-        // They will finish when the child process closes its stdout/stderr
-    }
+        // Capture the output into the log file. The terminal of a
+        // foreground container is also proxied to the user's, which is put
+        // into raw mode so that keys such as ^C reach the container.
+        let mut _master = None;
+        let log_handles = match console {
+            Some(console) => match tty::receive_master(&console) {
+                Ok(master) => {
+                    if !detach {
+                        raw_mode = RawMode::enable(0).unwrap_or_else(|e| {
+                            warn!("{}", e);
+                            None
+                        });
+                    }
+                    let handles = match monitor::proxy_tty(
+                        &master, &log_path, &clients, !detach,
+                    ) {
+                        Ok(handle) => vec![handle],
+                        Err(e) => {
+                            warn!("Failed to proxy container terminal: {}", e);
+                            Vec::new()
+                        }
+                    };
+                    input.set(master.try_clone().ok());
+                    // Keep the terminal open for as long as the container
+                    // runs
+                    _master = Some(master);
+                    handles
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    Vec::new()
+                }
+            },
+            None => {
+                // SAFETY: the pipe writer gives up ownership of its fd
+                input.set(parent.stdin.take().map(|stdin| unsafe {
+                    File::from_raw_fd(stdin.into_fd())
+                }));
+                monitor::capture_logs(
+                    parent.stdout.take(),
+                    parent.stderr.take(),
+                    &log_path,
+                    &clients,
+                )
+            }
+        };
+
+        // Let rocker attach connect to the container stdio
+        if restart_count == 0
+            && let Err(e) = attach::serve(
+                &ContainerStore::attach_socket_path(&container_name),
+                input.clone(),
+                clients.clone(),
+            )
+        {
+            warn!("{}", e);
+        }
 
-    trace!("waiting parent finish");
-    let exit = parent.wait();
-    let exit = match exit {
-        Ok(e) => e,
-        Err(e) => {
-            error!("Failed to wait for parent process: {}", e);
-            // Still try to cleanup even if wait failed
-            std::process::exit(-1);
+        // A created container is started by rocker start, which runs the
+        // poststart hooks itself
+        if !created {
+            run_hooks(
+                &hooks.poststart,
+                &spec::State::of(&container_info).with_status("running"),
+            );
         }
-    };
-    trace!("parent process wait finished exit status is {}", exit);
 
-    // Cleanup .pivot_root directory if it exists
-    // (may not exist if container failed during pivot_root)
-    let pwd = std::env::current_dir();
-    if let Ok(pwd) = pwd {
-        let old_root = pwd.join("busybox").join(".pivot_root");
+        // The container is up, let the detached CLI print its ID and return
+        if let Some(notifier) = notifier.take()
+            && let Err(e) = notifier.ready()
+        {
+            warn!("Failed to notify rocker run: {}", e);
+        }
+
+        let health_checker = healthcheck.clone().map(|config| {
+            health::HealthChecker::start(&container_name, config)
+        });
+
+        trace!("waiting parent finish");
+        let started = Instant::now();
+        let exit = match parent.wait() {
+            Ok(e) => e,
+            Err(e) => {
+                error!("Failed to wait for parent process: {}", e);
+                // Still try to cleanup even if wait failed
+                std::process::exit(-1);
+            }
+        };
+        trace!("parent process wait finished exit status is {}", exit);
+        let exit_code = exit
+            .code()
+            .or_else(|| exit.signal().map(|signal| 128 + signal))
+            .unwrap_or(-1);
+        let signal = exit
+            .signal()
+            .and_then(|signal| nix::sys::signal::Signal::try_from(signal).ok())
+            .map(|signal| signal.as_str().to_string());
+
+        if let Some(health_checker) = health_checker {
+            health_checker.stop();
+        }
+
+        // Drain the remaining output before the container is marked exited
+        for handle in log_handles {
+            let _ = handle.join();
+        }
+
+        // Cleanup .pivot_root directory if it exists
+        // (may not exist if container failed during pivot_root)
+        let old_root = rootfs_path.join(".pivot_root");
         if old_root.exists()
-            && let Err(e) =
-                std::fs::remove_dir_all(old_root.as_os_str().to_str().unwrap())
+            && let Err(e) = std::fs::remove_dir_all(&old_root)
         {
             warn!("Failed to remove .pivot_root directory: {}", e);
         }
-    }
 
-    // Destroy cgroups (may not exist if container failed early)
-    let _ = cgroup_manager.destroy();
+        run_hooks(
+            &hooks.poststop,
+            &spec::State::of(&container_info).with_status("stopped"),
+        );
 
-    // Update container status based on TTY mode:
-    // - TTY mode: Delete metadata (container exits with user)
-    // - Non-TTY mode: Update status to Exited (keep metadata for logs)
-    if tty {
-        match ContainerStore::delete(&container_name) {
-            Ok(_) => trace!("Container {} metadata deleted", container_name),
-            Err(e) => warn!(
-                "Failed to delete container {} metadata: {}",
-                container_name, e
-            ),
+        // A foreground container with a terminal is deleted once it exits
+        if tty && !detach {
+            break exit_code;
         }
-    } else {
-        // Update status to Exited for non-TTY containers
-        match ContainerStore::update_status(
+
+        // The OOM kill counter goes away with the cgroup
+        let oom_killed =
+            cgroup_manager.oom_killed(oom_kills).unwrap_or_else(|e| {
+                debug!("Failed to check for OOM kills: {}", e);
+                false
+            });
+
+        // rocker stop marks the container stopped before signaling it
+        let stopped = is_stopped(&container_name);
+
+        // Update status to Exited and record how the command ended (keep
+        // metadata for logs)
+        match ContainerStore::record_exit(
             &container_name,
-            ContainerStatus::Exited,
+            exit_code,
+            signal,
+            oom_killed,
         ) {
             Ok(_) => {
                 trace!("Container {} status updated to Exited", container_name)
@@ -389,16 +1489,132 @@ fn run(image: Option<&str>, tty: bool, cmd: &str, res: &ResourceConfig) {
                 container_name, e
             ),
         }
+        container_info.exit_code = Some(exit_code);
+
+        if stopped || !restart.should_restart(exit_code, restart_count) {
+            break exit_code;
+        }
+
+        if started.elapsed() >= restart::BACKOFF_RESET {
+            failures = 0;
+        }
+        let delay = restart::backoff(failures);
+        failures += 1;
+        info!(
+            "Restarting container {} in {:?} after exit code {}",
+            container_name, delay, exit_code
+        );
+        let _ = ContainerStore::update_status(
+            &container_name,
+            ContainerStatus::Restarting,
+        );
+        std::thread::sleep(delay);
+        if is_stopped(&container_name) {
+            let _ = ContainerStore::update_status(
+                &container_name,
+                ContainerStatus::Exited,
+            );
+            break exit_code;
+        }
+        restart_count += 1;
+    };
+    drop(raw_mode);
+
+    // Destroy cgroups (may not exist if container failed early)
+    let _ = cgroup_manager.destroy();
+
+    // Delete the workspace and metadata of a foreground TTY container, it
+    // exits with the user
+    if tty && !detach {
+        if let Err(e) = Workspace::delete(&container_name) {
+            warn!(
+                "Failed to delete container {} workspace: {}",
+                container_name, e
+            );
+        }
+        match ContainerStore::delete(&container_name) {
+            Ok(_) => trace!("Container {} metadata deleted", container_name),
+            Err(e) => warn!(
+                "Failed to delete container {} metadata: {}",
+                container_name, e
+            ),
+        }
     }
 
-    let exit_code = exit.code().unwrap_or(-1);
     debug!("Container exiting with code: {}", exit_code);
     std::process::exit(exit_code);
 }
 
-fn init(cmd: &str) -> Result<()> {
-    debug!("rocker init cmd:{}", cmd);
-    Container::init_process(cmd, &[])
+/// Run hooks whose failure doesn't affect the container, warning about the
+/// failed ones.
+fn run_hooks(hooks: &[Hook], state: &spec::State) {
+    for hook in hooks {
+        if let Err(e) = hook.run(state) {
+            warn!("{:#}", e);
+        }
+    }
+}
+
+/// Create the container init process, with a terminal of its own if `tty`.
+///
+/// Returns the process and, with a terminal, the socket its master is sent
+/// back on.
+fn spawn_container(
+    tty: bool,
+    rootfs_path: &Path,
+    namespaces: &[LinuxNamespace],
+    sync_setup: bool,
+) -> Result<(unshare::Child, Option<UnixStream>)> {
+    // Containers with a terminal send its master back over this socket
+    let (console, container_console) = if tty {
+        let (console, container_console) = tty::console_socket()?;
+        (Some(console), Some(container_console))
+    } else {
+        (None, None)
+    };
+
+    let parent = Container::create_parent_process(
+        container_console,
+        rootfs_path,
+        namespaces,
+        sync_setup,
+    )
+    .context("create parent process failed")?;
+    Ok((parent, console))
+}
+
+/// Whether `rocker stop` was called on the container, which disables
+/// restarting it.
+fn is_stopped(container_name: &str) -> bool {
+    ContainerStore::load(container_name)
+        .map(|info| info.status == ContainerStatus::Stopped)
+        .unwrap_or(true)
+}
+
+/// Whether a container with the status may still have a process.
+fn is_live(status: &ContainerStatus) -> bool {
+    !matches!(status, ContainerStatus::Stopped | ContainerStatus::Exited)
+}
+
+/// Write a template bundle configuration running `command`.
+fn spec_bundle(bundle: &Path, tty: bool, command: Vec<String>) -> Result<()> {
+    let command = if command.is_empty() {
+        vec![String::from("sh")]
+    } else {
+        command
+    };
+    Spec::new(command, tty).save(bundle)?;
+    println!(
+        "Created {}, put the container filesystem into {}",
+        bundle.join(spec::CONFIG_FILE).display(),
+        bundle.join("rootfs").display()
+    );
+    Ok(())
+}
+
+fn init() -> Result<()> {
+    debug!("rocker init");
+    Container::init_process()
 }
 
 /// List all containers.
@@ -420,11 +1636,7 @@ fn list_containers() -> Result<()> {
             info.id,
             info.name,
             info.pid,
-            match info.status {
-                ContainerStatus::Running => "running",
-                ContainerStatus::Stopped => "stopped",
-                ContainerStatus::Exited => "exited",
-            },
+            info.status_summary(),
             info.command,
             info.created_time
         )?;
@@ -466,71 +1678,525 @@ fn log_container(container_name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Print the metadata of a container.
+///
+/// With a field name, only the value of that field is printed, strings
+/// without quotes and missing values as an empty line, so that scripts can
+/// use it directly.
+fn inspect_container(container_name: &str, field: Option<&str>) -> Result<()> {
+    let info = ContainerStore::load(container_name).with_context(|| {
+        format!("Failed to load container {}", container_name)
+    })?;
+    let Some(field) = field else {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    };
+
+    let info = serde_json::to_value(&info)
+        .context("Failed to serialize container info")?;
+    match info.get(field) {
+        Some(serde_json::Value::String(value)) => println!("{}", value),
+        Some(serde_json::Value::Null) => println!(),
+        Some(value) => println!("{}", value),
+        None => {
+            return Err(anyhow::anyhow!(
+                "Container {} has no field {}",
+                container_name,
+                field
+            ));
+        }
+    }
+    Ok(())
+}
+
 /// Stop a running container.
 ///
-/// Sends SIGTERM to the container process and updates its status to stopped.
-fn stop_container(container_name: &str) -> Result<()> {
+/// Marks the container stopped, which keeps it from being restarted, and
+/// sends SIGTERM to the container process. If the container still has
+/// processes after `timeout`, all of them are killed. A created container
+/// is killed right away, its init ignores SIGTERM until it runs the
+/// command. A paused container is thawed after the signal, so that it
+/// handles it right away.
+fn stop_container(container_name: &str, timeout: Duration) -> Result<()> {
+    use nix::errno::Errno;
     use nix::sys::signal::{self, Signal};
     use nix::unistd::Pid;
 
-    let mut info = ContainerStore::load(container_name).with_context(|| {
+    let info = ContainerStore::load(container_name).with_context(|| {
         format!("Failed to load container {}", container_name)
     })?;
 
-    if info.status == ContainerStatus::Stopped {
-        println!("Container {} is already stopped", container_name);
-        return Ok(());
+    // Marked first, so that the monitor doesn't restart the container once
+    // it exits. The PID is read under the same lock: the monitor clears it
+    // once the container exited, after which it may belong to another
+    // process.
+    let mut status = info.status.clone();
+    let mut pid = String::new();
+    ContainerStore::update(container_name, |info| {
+        status = info.status.clone();
+        if !is_live(&info.status) {
+            return false;
+        }
+        info.status = ContainerStatus::Stopped;
+        pid = info.pid.clone();
+        true
+    })
+    .with_context(|| {
+        format!("Failed to update container status for {}", container_name)
+    })?;
+
+    match status {
+        ContainerStatus::Stopped => {
+            println!("Container {} is already stopped", container_name);
+            return Ok(());
+        }
+        ContainerStatus::Exited => {
+            println!("Container {} is not running", container_name);
+            return Ok(());
+        }
+        ContainerStatus::Created
+        | ContainerStatus::Running
+        | ContainerStatus::Paused
+        | ContainerStatus::Restarting => {}
     }
+    let paused = status == ContainerStatus::Paused;
+    let stop_signal = if status == ContainerStatus::Created {
+        Signal::SIGKILL
+    } else {
+        Signal::SIGTERM
+    };
 
-    let pid: i32 = info.pid.parse().with_context(|| {
-        format!("Failed to parse container PID: {}", info.pid)
-    })?;
+    // A restarting container has no process, the monitor notices the
+    // status once the restart delay is over
+    if pid.is_empty() {
+        println!("Container {} stopped", container_name);
+        return Ok(());
+    }
+    let pid: i32 = pid
+        .parse()
+        .with_context(|| format!("Failed to parse container PID: {}", pid))?;
 
-    // Send SIGTERM to container process
-    signal::kill(Pid::from_raw(pid), Signal::SIGTERM).with_context(|| {
-        format!("Failed to send SIGTERM to container PID {}", pid)
-    })?;
+    match signal::kill(Pid::from_raw(pid), stop_signal) {
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!(
+                    "Failed to send {} to container PID {}",
+                    stop_signal, pid
+                )
+            });
+        }
+    }
+    let cgroup_manager = CgroupManager::new(&info.id);
+    if paused {
+        cgroup_manager.thaw().with_context(|| {
+            format!("Failed to unpause container {}", container_name)
+        })?;
+    }
 
-    // Update container status
-    info.status = ContainerStatus::Stopped;
-    info.pid = String::new(); // Clear PID
-    ContainerStore::save(&info).with_context(|| {
-        format!("Failed to update container status for {}", container_name)
-    })?;
+    if !wait_for_exit(&cgroup_manager, pid, timeout) {
+        info!(
+            "Container {} didn't exit within {}s, killing it",
+            container_name,
+            timeout.as_secs()
+        );
+        let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
+        for pid in cgroup_manager.pids().unwrap_or_default() {
+            let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
+        }
+        if !wait_for_exit(&cgroup_manager, pid, KILL_TIMEOUT) {
+            return Err(anyhow::anyhow!(
+                "Container {} still has processes after SIGKILL",
+                container_name
+            ));
+        }
+    }
 
     println!("Container {} stopped", container_name);
     Ok(())
 }
 
+/// How long the processes of a container may take to exit once killed.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait up to `timeout` for the container process and every other process
+/// of the container cgroup to exit. Returns whether they did.
+///
+/// Rootless containers have no cgroup, only their process is waited for.
+fn wait_for_exit(
+    cgroup_manager: &CgroupManager,
+    pid: i32,
+    timeout: Duration,
+) -> bool {
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        // The monitor reaps the process right away, so a zombie doesn't
+        // stay around for long
+        let running = signal::kill(Pid::from_raw(pid), None).is_ok()
+            || cgroup_manager.pids().is_ok_and(|pids| !pids.is_empty());
+        if !running {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
 /// Remove a stopped container.
 ///
-/// Deletes the container metadata directory. Cannot remove running containers.
+/// Unmounts the container workspace and deletes the container metadata
+/// directory. Cannot remove running containers.
 fn remove_container(container_name: &str) -> Result<()> {
     let info = ContainerStore::load(container_name).with_context(|| {
         format!("Failed to load container {}", container_name)
     })?;
 
-    if info.status == ContainerStatus::Running {
+    if matches!(
+        info.status,
+        ContainerStatus::Created
+            | ContainerStatus::Running
+            | ContainerStatus::Paused
+            | ContainerStatus::Restarting
+    ) {
         return Err(anyhow::anyhow!(
             "Cannot remove running container {}. Stop it first.",
             container_name
         ));
     }
 
+    // Delete workspace (mount point, write layer) before the metadata
+    // directory that contains it
+    Workspace::delete(container_name).with_context(|| {
+        format!("Failed to delete container {} workspace", container_name)
+    })?;
+
     // Delete container metadata
     ContainerStore::delete(container_name).with_context(|| {
         format!("Failed to delete container {}", container_name)
     })?;
 
-    // TODO: Delete workspace (mount points, write layers) when workspace module is implemented
-
     println!("Container {} removed", container_name);
     Ok(())
 }
 
+/// Run the command of a created container.
+///
+/// The container init blocks on opening the start FIFO for writing, opening
+/// it for reading releases it. Like runc, prints nothing on success.
+fn start_container(container_id: &str) -> Result<()> {
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+    use std::io::Read;
+
+    let info = ContainerStore::load(container_id).with_context(|| {
+        format!("Failed to load container {}", container_id)
+    })?;
+    if info.status != ContainerStatus::Created {
+        return Err(anyhow::anyhow!(
+            "Container {} is {}, not created",
+            container_id,
+            info.status
+        ));
+    }
+
+    // Opening the FIFO would block forever without the init on the other end
+    let pid: i32 = info.pid.parse().with_context(|| {
+        format!("Failed to parse container PID: {}", info.pid)
+    })?;
+    signal::kill(Pid::from_raw(pid), None).with_context(|| {
+        format!("Container {} process {} is gone", container_id, pid)
+    })?;
+
+    let fifo_path = ContainerStore::start_fifo_path(container_id);
+    let mut fifo = File::open(&fifo_path)
+        .with_context(|| format!("Failed to open {}", fifo_path.display()))?;
+    let mut released = Vec::new();
+    fifo.read_to_end(&mut released)
+        .with_context(|| format!("Failed to read {}", fifo_path.display()))?;
+    if released.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Container {} exited before it was started",
+            container_id
+        ));
+    }
+    let _ = std::fs::remove_file(&fifo_path);
+
+    ContainerStore::record_start(container_id).with_context(|| {
+        format!("Failed to update container status for {}", container_id)
+    })?;
+
+    // The configuration is read again for the hooks, only the monitor keeps
+    // it around
+    if let Some(bundle) = &info.bundle {
+        match Spec::load(Path::new(bundle)) {
+            Ok(Spec {
+                hooks: Some(hooks), ..
+            }) => run_hooks(
+                &hooks.poststart,
+                &spec::State::of(&info).with_status("running"),
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to run poststart hooks: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Print the OCI state of a container.
+fn print_state(container_id: &str) -> Result<()> {
+    let info = ContainerStore::load(container_id).with_context(|| {
+        format!("Failed to load container {}", container_id)
+    })?;
+    let state = serde_json::to_string_pretty(&spec::State::of(&info))
+        .context("Failed to serialize container state")?;
+    println!("{}", state);
+    Ok(())
+}
+
+/// Send a signal to the process of a created or running container.
+///
+/// With `all`, the signal is sent to every process of the container cgroup
+/// instead, including processes left behind by a command that exited.
+fn kill_container(container_id: &str, signal: &str, all: bool) -> Result<()> {
+    use nix::errno::Errno;
+    use nix::unistd::Pid;
+
+    let sig = parse_signal(signal)?;
+    let info = ContainerStore::load(container_id).with_context(|| {
+        format!("Failed to load container {}", container_id)
+    })?;
+
+    let pids = if all {
+        CgroupManager::new(&info.id).pids().with_context(|| {
+            format!(
+                "Failed to list the processes of container {}",
+                container_id
+            )
+        })?
+    } else if matches!(
+        info.status,
+        ContainerStatus::Created
+            | ContainerStatus::Running
+            | ContainerStatus::Paused
+    ) && !info.pid.is_empty()
+    {
+        vec![info.pid.parse().with_context(|| {
+            format!("Failed to parse container PID: {}", info.pid)
+        })?]
+    } else {
+        Vec::new()
+    };
+    if pids.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Container {} is not running",
+            container_id
+        ));
+    }
+
+    for pid in pids {
+        match nix::sys::signal::kill(Pid::from_raw(pid), sig) {
+            // Processes of the cgroup may exit meanwhile
+            Err(Errno::ESRCH) if all => {}
+            result => result.with_context(|| {
+                format!("Failed to send {} to container PID {}", sig, pid)
+            })?,
+        }
+    }
+    Ok(())
+}
+
+/// Parse a signal name such as `SIGKILL` or `kill`, or a signal number.
+fn parse_signal(signal: &str) -> Result<nix::sys::signal::Signal> {
+    use nix::sys::signal::Signal;
+
+    match signal.parse::<i32>() {
+        Ok(number) => Signal::try_from(number).ok(),
+        Err(_) => {
+            let name = signal.to_uppercase();
+            let name = if name.starts_with("SIG") {
+                name
+            } else {
+                format!("SIG{}", name)
+            };
+            name.parse::<Signal>().ok()
+        }
+    }
+    .ok_or_else(|| anyhow::anyhow!("Invalid signal: {}", signal))
+}
+
+/// Delete a stopped container.
+///
+/// With `force`, a created or running container is killed first, once its
+/// monitor recorded the exit the container is removed.
+fn delete_container(container_id: &str, force: bool) -> Result<()> {
+    use nix::sys::signal::{self, Signal};
+    use nix::unistd::Pid;
+
+    let info = ContainerStore::load(container_id).with_context(|| {
+        format!("Failed to load container {}", container_id)
+    })?;
+    if is_live(&info.status) {
+        if !force {
+            return Err(anyhow::anyhow!(
+                "Container {} is {}, kill it first or use --force",
+                container_id,
+                info.status
+            ));
+        }
+
+        // Marked first, so that the monitor doesn't restart the container.
+        // Only a container still live under the store lock is killed, the
+        // PID of one that exited meanwhile may belong to another process.
+        let mut pid = String::new();
+        let mut paused = false;
+        ContainerStore::update(container_id, |info| {
+            if !is_live(&info.status) {
+                return false;
+            }
+            paused = info.status == ContainerStatus::Paused;
+            info.status = ContainerStatus::Stopped;
+            pid = info.pid.clone();
+            true
+        })
+        .with_context(|| {
+            format!("Failed to update container status for {}", container_id)
+        })?;
+        if let Ok(pid) = pid.parse::<i32>() {
+            let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
+            // Frozen processes only die once they are thawed
+            if paused {
+                let _ = CgroupManager::new(&info.id).thaw();
+            }
+
+            // The monitor still cleans up the cgroup once the process exits
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while ContainerStore::load(container_id)
+                .map(|info| info.status != ContainerStatus::Exited)
+                .unwrap_or(false)
+            {
+                if Instant::now() >= deadline {
+                    return Err(anyhow::anyhow!(
+                        "Timed out waiting for container {} to exit",
+                        container_id
+                    ));
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    remove_container(container_id)
+}
+
+/// Pause a running container.
+///
+/// Freezes the processes of the container cgroup, and returns once all of
+/// them are frozen.
+fn pause_container(container_name: &str) -> Result<()> {
+    let info = ContainerStore::load(container_name).with_context(|| {
+        format!("Failed to load container {}", container_name)
+    })?;
+    match info.status {
+        ContainerStatus::Running => {}
+        ContainerStatus::Paused => {
+            return Err(anyhow::anyhow!(
+                "Container {} is already paused",
+                container_name
+            ));
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Container {} is not running",
+                container_name
+            ));
+        }
+    }
+
+    let cgroup_manager = CgroupManager::new(&info.id);
+    if let Err(e) = cgroup_manager.freeze() {
+        // Processes that did freeze are thawed again, the container keeps
+        // running
+        let _ = cgroup_manager.thaw();
+        return Err(e).with_context(|| {
+            format!("Failed to pause container {}", container_name)
+        });
+    }
+    // The container may have exited meanwhile
+    match ContainerStore::change_status(
+        container_name,
+        ContainerStatus::Running,
+        ContainerStatus::Paused,
+    ) {
+        Ok(true) => {}
+        result => {
+            // Don't leave the container frozen without a way to unpause it
+            let _ = cgroup_manager.thaw();
+            result.with_context(|| {
+                format!(
+                    "Failed to update container status for {}",
+                    container_name
+                )
+            })?;
+            return Err(anyhow::anyhow!(
+                "Container {} is not running",
+                container_name
+            ));
+        }
+    }
+
+    println!("Container {} paused", container_name);
+    Ok(())
+}
+
+/// Unpause a paused container.
+fn unpause_container(container_name: &str) -> Result<()> {
+    let info = ContainerStore::load(container_name).with_context(|| {
+        format!("Failed to load container {}", container_name)
+    })?;
+
+    // Marked running before it is thawed: once thawed, the container may
+    // exit right away, e.g. because it was killed while paused, and the
+    // monitor records that
+    let unpaused = ContainerStore::change_status(
+        container_name,
+        ContainerStatus::Paused,
+        ContainerStatus::Running,
+    )
+    .with_context(|| {
+        format!("Failed to update container status for {}", container_name)
+    })?;
+    if !unpaused {
+        return Err(anyhow::anyhow!(
+            "Container {} is not paused",
+            container_name
+        ));
+    }
+
+    if let Err(e) = CgroupManager::new(&info.id).thaw() {
+        let _ = ContainerStore::change_status(
+            container_name,
+            ContainerStatus::Running,
+            ContainerStatus::Paused,
+        );
+        return Err(e).with_context(|| {
+            format!("Failed to unpause container {}", container_name)
+        });
+    }
+
+    println!("Container {} unpaused", container_name);
+    Ok(())
+}
+
 /// Commit a container to an image.
 ///
-/// Creates a tar archive of the container filesystem.
+/// Creates a tar archive of the container's merged rootfs (image layer plus
+/// the container's writable layer). Volumes are mounted only inside the
+/// container's mount namespace, so their contents are never committed.
 fn commit_container(container_name: &str, image_name: &str) -> Result<()> {
     use std::process::Command;
 
@@ -538,13 +2204,20 @@ fn commit_container(container_name: &str, image_name: &str) -> Result<()> {
         format!("Failed to load container {}", container_name)
     })?;
 
-    // Current implementation uses busybox directory as the container rootfs
-    // TODO: Update when workspace module is implemented with proper mount points
-    let mnt_url = std::env::current_dir()
-        .map(|p| p.join("busybox"))
-        .unwrap_or_else(|_| {
-            PathBuf::from("/home/mathxh/project/rocker/busybox")
-        });
+    if rootless::is_rootless() {
+        return Err(anyhow::anyhow!(
+            "Committing rootless containers is not supported, their rootfs \
+             is only mounted inside the container"
+        ));
+    }
+
+    if !Workspace::is_mounted(container_name) {
+        return Err(anyhow::anyhow!(
+            "Container {} rootfs is not mounted",
+            container_name
+        ));
+    }
+    let mnt_url = Workspace::mount_path(container_name);
 
     // Save image tar to current working directory
     let image_tar = std::env::current_dir()
@@ -580,7 +2253,12 @@ fn commit_container(container_name: &str, image_name: &str) -> Result<()> {
 /// Execute a command in a running container.
 ///
 /// Enters the container's namespaces and executes the specified command.
-fn exec_container(container_name: &str, command: &str) -> Result<()> {
+fn exec_container(
+    container_name: &str,
+    args: &[String],
+    workdir: Option<String>,
+    user: Option<String>,
+) -> Result<()> {
     use nix::sched::CloneFlags;
     use nix::sched::setns;
 
@@ -590,28 +2268,44 @@ fn exec_container(container_name: &str, command: &str) -> Result<()> {
     let info = ContainerStore::load(container_name).with_context(|| {
         format!("Failed to load container {}", container_name)
     })?;
+    if info.status == ContainerStatus::Paused {
+        return Err(anyhow::anyhow!(
+            "Container {} is paused, unpause it first",
+            container_name
+        ));
+    }
 
     let pid: i32 = info.pid.parse().with_context(|| {
         format!("Failed to parse container PID: {}", info.pid)
     })?;
 
-    // Parse command into arguments
-    let args: Vec<&str> = command.split_whitespace().collect();
     if args.is_empty() {
         return Err(anyhow::anyhow!("No command specified"));
     }
 
-    // Get container environment variables from /proc/{pid}/environ
-    let container_envs = get_container_envs(pid)?;
-
-    // Enter container namespaces using setns
-    let namespaces = [
+    // Use the environment recorded at run time, reading it back from
+    // /proc/<pid>/environ would require ptrace access to the container
+    let mut container_envs = if info.env.is_empty() {
+        env::defaults(&info.id)
+    } else {
+        info.env.clone()
+    };
+    let workdir = workdir.or(info.workdir);
+    let user = user.or(info.user);
+
+    // Enter container namespaces using setns. The user namespace of a
+    // rootless container comes first, it owns the other namespaces.
+    let mut namespaces = Vec::new();
+    if rootless::is_rootless() {
+        namespaces.push(("user", CloneFlags::CLONE_NEWUSER));
+    }
+    namespaces.extend([
         ("ipc", CloneFlags::CLONE_NEWIPC),
         ("uts", CloneFlags::CLONE_NEWUTS),
         ("net", CloneFlags::CLONE_NEWNET),
         ("pid", CloneFlags::CLONE_NEWPID),
         ("mnt", CloneFlags::CLONE_NEWNS),
-    ];
+    ]);
 
     for (ns_name, clone_flag) in &namespaces {
         let ns_path = format!("/proc/{}/ns/{}", pid, ns_name);
@@ -623,44 +2317,77 @@ fn exec_container(container_name: &str, command: &str) -> Result<()> {
         })?;
     }
 
+    // The root and working directory of rocker are now the container
+    // root, so the user is resolved against the container's /etc/passwd
+    let user = user
+        .as_deref()
+        .map(|spec| User::resolve(spec, Path::new("/")))
+        .transpose()?;
+    if let Some(user) = &user {
+        user.check_mapped()?;
+    }
+    if let Some(user) = &user
+        && env::get(&container_envs, "HOME") == Some(env::DEFAULT_HOME)
+    {
+        container_envs =
+            env::merge(&[container_envs, vec![format!("HOME={}", user.home)]]);
+    }
+
+    // The exec'd process gets the same syscall filter and capabilities
+    // as the container
+    let capabilities = info.capabilities.as_deref();
+    let seccomp = info
+        .seccomp
+        .as_ref()
+        .map(|profile| SeccompFilter::compile(profile, capabilities))
+        .transpose()?;
+    let capabilities =
+        capabilities.map(CapabilitySet::from_names).transpose()?;
+
     // Execute command in container namespace
-    let status = std::process::Command::new(args[0])
+    let mut command = std::process::Command::new(env::find_executable(
+        &args[0],
+        &container_envs,
+    ));
+    command
+        .arg0(&args[0])
         .args(&args[1..])
-        .envs(container_envs)
-        .status()
-        .context("Failed to execute command")?;
+        .env_clear()
+        .envs(container_envs.iter().filter_map(|var| var.split_once('=')));
+    if let Some(workdir) = &workdir {
+        command.current_dir(workdir);
+    }
+    if seccomp.is_some() || capabilities.is_some() || user.is_some() {
+        // SAFETY: installing the filter, dropping capabilities and
+        // switching the user only make prctl, capset and set*id calls,
+        // which are async-signal-safe. As in rocker init, the filter has
+        // to be installed first.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(filter) = &seccomp {
+                    filter.install()?;
+                }
+                container::user::drop_privileges(
+                    user.as_ref(),
+                    capabilities.as_ref(),
+                )
+            });
+        }
+    }
+    let status = command.status().context("Failed to execute command")?;
 
     std::process::exit(status.code().unwrap_or(-1));
 }
 
-/// Get container environment variables from /proc/{pid}/environ.
-///
-/// Environment variables are null-separated, so we need to split by \0.
-fn get_container_envs(pid: i32) -> Result<Vec<(String, String)>> {
-    use std::fs;
-    use std::io::Read;
-
-    let environ_path = format!("/proc/{}/environ", pid);
-    let mut file = fs::File::open(&environ_path).with_context(|| {
-        format!("Failed to open container environ {}", environ_path)
-    })?;
-
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).with_context(|| {
-        format!("Failed to read container environ {}", environ_path)
-    })?;
-
-    let envs: Vec<(String, String)> = buffer
-        .split(|&b| b == 0)
-        .filter(|s| !s.is_empty())
-        .filter_map(|s| {
-            let s = std::str::from_utf8(s).ok()?;
-            let mut parts = s.splitn(2, '=');
-            Some((parts.next()?.to_string(), parts.next()?.to_string()))
-        })
-        .collect();
-
-    Ok(envs)
+/// Check that a `--workdir` is an absolute path.
+fn validate_workdir(workdir: Option<&str>) -> Result<()> {
+    match workdir {
+        Some(workdir) if !workdir.starts_with('/') => Err(anyhow::anyhow!(
+            "Invalid working directory {}, it must be absolute",
+            workdir
+        )),
+        _ => Ok(()),
+    }
 }
 
 /// List all images.
@@ -701,7 +2428,7 @@ fn list_images() -> Result<()> {
 /// Import a tar file as an image.
 ///
 /// Parses the image name (optionally with tag) and imports the tar file.
-fn import_image(tar_file: &str, image: &str) -> Result<()> {
+fn import_image(tar_file: &str, image: &str, changes: &[String]) -> Result<()> {
     // Parse image name and tag (format: "name" or "name:tag")
     let (name, tag) = if image.contains(':') {
         let parts: Vec<&str> = image.splitn(2, ':').collect();
@@ -710,9 +2437,16 @@ fn import_image(tar_file: &str, image: &str) -> Result<()> {
         (image, "latest")
     };
 
+    let mut config = ImageConfig::default();
+    for change in changes {
+        config.apply_change(change)?;
+    }
+    // Durations are only parsed when a container runs, check them now
+    HealthOptions::default().resolve(config.healthcheck.as_ref())?;
+
     // Import the image
-    let image_info =
-        ImageStore::import(tar_file, name, tag).with_context(|| {
+    let image_info = ImageStore::import(tar_file, name, tag, config)
+        .with_context(|| {
             format!("Failed to import image {} from {}", image, tar_file)
         })?;
 
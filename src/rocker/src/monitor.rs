@@ -0,0 +1,301 @@
+//! Per-container monitor.
+//!
+//! The monitor is the process that owns a running container: it is the
+//! parent of the container init process, drains its stdout/stderr into the
+//! log file, reaps its exit status and cleans up afterwards.
+//!
+//! In the foreground `rocker run` is its own monitor, and forwards the
+//! termination signals it receives to the container rather than dying and
+//! leaving it behind. With `rocker run -d`
+//! the CLI forks a monitor which leaves the terminal session, reports back
+//! once the container has started and then supervises it in the background
+//! while the CLI prints the container ID and returns.
+//!
+//! For containers with a terminal the monitor holds the terminal master
+//! instead of stdout/stderr pipes, so the terminal session lives as long as
+//! the container, whether or not a user is connected to it.
+//!
+//! The container output is also sent to the clients of `rocker attach`,
+//! whose input goes to the container stdin (see [`crate::attach`]).
+
+use crate::attach::{Clients, FrameKind};
+use anyhow::{Context, Result};
+use container::tty;
+use nix::sys::signal::{SigSet, Signal, kill};
+use nix::unistd::{
+    ForkResult, Pid, close, dup2, fork, pipe, read, setsid, write,
+};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::thread::{JoinHandle, spawn};
+
+/// Write end of the readiness pipe held by a detached monitor.
+pub struct ReadyNotifier {
+    fd: RawFd,
+}
+
+impl ReadyNotifier {
+    /// Tell the waiting CLI that the container has started.
+    ///
+    /// The monitor's stdio is then redirected to /dev/null so that it no
+    /// longer holds on to the user's terminal.
+    pub fn ready(self) -> Result<()> {
+        write(self.fd, b"1").context("Failed to notify rocker run")?;
+        let _ = close(self.fd);
+
+        let devnull = File::options()
+            .read(true)
+            .write(true)
+            .open("/dev/null")
+            .context("Failed to open /dev/null")?;
+        for fd in 0..=2 {
+            dup2(devnull.as_raw_fd(), fd)
+                .context("Failed to redirect monitor stdio")?;
+        }
+        Ok(())
+    }
+}
+
+/// Fork a detached monitor for the container.
+///
+/// This only returns in the forked monitor, which has been moved into a
+/// session of its own. The calling CLI process blocks until the monitor
+/// calls [`ReadyNotifier::ready`], prints the container ID if `print_id` and
+/// exits. If the monitor exits before that, starting the container failed;
+/// the monitor has already logged the reason on the shared stderr and the
+/// CLI exits with an error.
+pub fn detach(container_id: &str, print_id: bool) -> Result<ReadyNotifier> {
+    let (read_fd, write_fd) = pipe().context("Failed to create pipe")?;
+
+    // SAFETY: rocker is still single-threaded here, log capturing threads
+    // are only started by the monitor after the fork.
+    match unsafe { fork() }.context("Failed to fork monitor process")? {
+        ForkResult::Parent { .. } => {
+            let _ = close(write_fd);
+
+            let mut buf = [0u8; 1];
+            let started = loop {
+                match read(read_fd, &mut buf) {
+                    Ok(n) => break n == 1,
+                    Err(nix::errno::Errno::EINTR) => continue,
+                    Err(_) => break false,
+                }
+            };
+
+            if started {
+                if print_id {
+                    println!("{}", container_id);
+                }
+                std::process::exit(0);
+            }
+            error!("Failed to start container {}", container_id);
+            std::process::exit(-1);
+        }
+        ForkResult::Child => {
+            let _ = close(read_fd);
+            setsid().context("Failed to create monitor session")?;
+            Ok(ReadyNotifier { fd: write_fd })
+        }
+    }
+}
+
+/// Drain the container stdout and stderr into its log file and to the
+/// attached clients.
+///
+/// Each stream is copied by its own thread. The threads finish when the
+/// container closes the stream, which at the latest happens when its init
+/// process exits and the kernel tears down the PID namespace.
+pub fn capture_logs<O, E>(
+    stdout: Option<O>,
+    stderr: Option<E>,
+    log_path: &Path,
+    clients: &Clients,
+) -> Vec<JoinHandle<()>>
+where
+    O: Read + Send + 'static,
+    E: Read + Send + 'static,
+{
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    if let Some(stdout) = stdout {
+        match open_log(log_path) {
+            Ok(log_file) => handles.push(spawn_copy(
+                stdout,
+                log_file,
+                FrameKind::Stdout,
+                clients.clone(),
+            )),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    // Capture stderr - append to same log file
+    if let Some(stderr) = stderr {
+        match open_log(log_path) {
+            Ok(log_file) => handles.push(spawn_copy(
+                stderr,
+                log_file,
+                FrameKind::Stderr,
+                clients.clone(),
+            )),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    handles
+}
+
+/// Proxy the container terminal.
+///
+/// The terminal output is copied into the log file, to the attached
+/// clients and, if `interactive`, to stdout, while stdin is copied to the
+/// terminal. The size of the
+/// user's terminal is applied to the container terminal and kept in sync
+/// on `SIGWINCH`.
+///
+/// Only the handle of the output thread is returned. It finishes once the
+/// last process of the container closes the terminal.
+pub fn proxy_tty(
+    master: &File,
+    log_path: &Path,
+    clients: &Clients,
+    interactive: bool,
+) -> Result<JoinHandle<()>> {
+    // First, so that the copying threads inherit the blocked SIGWINCH
+    if interactive {
+        forward_window_size(master)?;
+    }
+
+    let log_file = open_log(log_path)?;
+    let output = master
+        .try_clone()
+        .context("Failed to clone terminal master")?;
+    let clients = clients.clone();
+    let handle = spawn(move || {
+        let mut stdout = std::io::stdout();
+        let mut buffer = [0; 4096];
+        // Fails with EIO once the slave is closed
+        while let Ok(n) = (&output).read(&mut buffer) {
+            if n == 0 {
+                break;
+            }
+            let _ = (&log_file).write_all(&buffer[..n]);
+            clients.broadcast(FrameKind::Stdout, &buffer[..n]);
+            if interactive {
+                let _ = stdout.write_all(&buffer[..n]);
+                let _ = stdout.flush();
+            }
+        }
+    });
+
+    if interactive {
+        let mut input = master
+            .try_clone()
+            .context("Failed to clone terminal master")?;
+        spawn(move || {
+            let _ = std::io::copy(&mut std::io::stdin(), &mut input);
+        });
+    }
+
+    Ok(handle)
+}
+
+/// Keep the size of the container terminal in sync with stdin's.
+///
+/// `SIGWINCH` is blocked in the calling thread and the threads it spawns
+/// afterwards, and handled by a dedicated thread. Threads spawned before
+/// would silently swallow the signal.
+fn forward_window_size(master: &File) -> Result<()> {
+    let master = master
+        .try_clone()
+        .context("Failed to clone terminal master")?;
+    if let Err(e) = tty::copy_window_size(0, master.as_raw_fd()) {
+        warn!("{}", e);
+    }
+
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGWINCH);
+    signals.thread_block().context("Failed to block SIGWINCH")?;
+    spawn(move || {
+        while signals.wait().is_ok() {
+            if let Err(e) = tty::copy_window_size(0, master.as_raw_fd()) {
+                warn!("{}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Signals a foreground `rocker run` forwards to the container.
+const FORWARDED_SIGNALS: &[Signal] = &[
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+];
+
+/// Forward the signals in [`FORWARDED_SIGNALS`] to the container init
+/// `pid`.
+///
+/// `rocker run` keeps running until the container exits, so it still
+/// records the exit and cleans up. As with [`forward_window_size`], this
+/// must be called before any other thread is spawned.
+///
+/// `SIGWINCH` is blocked as well, so that the forwarding thread doesn't
+/// swallow the resizes [`forward_window_size`] waits for later on.
+pub fn forward_signals(pid: i32) -> Result<()> {
+    let mut signals = SigSet::empty();
+    for signal in FORWARDED_SIGNALS {
+        signals.add(*signal);
+    }
+    let mut blocked = signals;
+    blocked.add(Signal::SIGWINCH);
+    blocked.thread_block().context("Failed to block signals")?;
+    spawn(move || {
+        while let Ok(signal) = signals.wait() {
+            debug!("forwarding {} to the container", signal);
+            if let Err(e) = kill(Pid::from_raw(pid), signal) {
+                warn!("Failed to forward {} to the container: {}", signal, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn open_log(log_path: &Path) -> Result<File> {
+    File::options()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .with_context(|| {
+            format!("Failed to open log file {}", log_path.display())
+        })
+}
+
+fn spawn_copy<R>(
+    mut reader: R,
+    mut log_file: File,
+    kind: FrameKind,
+    clients: Clients,
+) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = log_file.write_all(&buffer[..n]);
+                    clients.broadcast(kind, &buffer[..n]);
+                }
+                Err(_) => break,
+            }
+        }
+    })
+}